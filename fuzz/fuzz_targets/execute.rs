@@ -0,0 +1,102 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rustychip8;
+
+use std::io;
+
+use rustychip8::cheats::Cheats;
+use rustychip8::coverage::Coverage;
+use rustychip8::frontend::Command;
+use rustychip8::mmu::{Mmu, OutOfBoundsPolicy};
+use rustychip8::cpu::Cpu;
+use rustychip8::profiler::Profiler;
+use rustychip8::quirks::Quirks;
+use rustychip8::timing::{Scheduler, Timing};
+
+// Ten seconds of emulated time: enough to get through a ROM's setup and into
+// its main loop, while keeping every run short enough that a hang shows up
+// as a timeout.
+const MAX_FRAMES: usize = 600;
+
+// The cheat search steps taken a frame at a time, over and over: start, narrow
+// down a few ways, then freeze whatever is left if it's a single address.
+const SEARCH_STEPS: [Command; 6] = [
+    Command::NewSearch,
+    Command::SearchChanged,
+    Command::SearchIncreased,
+    Command::SearchDecreased,
+    Command::SearchUnchanged,
+    Command::Freeze
+];
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+
+    // The first byte picks the memory policy, quirks and timing so every
+    // combination gets exercised. The second picks what watches the program
+    // run: the profiler, coverage and cheats all hook into each step or
+    // frame.
+    let policy = match data[0] % 3 {
+        0 => OutOfBoundsPolicy::Wrap,
+        1 => OutOfBoundsPolicy::Clamp,
+        _ => OutOfBoundsPolicy::Error
+    };
+
+    let mut mmu = Mmu::with_policy(policy);
+
+    let rom = &data[2..];
+
+    if mmu.load_rom(rom).is_err() {
+        return;
+    }
+
+    let mut quirks = Quirks::new();
+    quirks.wrap_sprites = data[0] & 0x80 != 0;
+    quirks.display_wait = data[0] & 0x40 != 0;
+    quirks.shift_vy     = data[0] & 0x10 != 0;
+    quirks.load_store_i = data[0] & 0x08 != 0;
+
+    let timing = if data[0] & 0x20 != 0 {
+        Timing::CosmacVip
+    } else {
+        Timing::Flat(840)
+    };
+
+    let mut cpu = Cpu::new(mmu);
+    cpu.set_quirks(quirks);
+
+    if data[1] & 0x01 != 0 {
+        cpu.set_profiler(Some(Profiler::new()));
+    }
+
+    if data[1] & 0x02 != 0 {
+        cpu.set_coverage(Some(Coverage::new()));
+    }
+
+    let mut cheats = if data[1] & 0x04 != 0 { Some(Cheats::new()) } else { None };
+
+    let mut scheduler = Scheduler::new(timing);
+
+    for frame in 0..MAX_FRAMES {
+        if let Some(ref mut cheats) = cheats {
+            cheats.command(SEARCH_STEPS[frame % SEARCH_STEPS.len()], &cpu);
+            cheats.apply(&mut cpu);
+        }
+
+        if scheduler.run_frame(&mut cpu).is_err() {
+            break;
+        }
+    }
+
+    // The reports are written from what was recorded, so they get fuzzed too.
+    if let Some(profiler) = cpu.profiler() {
+        let _ = profiler.write_report(cpu.mmu(), &mut io::sink());
+    }
+
+    if let Some(coverage) = cpu.coverage() {
+        let _ = coverage.write_listing(rom, &mut io::sink());
+        let _ = coverage.write_heatmap(rom.len(), &mut io::sink());
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate rustychip8;
+
+use rustychip8::mmu::Mmu;
+
+fuzz_target!(|data: &[u8]| {
+    let mut mmu = Mmu::new();
+
+    let _ = mmu.load_rom(data);
+});
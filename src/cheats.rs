@@ -0,0 +1,471 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::vec::Vec;
+
+use config;
+use cpu::Cpu;
+use frontend::Command;
+use mmu::{Mmu, MEMORY_SIZE};
+
+// How many of the addresses a search has left are named after each step.
+const LISTED_CANDIDATES: usize = 4;
+
+// What a cheat holds still: a byte of memory or a V register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Memory(u16),
+    Register(usize)
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Target::Memory(address)    => write!(f, "{:#05X}", address),
+            Target::Register(register) => write!(f, "V{:X}", register)
+        }
+    }
+}
+
+// "V3" for a register, or a hex address like "0x3A0".
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Target, String> {
+        if s.starts_with('V') || s.starts_with('v') {
+            return match u8::from_str_radix(&s[1..], 16) {
+                Ok(register) if s.len() == 2 => Ok(Target::Register(register as usize)),
+                _ => Err(format!("Unknown register: {}", s))
+            };
+        }
+
+        match parse_hex(s) {
+            Some(address) if (address as usize) < MEMORY_SIZE => Ok(Target::Memory(address)),
+            _ => Err(format!("Expected an address up to 0xFFF or a register V0 to VF: {}", s))
+        }
+    }
+}
+
+// A target frozen at a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cheat {
+    pub target: Target,
+    pub value: u8
+}
+
+impl Cheat {
+    pub fn apply(&self, cpu: &mut Cpu) {
+        match self.target {
+            Target::Memory(address) => {
+                let _ = cpu.mmu_mut().write_byte(address as usize, self.value);
+            },
+            Target::Register(register) => cpu.set_register(register, self.value)
+        }
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {:#04X}", self.target, self.value)
+    }
+}
+
+// "target = value", with the value in hex, e.g. "0x3A0 = 0x05" or "V3 = FF".
+impl FromStr for Cheat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Cheat, String> {
+        let mut parts = s.splitn(2, '=');
+
+        let target = try!(parts.next().unwrap().trim().parse());
+
+        let value = match parts.next().map(|value| value.trim()) {
+            Some(value) => value,
+            None        => return Err(format!("Expected target = value: {}", s))
+        };
+
+        match parse_hex(value) {
+            Some(byte) if byte <= 0xFF => Ok(Cheat { target: target, value: byte as u8 }),
+            _ => Err(format!("Expected a byte in hex: {}", value))
+        }
+    }
+}
+
+// How a byte has to have changed since the last look to stay in a search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased
+}
+
+impl Filter {
+    fn keeps(&self, before: u8, after: u8) -> bool {
+        match *self {
+            Filter::Changed   => after != before,
+            Filter::Unchanged => after == before,
+            Filter::Increased => after > before,
+            Filter::Decreased => after < before
+        }
+    }
+
+    fn past_tense(&self) -> &'static str {
+        match *self {
+            Filter::Changed   => "changed",
+            Filter::Unchanged => "stayed the same",
+            Filter::Increased => "went up",
+            Filter::Decreased => "went down"
+        }
+    }
+}
+
+// Narrows memory down to the address of something like a lives counter, by
+// looking at how bytes change from one step to the next: die, and look for
+// what went down.
+pub struct Search {
+    candidates: Vec<u16>,
+    snapshot: Vec<u8>
+}
+
+impl Search {
+    // Starts with every address.
+    pub fn new(mmu: &Mmu) -> Search {
+        Search {
+            candidates: (0..MEMORY_SIZE as u16).collect(),
+            snapshot: snapshot(mmu)
+        }
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    // Keeps the addresses whose bytes changed as `filter` asks since the
+    // search started or was last narrowed.
+    pub fn narrow(&mut self, mmu: &Mmu, filter: Filter) {
+        let now = snapshot(mmu);
+
+        {
+            let before = &self.snapshot;
+
+            self.candidates.retain(|&address| filter.keeps(before[address as usize], now[address as usize]));
+        }
+
+        self.snapshot = now;
+    }
+}
+
+fn snapshot(mmu: &Mmu) -> Vec<u8> {
+    (0..MEMORY_SIZE).map(|address| mmu.read_byte(address).unwrap_or(0)).collect()
+}
+
+// The cheats in use, read from a file and saved back to it as they're
+// changed, and a search for more. The file has a cheat a line, with blank
+// lines and # comments allowed.
+pub struct Cheats {
+    path: Option<String>,
+    cheats: Vec<Cheat>,
+    search: Option<Search>
+}
+
+impl Cheats {
+    // No cheats, and nowhere to save them.
+    pub fn new() -> Cheats {
+        Cheats {
+            path: None,
+            cheats: Vec::new(),
+            search: None
+        }
+    }
+
+    // Reads the cheats at `path`, which needn't exist yet.
+    pub fn load(path: &str) -> Result<Cheats, String> {
+        let mut text = String::new();
+
+        match File::open(path).and_then(|mut file| file.read_to_string(&mut text)) {
+            Ok(_) => { },
+            Err(ref e) if e.kind() == ErrorKind::NotFound => { },
+            Err(e) => return Err(format!("Can't read {}: {}", path, e))
+        }
+
+        let cheats = try!(parse(&text).map_err(|e| format!("Error in {}: {}", path, e)));
+
+        Ok(Cheats {
+            path: Some(path.to_string()),
+            cheats: cheats,
+            search: None
+        })
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_ref().map(|path| path.as_str())
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    // Called before each frame, to put the frozen values back.
+    pub fn apply(&self, cpu: &mut Cpu) {
+        for cheat in self.cheats.iter() {
+            cheat.apply(cpu);
+        }
+    }
+
+    // Freezes the cheat's target, or thaws it if it was already frozen.
+    // Returns whether it's frozen now.
+    pub fn toggle(&mut self, cheat: Cheat) -> bool {
+        if self.cheats.iter().any(|c| c.target == cheat.target) {
+            self.cheats.retain(|c| c.target != cheat.target);
+            false
+        } else {
+            self.cheats.push(cheat);
+            true
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None           => return Ok(())
+        };
+
+        if let Some(directory) = Path::new(path).parent() {
+            try!(fs::create_dir_all(directory));
+        }
+
+        let mut file = try!(File::create(path));
+
+        try!(writeln!(file, "# RustyChip8 cheats: an address or register = a value, in hex"));
+
+        for cheat in self.cheats.iter() {
+            try!(writeln!(file, "{}", cheat));
+        }
+
+        Ok(())
+    }
+
+    // Acts on the search and freeze commands, returning a message about how
+    // it went.
+    pub fn command(&mut self, command: Command, cpu: &Cpu) -> Option<String> {
+        match command {
+            Command::NewSearch => {
+                self.search = Some(Search::new(cpu.mmu()));
+                Some(format!("Searching all {} addresses", MEMORY_SIZE))
+            },
+            Command::SearchChanged   => Some(self.narrow(cpu, Filter::Changed)),
+            Command::SearchUnchanged => Some(self.narrow(cpu, Filter::Unchanged)),
+            Command::SearchIncreased => Some(self.narrow(cpu, Filter::Increased)),
+            Command::SearchDecreased => Some(self.narrow(cpu, Filter::Decreased)),
+            Command::Freeze          => Some(self.freeze(cpu)),
+            _ => None
+        }
+    }
+
+    fn narrow(&mut self, cpu: &Cpu, filter: Filter) -> String {
+        let search = match self.search {
+            Some(ref mut search) => search,
+            None                 => return String::from("Start a search first")
+        };
+
+        search.narrow(cpu.mmu(), filter);
+
+        let candidates = search.candidates();
+
+        let listed: Vec<String> = candidates.iter().take(LISTED_CANDIDATES)
+            .map(|address| format!("{:#05X}", address))
+            .collect();
+
+        match candidates.len() {
+            0 => format!("No addresses {}", filter.past_tense()),
+            1 => format!("1 address {}: {}", filter.past_tense(), listed[0]),
+            n if n <= LISTED_CANDIDATES => {
+                format!("{} addresses {}: {}", n, filter.past_tense(), listed.join(" "))
+            },
+            n => format!("{} addresses {}: {} ...", n, filter.past_tense(), listed.join(" "))
+        }
+    }
+
+    // Freezes the address a search came down to at the value it has now, or
+    // thaws it.
+    fn freeze(&mut self, cpu: &Cpu) -> String {
+        let address = match self.search.as_ref().map(|search| search.candidates()) {
+            Some(candidates) if candidates.len() == 1 => candidates[0],
+            Some(candidates) if candidates.is_empty() => return String::from("The search has no addresses left"),
+            Some(candidates) => return format!("Narrow the search to one address first ({} left)", candidates.len()),
+            None => return String::from("Search for an address to freeze first")
+        };
+
+        let cheat = Cheat {
+            target: Target::Memory(address),
+            value: cpu.mmu().read_byte(address as usize).unwrap_or(0)
+        };
+
+        let message = if self.toggle(cheat) {
+            format!("Froze {}", cheat)
+        } else {
+            format!("Thawed {}", cheat.target)
+        };
+
+        match self.save() {
+            Ok(())  => message,
+            Err(e)  => format!("{}, but couldn't save it: {}", message, e)
+        }
+    }
+}
+
+// Where a ROM's cheats are kept unless --cheats says otherwise: named after
+// its SHA-1, under the config directory.
+pub fn default_path(sha1: &str) -> Option<String> {
+    config::directory().map(|directory| format!("{}/cheats/{}.txt", directory, sha1))
+}
+
+fn parse(text: &str) -> Result<Vec<Cheat>, String> {
+    let mut cheats = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.splitn(2, '#').next().unwrap().trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let cheat = try!(line.parse().map_err(|e| format!("line {}: {}", number + 1, e)));
+
+        cheats.push(cheat);
+    }
+
+    Ok(cheats)
+}
+
+fn parse_hex(s: &str) -> Option<u16> {
+    let digits = if s.starts_with("0x") || s.starts_with("0X") { &s[2..] } else { s };
+
+    u16::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use mmu::Mmu;
+
+    use super::*;
+
+    #[test]
+    fn targets_round_trip() {
+        for target in [Target::Memory(0), Target::Memory(0x3A0), Target::Memory(0xFFF),
+                       Target::Register(0), Target::Register(0xF)].iter() {
+            assert_eq!(target.to_string().parse::<Target>(), Ok(*target));
+        }
+
+        assert_eq!(Target::Memory(0x3A0).to_string(), "0x3A0");
+        assert_eq!(Target::Register(0xA).to_string(), "VA");
+    }
+
+    #[test]
+    fn parses_targets_in_either_case() {
+        assert_eq!("v3".parse::<Target>(), Ok(Target::Register(3)));
+        assert_eq!("Vf".parse::<Target>(), Ok(Target::Register(0xF)));
+        assert_eq!("3a0".parse::<Target>(), Ok(Target::Memory(0x3A0)));
+        assert_eq!("0X3A0".parse::<Target>(), Ok(Target::Memory(0x3A0)));
+
+        assert!("V10".parse::<Target>().is_err());
+        assert!("VG".parse::<Target>().is_err());
+        assert!("0x1000".parse::<Target>().is_err());
+        assert!("".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn cheats_round_trip() {
+        let cheats = [
+            Cheat { target: Target::Memory(0x3A0), value: 0x05 },
+            Cheat { target: Target::Register(3), value: 0xFF },
+            Cheat { target: Target::Memory(0), value: 0 }
+        ];
+
+        for cheat in cheats.iter() {
+            assert_eq!(cheat.to_string().parse::<Cheat>(), Ok(*cheat));
+        }
+
+        assert_eq!(cheats[0].to_string(), "0x3A0 = 0x05");
+        assert_eq!("V3=FF".parse::<Cheat>(), Ok(cheats[1]));
+    }
+
+    #[test]
+    fn rejects_malformed_cheats() {
+        assert!("0x3A0".parse::<Cheat>().is_err());
+        assert!("0x3A0 = ".parse::<Cheat>().is_err());
+        assert!("0x3A0 = 0x100".parse::<Cheat>().is_err());
+        assert!("V3 = 0x1 = 0x2".parse::<Cheat>().is_err());
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let text = "# RustyChip8 cheats\n\
+                    \n\
+                    0x3A0 = 0x05   # lives\n\
+                    \t  \n\
+                    V3 = FF\n";
+
+        assert_eq!(parse(text), Ok(vec![
+            Cheat { target: Target::Memory(0x3A0), value: 0x05 },
+            Cheat { target: Target::Register(3), value: 0xFF }
+        ]));
+    }
+
+    #[test]
+    fn reports_the_line_of_a_bad_cheat() {
+        let error = parse("# cheats\n0x3A0 = 0x05\nlives = 3\n").err().unwrap();
+
+        assert!(error.starts_with("line 3: "), "{}", error);
+    }
+
+    #[test]
+    fn narrows_a_search_by_how_bytes_changed() {
+        // Three bytes of 5, then one goes down and one goes up.
+        let narrowed = |filter: Filter| {
+            let mut mmu = Mmu::new();
+
+            for address in 0x300..0x303 {
+                mmu.write_byte(address, 5).unwrap();
+            }
+
+            let mut search = Search::new(&mmu);
+
+            mmu.write_byte(0x300, 4).unwrap();
+            mmu.write_byte(0x301, 6).unwrap();
+
+            search.narrow(&mmu, filter);
+
+            let candidates = search.candidates();
+
+            (candidates.len(), candidates.contains(&0x300), candidates.contains(&0x301),
+             candidates.contains(&0x302))
+        };
+
+        assert_eq!(narrowed(Filter::Changed), (2, true, true, false));
+        assert_eq!(narrowed(Filter::Unchanged), (MEMORY_SIZE - 2, false, false, true));
+        assert_eq!(narrowed(Filter::Increased), (1, false, true, false));
+        assert_eq!(narrowed(Filter::Decreased), (1, true, false, false));
+    }
+
+    #[test]
+    fn compares_each_narrowing_with_the_last() {
+        let mut mmu = Mmu::new();
+        let mut search = Search::new(&mmu);
+
+        mmu.write_byte(0x300, 3).unwrap();
+        mmu.write_byte(0x301, 3).unwrap();
+        search.narrow(&mmu, Filter::Increased);
+
+        assert_eq!(search.candidates(), &[0x300, 0x301]);
+
+        // Since the last look, 0x300 went down and 0x301 stayed put.
+        mmu.write_byte(0x300, 2).unwrap();
+        search.narrow(&mmu, Filter::Decreased);
+
+        assert_eq!(search.candidates(), &[0x300]);
+    }
+}
@@ -0,0 +1,547 @@
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::vec::Vec;
+
+use options;
+
+// Settings read from a TOML file. Top level keys apply to every ROM, and
+// tables named after a ROM's file name or SHA-1 apply to just that ROM:
+//
+//   scale = 8
+//   palette = "amber"
+//   timing = 1000
+//   keymap = "x123qweasdzc4rfv"
+//
+//   [rom."BLINKY"]
+//   wrap_sprites = true
+//
+//   # PONG
+//   [rom.b232ef880bd6060fb45fa6effed7edf0ae95670e]
+//   timing = "vip"
+//
+// Keys are command line options without the leading dashes, and with
+// underscores or dashes between words. Each becomes that option, so the file
+// accepts exactly what the command line does; `true` turns a flag on and
+// `false` gives its --no- form, so `shift_vy = false` in a ROM's table undoes
+// `shift_vy = true` at the top. Options that take a value can't be given
+// true or false, nor flags anything else. SHA-1s may be in either case.
+//
+// Only the parts of TOML settings need are understood: tables, and keys with
+// string, integer, float or boolean values.
+pub struct Config {
+    path: String,
+    tables: Vec<Table>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool)
+}
+
+struct Table {
+    name: Vec<String>,
+    entries: Vec<(String, Value)>
+}
+
+impl Table {
+    fn is(&self, name: &[&str]) -> bool {
+        self.name.len() == name.len() && self.name.iter().zip(name).all(|(a, b)| a == b)
+    }
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, String> {
+        let mut text = String::new();
+
+        try!(File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|e| format!("Couldn't read {}: {}", path, e)));
+
+        Config::parse(path, &text)
+    }
+
+    // `path` is only used in error messages.
+    pub fn parse(path: &str, text: &str) -> Result<Config, String> {
+        let mut tables = vec![Table { name: Vec::new(), entries: Vec::new() }];
+
+        for (number, line) in text.lines().enumerate() {
+            let mut parser = Parser::new(line);
+
+            let result = parser.parse_line().and_then(|line| {
+                match line {
+                    Line::Table(name) => {
+                        let known = match name.len() {
+                            2 => name[0] == "rom",
+                            _ => false
+                        };
+
+                        if !known {
+                            return Err(format!("unknown table [{}]; expected [rom.<file name or SHA-1>]",
+                                               name.join(".")));
+                        }
+
+                        // SHA-1s are matched as the lowercase hex they're
+                        // printed in.
+                        let mut name = name;
+
+                        if is_sha1(&name[1]) {
+                            name[1] = name[1].to_lowercase();
+                        }
+
+                        tables.push(Table { name: name, entries: Vec::new() });
+                    },
+                    Line::Entry(key, value) => {
+                        let table = tables.last_mut().unwrap();
+
+                        if table.entries.iter().any(|&(ref existing, _)| *existing == key) {
+                            return Err(format!("{} is set twice", key));
+                        }
+
+                        // A flag given a value, or a value-taking option given
+                        // none, would take the next argument as its value.
+                        let flag = options::is_flag(&option_for(&key));
+
+                        match value {
+                            Value::Boolean(_) if !flag => {
+                                return Err(format!("{} takes a value, not true or false", key));
+                            },
+                            Value::Boolean(_) => { },
+                            _ if flag => {
+                                return Err(format!("{} is on or off, so should be true or false", key));
+                            },
+                            _ => { }
+                        }
+
+                        table.entries.push((key, value));
+                    },
+                    Line::Blank => { }
+                }
+
+                Ok(())
+            });
+
+            if let Err(e) = result {
+                return Err(format!("{}:{}: {}", path, number + 1, e));
+            }
+        }
+
+        Ok(Config {
+            path: path.to_string(),
+            tables: tables
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // The settings for a ROM as command line arguments: the global ones, then
+    // any for its file name, then any for its hash, so the most specific
+    // comes last and wins.
+    pub fn args_for(&self, file_name: &str, sha1: &str) -> Vec<String> {
+        let mut args = Vec::new();
+
+        let names: [&[&str]; 3] = [&[], &["rom", file_name], &["rom", sha1]];
+
+        for name in names.iter() {
+            for table in self.tables.iter() {
+                if !table.is(name) {
+                    continue;
+                }
+
+                for &(ref key, ref value) in table.entries.iter() {
+                    let option = option_for(key);
+
+                    match *value {
+                        Value::Boolean(true)  => args.push(option),
+                        Value::Boolean(false) => args.push(negated(&option)),
+                        Value::String(ref s)  => { args.push(option); args.push(s.clone()); },
+                        Value::Integer(n)     => { args.push(option); args.push(n.to_string()); },
+                        Value::Float(n)       => { args.push(option); args.push(n.to_string()); }
+                    }
+                }
+            }
+        }
+
+        args
+    }
+}
+
+// The command line option for a key: --wrap-sprites for wrap_sprites.
+fn option_for(key: &str) -> String {
+    format!("--{}", key.replace("_", "-"))
+}
+
+fn is_sha1(name: &str) -> bool {
+    name.len() == 40 && name.chars().all(|c| c.is_digit(16))
+}
+
+// The opposite of a flag: --no-overlay for --overlay, and back.
+fn negated(option: &str) -> String {
+    if option.starts_with("--no-") {
+        format!("--{}", &option[5..])
+    } else {
+        format!("--no-{}", &option[2..])
+    }
+}
+
+// Where the config file lives when --config isn't given, if there is one:
+// config.toml in `directory()`.
+pub fn default_path() -> Option<String> {
+    let path = match directory() {
+        Some(directory) => format!("{}/config.toml", directory),
+        None            => return None
+    };
+
+    if Path::new(&path).is_file() { Some(path) } else { None }
+}
+
+// $XDG_CONFIG_HOME/rustychip8, or under ~/.config without it. It may not
+// exist yet.
+pub fn directory() -> Option<String> {
+    let base = match env::var("XDG_CONFIG_HOME") {
+        Ok(ref dir) if !dir.is_empty() => dir.clone(),
+        _ => {
+            match env::var("HOME") {
+                Ok(home) => format!("{}/.config", home),
+                Err(_)   => return None
+            }
+        }
+    };
+
+    Some(format!("{}/rustychip8", base))
+}
+
+enum Line {
+    Blank,
+    Table(Vec<String>),
+    Entry(String, Value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize
+}
+
+impl Parser {
+    fn new(line: &str) -> Parser {
+        Parser {
+            chars: line.chars().collect(),
+            position: 0
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.position += 1;
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ') | Some('\t') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c)                  => Err(format!("expected '{}', found '{}'", expected, c)),
+            None                     => Err(format!("expected '{}'", expected))
+        }
+    }
+
+    // Only whitespace or a comment may follow.
+    fn expect_end(&mut self) -> Result<(), String> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            None | Some('#') => Ok(()),
+            Some(c)          => Err(format!("unexpected '{}'", c))
+        }
+    }
+
+    fn parse_line(&mut self) -> Result<Line, String> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            None | Some('#') => Ok(Line::Blank),
+            Some('[') => {
+                self.position += 1;
+
+                let name = try!(self.parse_key());
+
+                try!(self.expect(']'));
+                try!(self.expect_end());
+
+                Ok(Line::Table(name))
+            },
+            Some(_) => {
+                let key = try!(self.parse_key());
+
+                if key.len() != 1 {
+                    return Err(format!("dotted keys aren't supported: {}", key.join(".")));
+                }
+
+                try!(self.expect('='));
+
+                let value = try!(self.parse_value());
+
+                try!(self.expect_end());
+
+                Ok(Line::Entry(key[0].clone(), value))
+            }
+        }
+    }
+
+    // A dotted key, each part bare or quoted: rom."Space Invaders".
+    fn parse_key(&mut self) -> Result<Vec<String>, String> {
+        let mut parts = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+
+            let part = match self.peek() {
+                Some('"') | Some('\'') => try!(self.parse_string()),
+                _ => {
+                    let start = self.position;
+
+                    while let Some(c) = self.peek() {
+                        if c.is_alphanumeric() || c == '_' || c == '-' {
+                            self.position += 1;
+                        } else {
+                            break;
+                        }
+                    }
+
+                    if start == self.position {
+                        return Err(String::from("expected a key"));
+                    }
+
+                    self.chars[start..self.position].iter().cloned().collect()
+                }
+            };
+
+            parts.push(part);
+
+            self.skip_whitespace();
+
+            if self.peek() != Some('.') {
+                return Ok(parts);
+            }
+
+            self.position += 1;
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('"') | Some('\'') => return Ok(Value::String(try!(self.parse_string()))),
+            None                   => return Err(String::from("expected a value")),
+            _                      => { }
+        }
+
+        let start = self.position;
+
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || c == '_' || c == '-' || c == '+' || c == '.' {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+
+        let word: String = self.chars[start..self.position].iter().cloned().collect();
+        let number = word.replace("_", "");
+
+        if word == "true" {
+            Ok(Value::Boolean(true))
+        } else if word == "false" {
+            Ok(Value::Boolean(false))
+        } else if number.starts_with("0x") {
+            i64::from_str_radix(&number[2..], 16).map(Value::Integer)
+                .map_err(|_| format!("invalid number: {}", word))
+        } else if let Ok(n) = number.parse::<i64>() {
+            Ok(Value::Integer(n))
+        } else if let Ok(n) = number.parse::<f64>() {
+            Ok(Value::Float(n))
+        } else {
+            Err(format!("invalid value: {}", word))
+        }
+    }
+
+    // A "basic" string with backslash escapes, or a 'literal' one without.
+    fn parse_string(&mut self) -> Result<String, String> {
+        let quote = self.next().unwrap();
+        let mut s = String::new();
+
+        loop {
+            match self.next() {
+                None => return Err(String::from("unterminated string")),
+                Some(c) if c == quote => return Ok(s),
+                Some('\\') if quote == '"' => {
+                    match self.next() {
+                        Some('n')  => s.push('\n'),
+                        Some('t')  => s.push('\t'),
+                        Some('"')  => s.push('"'),
+                        Some('\\') => s.push('\\'),
+                        Some(c)    => return Err(format!("unknown escape: \\{}", c)),
+                        None       => return Err(String::from("unterminated string"))
+                    }
+                },
+                Some(c) => s.push(c)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use options::Options;
+
+    use super::*;
+
+    fn args(config: &Config, file_name: &str, sha1: &str) -> Vec<String> {
+        config.args_for(file_name, sha1)
+    }
+
+    #[test]
+    fn parses_each_kind_of_value() {
+        let config = Config::parse("test", "scale = 8\n\
+                                            palette = \"amber\"\n\
+                                            phosphor = 0x10\n\
+                                            crt = 'scanlines=0.5'\n\
+                                            wrap_sprites = true   # comment\n\
+                                            \n\
+                                            # comment\n").unwrap();
+
+        assert_eq!(args(&config, "PONG", "abc"),
+                   ["--scale", "8", "--palette", "amber", "--phosphor", "16",
+                    "--crt", "scanlines=0.5", "--wrap-sprites"]);
+    }
+
+    #[test]
+    fn applies_tables_only_to_their_rom() {
+        let config = Config::parse("test", "scale = 8\n\
+                                            [rom.PONG]\n\
+                                            scale = 4\n\
+                                            [rom.\"Space Invaders\"]\n\
+                                            scale = 6\n\
+                                            [rom.'BRIX']\n\
+                                            scale = 2\n").unwrap();
+
+        assert_eq!(args(&config, "PONG", "abc"), ["--scale", "8", "--scale", "4"]);
+        assert_eq!(args(&config, "Space Invaders", "abc"), ["--scale", "8", "--scale", "6"]);
+        assert_eq!(args(&config, "BRIX", "abc"), ["--scale", "8", "--scale", "2"]);
+        assert_eq!(args(&config, "TETRIS", "abc"), ["--scale", "8"]);
+    }
+
+    #[test]
+    fn puts_the_most_specific_table_last() {
+        // Written least specific last, to show the order comes from the kind
+        // of table and not from the file.
+        let config = Config::parse("test", "[rom.abc]\n\
+                                            palette = \"lcd\"\n\
+                                            [rom.PONG]\n\
+                                            palette = \"green\"\n").unwrap();
+
+        assert_eq!(args(&config, "PONG", "abc"), ["--palette", "green", "--palette", "lcd"]);
+    }
+
+    #[test]
+    fn turns_flags_off_with_false() {
+        let config = Config::parse("test", "shift_vy = true\n\
+                                            no_overlay = true\n\
+                                            [rom.PONG]\n\
+                                            shift-vy = false\n\
+                                            no_overlay = false\n").unwrap();
+
+        let pong = args(&config, "PONG", "abc");
+
+        assert_eq!(pong, ["--shift-vy", "--no-overlay", "--no-shift-vy", "--overlay"]);
+
+        let mut command_line = pong.clone();
+        command_line.push(String::from("PONG"));
+
+        let options = Options::parse(command_line.into_iter()).unwrap();
+
+        assert!(!options.quirks.shift_vy);
+        assert!(options.show_overlay);
+
+        let mut command_line = pong.clone();
+        command_line.extend(vec![String::from("--shift-vy"), String::from("PONG")]);
+
+        assert!(Options::parse(command_line.into_iter()).unwrap().quirks.shift_vy);
+    }
+
+    #[test]
+    fn matches_sha1_tables_whatever_their_case() {
+        let config = Config::parse("test", "[rom.B232EF880BD6060FB45FA6EFFED7EDF0AE95670E]\n\
+                                            timing = \"vip\"\n\
+                                            [rom.PONG]\n\
+                                            scale = 4\n").unwrap();
+
+        assert_eq!(args(&config, "x", "b232ef880bd6060fb45fa6effed7edf0ae95670e"), ["--timing", "vip"]);
+        assert_eq!(args(&config, "pong", "abc"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rejects_booleans_for_options_that_take_values() {
+        assert_eq!(Config::parse("config.toml", "scale = true").err().unwrap(),
+                   "config.toml:1: scale takes a value, not true or false");
+        assert_eq!(Config::parse("config.toml", "[rom.PONG]\npalette = false").err().unwrap(),
+                   "config.toml:2: palette takes a value, not true or false");
+        assert_eq!(Config::parse("config.toml", "debug = \"yes\"").err().unwrap(),
+                   "config.toml:1: debug is on or off, so should be true or false");
+        assert_eq!(Config::parse("config.toml", "no_overlay_in_captures = 1").err().unwrap(),
+                   "config.toml:1: no_overlay_in_captures is on or off, so should be true or false");
+    }
+
+    #[test]
+    fn unescapes_basic_strings_only() {
+        let config = Config::parse("test", "keymap = \"a\\\"b\\\\c\\td\"\n\
+                                            hotkeys = 'a\\tb'\n").unwrap();
+
+        assert_eq!(args(&config, "PONG", "abc"), ["--keymap", "a\"b\\c\td", "--hotkeys", "a\\tb"]);
+
+        assert_eq!(Config::parse("test", "keymap = \"a\\qb\"").err().unwrap(),
+                   "test:1: unknown escape: \\q");
+        assert_eq!(Config::parse("test", "keymap = \"ab").err().unwrap(),
+                   "test:1: unterminated string");
+    }
+
+    #[test]
+    fn rejects_keys_set_twice_in_a_table() {
+        assert_eq!(Config::parse("test", "scale = 8\nscale = 4").err().unwrap(),
+                   "test:2: scale is set twice");
+
+        assert!(Config::parse("test", "scale = 8\n[rom.PONG]\nscale = 4").is_ok());
+    }
+
+    #[test]
+    fn rejects_what_it_doesnt_understand() {
+        assert_eq!(Config::parse("test", "[display]").err().unwrap(),
+                   "test:1: unknown table [display]; expected [rom.<file name or SHA-1>]");
+        assert_eq!(Config::parse("test", "a.b = 1").err().unwrap(),
+                   "test:1: dotted keys aren't supported: a.b");
+        assert_eq!(Config::parse("test", "scale = 8 9").err().unwrap(),
+                   "test:1: unexpected '9'");
+        assert_eq!(Config::parse("test", "scale =").err().unwrap(),
+                   "test:1: expected a value");
+    }
+}
@@ -0,0 +1,367 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::vec::Vec;
+
+use detect;
+use disassembler;
+use mmu::{MEMORY_SIZE, ROM_START};
+use png;
+
+// The heatmap has a square cell per byte, 64 to a row, so 4 KB of memory
+// makes a square.
+const HEATMAP_COLUMNS: usize = 64;
+const CELL_SIZE: usize = 8;
+
+// ROM bytes nothing touched are dark gray, to show where the ROM is.
+const UNTOUCHED_SHADE: u8 = 0x30;
+
+// At least this many bytes nothing touched in a row go on one line of the
+// listing.
+const UNTOUCHED_RUN: usize = 4;
+
+// Records which bytes of memory the program runs as code, reads as data
+// through I (Dxyn and Fx65) and writes through I (Fx33 and Fx55), and how
+// often. Fed by `Cpu::step`. Written out when the program ends, as a
+// heatmap of all of memory or as a listing of the ROM that tells code from
+// data by what was seen running as well as by tracing it.
+pub struct Coverage {
+    executed: Vec<u64>,
+    read: Vec<u64>,
+    written: Vec<u64>
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            executed: vec![0; MEMORY_SIZE],
+            read: vec![0; MEMORY_SIZE],
+            written: vec![0; MEMORY_SIZE]
+        }
+    }
+
+    // Called after each instruction is run, with where it was and what I
+    // was before it ran.
+    pub fn record(&mut self, pc: u16, instruction: u16, i: u16) {
+        self.executed[pc as usize] += 1;
+
+        let x = (instruction as usize >> 8) & 0xF;
+
+        let (counts, length) = match instruction & 0xF0FF {
+            0xF033 => (&mut self.written, 3),
+            0xF055 => (&mut self.written, x + 1),
+            0xF065 => (&mut self.read, x + 1),
+            _ if instruction & 0xF000 == 0xD000 => (&mut self.read, instruction as usize & 0xF),
+            _ => return
+        };
+
+        for offset in 0..length {
+            counts[(i as usize + offset) % MEMORY_SIZE] += 1;
+        }
+    }
+
+    // Writes a heatmap if `path` ends in .png, or a listing otherwise.
+    // `rom` is the program as loaded.
+    pub fn save(&self, path: &str, rom: &[u8]) -> io::Result<()> {
+        let mut out = BufWriter::new(try!(File::create(path)));
+
+        let heatmap = Path::new(path).extension()
+            .map_or(false, |extension| extension.to_string_lossy().eq_ignore_ascii_case("png"));
+
+        if heatmap {
+            try!(self.write_heatmap(rom.len(), &mut out));
+        } else {
+            try!(self.write_listing(rom, &mut out));
+        }
+
+        out.flush()
+    }
+
+    // All of memory as a PNG, a row of 64 bytes to a row of cells. Code that
+    // ran is green, data read is blue and data written is red, brighter the
+    // more often, so a byte both read and written comes out magenta.
+    pub fn write_heatmap<W: Write>(&self, rom_size: usize, out: &mut W) -> io::Result<()> {
+        let rows  = MEMORY_SIZE / HEATMAP_COLUMNS;
+        let width = HEATMAP_COLUMNS * CELL_SIZE;
+
+        let executed = self.instruction_bytes();
+
+        let most_executed = executed.iter().cloned().max().unwrap_or(0);
+        let most_read     = self.read.iter().cloned().max().unwrap_or(0);
+        let most_written  = self.written.iter().cloned().max().unwrap_or(0);
+
+        let mut rgb = Vec::with_capacity(width * rows * CELL_SIZE * 3);
+
+        for row in 0..rows * CELL_SIZE {
+            for column in 0..width {
+                let address = (row / CELL_SIZE) * HEATMAP_COLUMNS + column / CELL_SIZE;
+
+                let mut color = [
+                    shade(self.written[address], most_written),
+                    shade(executed[address], most_executed),
+                    shade(self.read[address], most_read)
+                ];
+
+                let in_rom = address >= ROM_START && address < ROM_START + rom_size;
+
+                if in_rom && color == [0, 0, 0] {
+                    color = [UNTOUCHED_SHADE; 3];
+                }
+
+                rgb.extend_from_slice(&color);
+            }
+        }
+
+        png::write(out, width, rows * CELL_SIZE, &rgb)
+    }
+
+    // The ROM, and any memory past it the program used, a line an
+    // instruction or a byte of data. Code is what ran plus what can be traced
+    // from there; what the program read or wrote through I is data, even if
+    // a trace went through it. Each line has flags for how the bytes were
+    // used, x for run, r for read and w for written, and how many times.
+    pub fn write_listing<W: Write>(&self, rom: &[u8], out: &mut W) -> io::Result<()> {
+        let entries: Vec<usize> = (ROM_START..ROM_START + rom.len())
+            .filter(|&address| self.executed[address] > 0)
+            .collect();
+
+        let traced = detect::trace_from(rom, &entries);
+
+        let is_code = |address: usize| {
+            let offset = address.wrapping_sub(ROM_START);
+
+            self.executed[address] > 0 ||
+                (offset < traced.len() && traced[offset] && !self.is_data(address) && !self.is_data(address + 1))
+        };
+
+        let last_used = (ROM_START..MEMORY_SIZE).rev().find(|&address| self.is_used(address));
+        let end = last_used.map_or(ROM_START, |address| address + 1).max(ROM_START + rom.len());
+
+        try!(self.write_summary(rom, out));
+
+        let mut address = ROM_START;
+
+        while address < end {
+            let offset = address - ROM_START;
+
+            if is_code(address) && offset + 1 < rom.len() {
+                let instruction = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+
+                try!(writeln!(out, "{:03X}  {:04X}  {:<22} {}  {:>10}", address, instruction,
+                              disassembler::disassemble(instruction), self.flags(address, 2),
+                              self.executed[address]));
+
+                address += 2;
+                continue;
+            }
+
+            let untouched = (address..end)
+                .take_while(|&a| !self.is_used(a) && !is_code(a))
+                .count();
+
+            if untouched >= UNTOUCHED_RUN {
+                try!(writeln!(out, "{:03X}  {} bytes never touched", address, untouched));
+
+                address += untouched;
+                continue;
+            }
+
+            // Past the end of the ROM there's only RAM the program used.
+            let text = match rom.get(offset) {
+                Some(&byte) => format!("{:02X}    DB {:#04X}  {}", byte, byte, bits(byte)),
+                None        => String::from("--    RAM")
+            };
+
+            try!(writeln!(out, "{:03X}  {:<28} {}  {:>10}", address, text, self.flags(address, 1),
+                          self.read[address] + self.written[address]));
+
+            address += 1;
+        }
+
+        Ok(())
+    }
+
+    fn write_summary<W: Write>(&self, rom: &[u8], out: &mut W) -> io::Result<()> {
+        let rom_range = ROM_START..ROM_START + rom.len();
+        let executed  = self.instruction_bytes();
+
+        let count = |counts: &[u64]| rom_range.clone().filter(|&address| counts[address] > 0).count();
+
+        let run     = count(&executed);
+        let read    = count(&self.read);
+        let written = count(&self.written);
+        let unused  = rom_range.clone().filter(|&address| executed[address] == 0 && !self.is_data(address)).count();
+
+        let below = (0..ROM_START).filter(|&address| self.read[address] > 0).count();
+
+        try!(writeln!(out, "; Coverage of a {} byte ROM: {} bytes run as code, {} read as data, {} written,",
+                      rom.len(), run, read, written));
+        try!(writeln!(out, "; {} never touched. {} bytes of the font and interpreter area below {:03X} read.",
+                      unused, below, ROM_START));
+        try!(writeln!(out, "; Flags: x run, r read, w written. Counts are runs for code and accesses for data."));
+
+        writeln!(out, "")
+    }
+
+    // How often each byte was run, counting both bytes of each instruction.
+    fn instruction_bytes(&self) -> Vec<u64> {
+        (0..MEMORY_SIZE).map(|address| {
+            let previous = if address > 0 { self.executed[address - 1] } else { 0 };
+
+            self.executed[address].max(previous)
+        }).collect()
+    }
+
+    fn is_data(&self, address: usize) -> bool {
+        address < MEMORY_SIZE && (self.read[address] > 0 || self.written[address] > 0)
+    }
+
+    fn is_used(&self, address: usize) -> bool {
+        self.executed[address] > 0 || self.is_data(address)
+    }
+
+    fn flags(&self, address: usize, length: usize) -> String {
+        let any = |counts: &[u64]| (address..address + length).any(|a| a < MEMORY_SIZE && counts[a] > 0);
+
+        let mut flags = String::new();
+
+        flags.push(if self.executed[address] > 0 { 'x' } else { '-' });
+        flags.push(if any(&self.read) { 'r' } else { '-' });
+        flags.push(if any(&self.written) { 'w' } else { '-' });
+
+        flags
+    }
+}
+
+// Brighter for more, on a square root curve so rarely touched bytes still
+// show up next to the busiest.
+fn shade(count: u64, most: u64) -> u8 {
+    if count == 0 || most == 0 {
+        return 0;
+    }
+
+    let level = (count as f64 / most as f64).sqrt();
+
+    (0x40 as f64 + level * (0xFF - 0x40) as f64) as u8
+}
+
+// A byte as the sprite row it would draw.
+fn bits(byte: u8) -> String {
+    (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touched(counts: &[u64]) -> Vec<usize> {
+        (0..MEMORY_SIZE).filter(|&address| counts[address] > 0).collect()
+    }
+
+    #[test]
+    fn records_sprite_rows_read_by_dxyn() {
+        let mut coverage = Coverage::new();
+
+        coverage.record(0x200, 0xD125, 0x300);
+
+        assert_eq!(touched(&coverage.executed), vec![0x200]);
+        assert_eq!(touched(&coverage.read), vec![0x300, 0x301, 0x302, 0x303, 0x304]);
+        assert_eq!(touched(&coverage.written), vec![]);
+    }
+
+    #[test]
+    fn records_bytes_stored_and_loaded_through_i() {
+        let mut coverage = Coverage::new();
+
+        coverage.record(0x200, 0xF733, 0x400);
+        coverage.record(0x202, 0xF355, 0x410);
+        coverage.record(0x204, 0xF265, 0x420);
+        coverage.record(0x206, 0xF065, 0x420);
+
+        assert_eq!(touched(&coverage.written), vec![0x400, 0x401, 0x402, 0x410, 0x411, 0x412, 0x413]);
+        assert_eq!(touched(&coverage.read), vec![0x420, 0x421, 0x422]);
+        assert_eq!(coverage.read[0x420], 2);
+    }
+
+    #[test]
+    fn wraps_accesses_past_the_end_of_memory() {
+        let mut coverage = Coverage::new();
+
+        coverage.record(0x200, 0xF365, 0xFFE);
+        coverage.record(0x202, 0xF033, 0xFFF);
+
+        assert_eq!(touched(&coverage.read), vec![0x000, 0x001, 0xFFE, 0xFFF]);
+        assert_eq!(touched(&coverage.written), vec![0x000, 0x001, 0xFFF]);
+    }
+
+    #[test]
+    fn ignores_instructions_that_leave_memory_alone() {
+        let mut coverage = Coverage::new();
+
+        coverage.record(0x200, 0xA300, 0x000);
+        coverage.record(0x202, 0xF31E, 0x300);
+        coverage.record(0x204, 0xD120, 0x300);
+
+        assert_eq!(touched(&coverage.read), vec![]);
+        assert_eq!(touched(&coverage.written), vec![]);
+    }
+
+    // SE V0, 0 skips a sprite that also decodes as LD V0, 0x18, which is
+    // drawn, then SNE V0, 0 falls through to a loop, never reaching the
+    // ADD after it.
+    const ROM: [u8; 16] = [
+        0x30, 0x00,             // 200: SE V0, 0
+        0x60, 0x18,             // 202: sprite
+        0xA2, 0x02,             // 204: LD I, 0x202
+        0xD0, 0x12,             // 206: DRW V0, V0, 2
+        0x40, 0x00,             // 208: SNE V0, 0
+        0x12, 0x0A,             // 20A: JP 0x20A
+        0x70, 0x01,             // 20C: ADD V0, 1
+        0x12, 0x0E              // 20E: JP 0x20E
+    ];
+
+    fn listing() -> Vec<String> {
+        let mut coverage = Coverage::new();
+
+        coverage.record(0x200, 0x3000, 0x000);
+        coverage.record(0x204, 0xA202, 0x000);
+        coverage.record(0x206, 0xD012, 0x202);
+        coverage.record(0x208, 0x4000, 0x202);
+        coverage.record(0x20A, 0x120A, 0x202);
+        coverage.record(0x20A, 0x120A, 0x202);
+
+        let mut out = Vec::new();
+        coverage.write_listing(&ROM, &mut out).unwrap();
+
+        String::from_utf8(out).unwrap().lines()
+            .filter(|line| !line.starts_with(';') && !line.is_empty())
+            .map(|line| line.split_whitespace().collect::<Vec<&str>>().join(" "))
+            .collect()
+    }
+
+    #[test]
+    fn lists_code_that_ran_with_its_counts() {
+        let listing = listing();
+
+        assert_eq!(listing[0], "200 3000 SE V0, 0x00 x-- 1");
+        assert!(listing.contains(&String::from("20A 120A JP 0x20A x-- 2")));
+    }
+
+    #[test]
+    fn lists_bytes_read_as_data_even_where_a_trace_goes() {
+        let listing = listing();
+
+        assert_eq!(listing[1], "202 60 DB 0x60 .##..... -r- 1");
+        assert_eq!(listing[2], "203 18 DB 0x18 ...##... -r- 1");
+        assert_eq!(listing[3], "204 A202 LD I, 0x202 x-- 1");
+    }
+
+    #[test]
+    fn lists_code_traced_but_never_run() {
+        let listing = listing();
+
+        assert!(listing.contains(&String::from("20C 7001 ADD V0, 0x01 --- 0")));
+        assert!(listing.contains(&String::from("20E 120E JP 0x20E --- 0")));
+        assert_eq!(listing.len(), 9);
+    }
+}
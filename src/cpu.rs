@@ -1,45 +1,237 @@
+use coverage::Coverage;
+use error::Error;
+use framebuffer::{self, Framebuffer};
 use mmu::Mmu;
+use profiler::Profiler;
+use quirks::Quirks;
+use std::mem;
 use std::vec::Vec;
 use rand::random;
 
+const SCREEN_WIDTH: u16  = framebuffer::WIDTH as u16;
+const SCREEN_HEIGHT: u16 = framebuffer::HEIGHT as u16;
+
+// Machine cycles the VIP interpreter spends fetching and decoding every
+// instruction before running its routine.
+const FETCH_CYCLES: u32 = 40;
+
 pub struct Cpu {
     mmu: Mmu,
+    quirks: Quirks,
     registers: Vec<u8>,
     stack: Vec<u16>,
-    video: Vec<u8>,
+    video: Framebuffer,
     input: Vec<u8>,
     delay_timer: u8,
     sound_timer: u8,
     sp: u16,
     pc: u16,
-    i: u16
+    i: u16,
+    cycles: u64,
+    instructions: u64,
+    waiting_for_vblank: bool,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>
 }
 
 impl Cpu {
     pub fn new(mmu: Mmu) -> Cpu {
         Cpu {
             mmu: mmu,
+            quirks: Quirks::new(),
             registers: vec![0; 16],
             stack: vec![0; 16],
-            video: vec![0; 2048],
+            video: Framebuffer::new(framebuffer::WIDTH, framebuffer::HEIGHT),
             input: vec![0; 16],
             delay_timer: 0,
             sound_timer: 0,
             sp: 0,
             pc: 0x200,
-            i: 0
+            i: 0,
+            cycles: 0,
+            instructions: 0,
+            waiting_for_vblank: false,
+            profiler: None,
+            coverage: None
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.video
+    }
+
+    pub fn framebuffer_mut(&mut self) -> &mut Framebuffer {
+        &mut self.video
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc & 0x0FFF;
+    }
+
+    pub fn mmu(&self) -> &Mmu {
+        &self.mmu
+    }
+
+    pub fn mmu_mut(&mut self) -> &mut Mmu {
+        &mut self.mmu
+    }
+
+    // Puts the registers, timers and display back how they were at power on.
+    // Memory is the Mmu's business and is left alone, and so are the profile
+    // and coverage.
+    pub fn reset(&mut self) {
+        let quirks = self.quirks;
+        let mmu = mem::replace(&mut self.mmu, Mmu::new());
+        let coverage = self.coverage.take();
+        let mut profiler = self.profiler.take();
+
+        if let Some(ref mut profiler) = profiler {
+            profiler.restart();
+        }
+
+        *self = Cpu::new(mmu);
+        self.quirks = quirks;
+        self.profiler = profiler;
+        self.coverage = coverage;
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    // Profiling costs a little on every instruction, so it's off unless
+    // given a profiler.
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    // As with profiling, coverage is off unless asked for.
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+        self.coverage = coverage;
+    }
+
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    pub fn set_register(&mut self, register: usize, value: u8) {
+        self.registers[register] = value;
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    // Moving SP up uncovers whatever return addresses were last there.
+    pub fn set_sp(&mut self, sp: u16) {
+        self.sp = sp.min(self.stack.len() as u16);
+    }
+
+    // Where the calls in progress were made from, outermost first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    pub fn is_key_down(&self, key: usize) -> bool {
+        self.input[key] != 0
+    }
+
+    pub fn set_keys(&mut self, keys: &[bool]) {
+        for (key, pressed) in keys.iter().take(16).enumerate() {
+            self.input[key] = *pressed as u8;
         }
     }
 
-    pub fn video_buffer(&self) -> Vec<u8> {
-        self.video.to_vec()
+    // COSMAC VIP machine cycles spent on the instructions executed so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
     }
 
-    pub fn step(&mut self) {
+    // Instructions executed since power on.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    // True while a draw is stalled waiting for the next 60 Hz tick.
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    pub fn step(&mut self) -> Result<(), Error> {
         // print_address(self.pc);
 
-        let instruction = self.mmu.read_word(self.pc as usize);
-        self.execute(instruction);
+        if self.waiting_for_vblank {
+            return Ok(());
+        }
+
+        let pc          = self.pc;
+        let i           = self.i;
+        let instruction = try!(self.mmu.read_word(pc as usize));
+        let cycles      = self.cycles_for(instruction);
+
+        try!(self.execute(instruction));
+
+        self.cycles += cycles as u64;
+        self.instructions += 1;
+
+        // The address space is 12 bits wide; keep PC inside it.
+        self.pc &= 0x0FFF;
+
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.record(pc, instruction, self.pc, self.delay_timer);
+        }
+
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.record(pc, instruction, i);
+        }
+
+        Ok(())
+    }
+
+    // Called at 60 Hz: counts the timers down and ends any display wait.
+    pub fn tick(&mut self) {
+        self.waiting_for_vblank = false;
+        self.video.end_frame();
 
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
@@ -53,7 +245,7 @@ impl Cpu {
         }
     }
 
-    fn execute(&mut self, instruction: u16) {
+    fn execute(&mut self, instruction: u16) -> Result<(), Error> {
         // println!("Instruction: {}", format!("{:X}", instruction));
 
         match instruction {
@@ -61,13 +253,13 @@ impl Cpu {
                 self.cls();
             },
             0x00EE => {
-                self.ret();
+                try!(self.ret());
             },
             0x1000 ... 0x1FFF => {
                 self.jp(instruction);
             },
             0x2000 ... 0x2FFF => {
-                self.call(instruction);
+                try!(self.call(instruction));
             },
             0x3000 ... 0x3FFF => {
                 self.se_v(instruction);
@@ -114,7 +306,7 @@ impl Cpu {
                         self.shl_v(instruction);
                     },
                     _ => {
-                        missing_instruction(instruction);
+                        return missing_instruction(instruction);
                     }
                 }
             },
@@ -131,7 +323,7 @@ impl Cpu {
                 self.rnd_v(instruction);
             },
             0xD000 ... 0xDFFF => {
-                self.drw_vv(instruction);
+                try!(self.drw_vv(instruction));
             },
             0xE000 ... 0xEFFF => {
                 match instruction & 0x00FF {
@@ -142,7 +334,7 @@ impl Cpu {
                         self.sknp_v(instruction);
                     },
                     _ => {
-                        missing_instruction(instruction);
+                        return missing_instruction(instruction);
                     }
                 }
             },
@@ -167,36 +359,104 @@ impl Cpu {
                         self.ld_f_v(instruction);
                     },
                     0x0033 => {
-                        self.ld_b_v(instruction);
+                        try!(self.ld_b_v(instruction));
                     },
                     0x0055 => {
-                        self.ld_i_v(instruction);
+                        try!(self.ld_i_v(instruction));
                     },
                     0x0065 => {
-                        self.ld_v_i(instruction);
+                        try!(self.ld_v_i(instruction));
                     },
                     _ => {
-                        missing_instruction(instruction);
+                        return missing_instruction(instruction);
                     }
                 }
             },
             _ => {
-                missing_instruction(instruction);
+                return missing_instruction(instruction);
             }
         }
+
+        Ok(())
+    }
+
+    // Machine cycles the COSMAC VIP interpreter spends on an instruction,
+    // counting 2 cycles per CDP1802 instruction (3 for long branches) in its
+    // routines, on top of FETCH_CYCLES for fetching and decoding. Must be
+    // called before the instruction executes, as some costs depend on state.
+    fn cycles_for(&self, instruction: u16) -> u32 {
+        let x = register_from(instruction);
+
+        let cycles = match instruction {
+            0x00E0 => {
+                // Clears 256 bytes of display memory, 6 cycles per byte.
+                24 + 256 * 6
+            },
+            0x00EE => 10,
+            0x1000 ... 0x1FFF => 12,
+            0x2000 ... 0x2FFF => 26,
+            0x3000 ... 0x3FFF => 10,
+            0x4000 ... 0x4FFF => 10,
+            0x5000 ... 0x5FFF => 14,
+            0x6000 ... 0x6FFF => 6,
+            0x7000 ... 0x7FFF => 10,
+            // The VIP builds and runs a small 1802 routine for every 8xyn.
+            0x8000 ... 0x8FFF => 44,
+            0x9000 ... 0x9FFF => 14,
+            0xA000 ... 0xAFFF => 12,
+            0xB000 ... 0xBFFF => 22,
+            0xC000 ... 0xCFFF => 36,
+            0xD000 ... 0xDFFF => {
+                // Sprites that aren't byte aligned are shifted a bit at a
+                // time and spill into a second display byte.
+                let height = (instruction & 0x000F) as u32;
+                let shift  = (self.registers[x] % 8) as u32;
+
+                let row = if shift == 0 { 34 } else { 52 + shift * 8 };
+
+                52 + height * row
+            },
+            0xE000 ... 0xEFFF => 14,
+            0xF000 ... 0xFFFF => {
+                match instruction & 0x00FF {
+                    0x000A => 16,
+                    0x001E => 16,
+                    0x0029 => 16,
+                    0x0033 => {
+                        // BCD is done by repeated subtraction, so the cost
+                        // grows with the sum of the digits.
+                        let value  = self.registers[x] as u32;
+                        let digits = value / 100 + (value / 10) % 10 + value % 10;
+
+                        40 + digits * 6
+                    },
+                    0x0055 | 0x0065 => 14 + (x as u32 + 1) * 14,
+                    _ => 10
+                }
+            },
+            _ => 0
+        };
+
+        FETCH_CYCLES + cycles
     }
 
     // 0x00E0
     fn cls(&mut self) {
-        self.video = vec![0; 2048];
+        self.video.clear();
 
         self.pc += 2;
     }
 
     // 0x00EE
-    fn ret(&mut self) {
+    fn ret(&mut self) -> Result<(), Error> {
+        if self.sp == 0 {
+            return Err(Error::StackUnderflow);
+        }
+
         self.sp -= 1;
         self.pc = self.stack[self.sp as usize] + 2;
+
+        Ok(())
     }
 
     // 0x1nnn
@@ -205,10 +465,16 @@ impl Cpu {
     }
 
     // 0x2nnn
-    fn call(&mut self, instruction: u16) {
+    fn call(&mut self, instruction: u16) -> Result<(), Error> {
+        if self.sp as usize >= self.stack.len() {
+            return Err(Error::StackOverflow);
+        }
+
         self.stack[self.sp as usize] = self.pc;
         self.sp += 1;
         self.pc = instruction & 0x0FFF;
+
+        Ok(())
     }
 
     // 0x3xkk
@@ -340,11 +606,10 @@ impl Cpu {
 
     // 0x8xy6
     fn shr_v(&mut self, instruction: u16) {
-        let (x, value) = register_and_value_from(instruction);
-
-        self.registers[0xF] = value & 0x1;
+        let (x, source) = self.shift_source(instruction);
 
-        self.registers[x] >>= 1;
+        self.registers[x]   = source >> 1;
+        self.registers[0xF] = source & 0x1;
 
         self.pc += 2;
     }
@@ -366,15 +631,25 @@ impl Cpu {
 
     // 0x8xyE
     fn shl_v(&mut self, instruction: u16) {
-        let (x, value) = register_and_value_from(instruction);
-
-        self.registers[0xF] = value >> 7;
+        let (x, source) = self.shift_source(instruction);
 
-        self.registers[x] <<= 1;
+        self.registers[x]   = source << 1;
+        self.registers[0xF] = source >> 7;
 
         self.pc += 2;
     }
 
+    // The register a shift writes to and the value it shifts.
+    fn shift_source(&self, instruction: u16) -> (usize, u8) {
+        let (x, y) = registers_from(instruction);
+
+        if self.quirks.shift_vy {
+            (x, self.registers[y])
+        } else {
+            (x, self.registers[x])
+        }
+    }
+
     // 0x9xy0
     fn sne_v_v(&mut self, instruction: u16) {
         let (x, y) = registers_from(instruction);
@@ -408,12 +683,14 @@ impl Cpu {
     }
 
     // 0xDxyn
-    fn drw_vv(&mut self, instruction: u16) {
+    fn drw_vv(&mut self, instruction: u16) -> Result<(), Error> {
         let vx = ((instruction & 0x0F00) >> 8) as usize;
         let vy = ((instruction & 0x00F0) >> 4) as usize;
 
-        let x = self.registers[vx];
-        let y = self.registers[vy];
+        // The starting position always wraps; only the pixels that run off
+        // the edge are subject to the wrap_sprites quirk.
+        let x = self.registers[vx] as u16 % SCREEN_WIDTH;
+        let y = self.registers[vy] as u16 % SCREEN_HEIGHT;
 
         let height = instruction & 0x000F;
 
@@ -422,28 +699,41 @@ impl Cpu {
         self.registers[0xF] = 0;
 
         for yline in 0..height {
+            if y + yline >= SCREEN_HEIGHT && !self.quirks.wrap_sprites {
+                break;
+            }
 
-            pixel = self.mmu.read_byte((self.i + yline) as usize) as u16;
+            pixel = try!(self.mmu.read_byte(self.i as usize + yline as usize)) as u16;
 
             for xline in 0..8 {
+                if x + xline >= SCREEN_WIDTH && !self.quirks.wrap_sprites {
+                    break;
+                }
+
                 if pixel & (0x80 >> xline) != 0 {
-                    let vc = video_coordinates(x, y, xline, yline);
+                    let column = ((x + xline) % SCREEN_WIDTH) as usize;
+                    let row    = ((y + yline) % SCREEN_HEIGHT) as usize;
 
-                    if self.video[vc] == 1 {
+                    if self.video.toggle(column, row) {
                         self.registers[0xF] = 1;
                     }
-                    self.video[vc] ^= 1;
                 }
             }
         }
 
+        if self.quirks.display_wait {
+            self.waiting_for_vblank = true;
+        }
+
         self.pc += 2;
+
+        Ok(())
     }
 
     // 0xEx9E
     fn skp_v(&mut self, instruction: u16) {
         let register = register_from(instruction);
-        let key      = self.registers[register] as usize;
+        let key      = (self.registers[register] & 0xF) as usize;
 
         if self.input[key] == 1 {
             self.pc += 4;
@@ -455,7 +745,7 @@ impl Cpu {
     // 0xExA1
     fn sknp_v(&mut self, instruction: u16) {
         let register = register_from(instruction);
-        let key      = self.registers[register] as usize;
+        let key      = (self.registers[register] & 0xF) as usize;
 
         if self.input[key] != 1 {
             self.pc += 4;
@@ -509,7 +799,7 @@ impl Cpu {
     fn add_i_v(&mut self, instruction: u16) {
         let register = register_from(instruction);
 
-        self.i += self.registers[register] as u16;
+        self.i = self.i.wrapping_add(self.registers[register] as u16);
 
         self.pc += 2;
     }
@@ -524,42 +814,52 @@ impl Cpu {
     }
 
     // 0xFx33
-    fn ld_b_v(&mut self, instruction: u16) {
+    fn ld_b_v(&mut self, instruction: u16) -> Result<(), Error> {
         let register = register_from(instruction);
         let value    = self.registers[register];
 
-        self.mmu.write_byte(self.i as usize, value / 100);
-        self.mmu.write_byte(self.i as usize + 1, (value / 10) % 10);
-        self.mmu.write_byte(self.i as usize + 2, (value % 100) % 10);
+        try!(self.mmu.write_byte(self.i as usize, value / 100));
+        try!(self.mmu.write_byte(self.i as usize + 1, (value / 10) % 10));
+        try!(self.mmu.write_byte(self.i as usize + 2, (value % 100) % 10));
 
         self.pc += 2;
+
+        Ok(())
     }
 
     // 0xFx55
-    fn ld_i_v(&mut self, instruction: u16) {
+    fn ld_i_v(&mut self, instruction: u16) -> Result<(), Error> {
         let last_register = register_from(instruction);
 
         for index in 0..(last_register + 1) {
-            self.mmu.write_byte(self.i as usize + index, self.registers[index]);
+            try!(self.mmu.write_byte(self.i as usize + index, self.registers[index]));
+        }
+
+        if self.quirks.load_store_i {
+            self.i = self.i.wrapping_add(last_register as u16 + 1);
         }
 
         self.pc += 2;
+
+        Ok(())
     }
 
     // 0xFx65
-    fn ld_v_i(&mut self, instruction: u16) {
+    fn ld_v_i(&mut self, instruction: u16) -> Result<(), Error> {
         let last_register = register_from(instruction);
 
         for index in 0..(last_register + 1) {
-            self.registers[index] = self.mmu.read_byte(self.i as usize + index);
+            self.registers[index] = try!(self.mmu.read_byte(self.i as usize + index));
+        }
+
+        if self.quirks.load_store_i {
+            self.i = self.i.wrapping_add(last_register as u16 + 1);
         }
 
         self.pc += 2;
-    }
-}
 
-fn video_coordinates(x: u8, y: u8, xline: u16, yline: u16) -> usize {
-    (x as u16 + xline + ((y as u16 + yline) * 64)) as usize
+        Ok(())
+    }
 }
 
 fn register_from(instruction: u16) -> usize {
@@ -588,7 +888,117 @@ fn print_address(address: u16) {
     }
 }
 
-fn missing_instruction(instruction: u16) {
-    panic!("Missing instruction: {} (HEX) / {} (DEC)",
-        format!("{:#X}", instruction), instruction)
+fn missing_instruction(instruction: u16) -> Result<(), Error> {
+    Err(Error::UnknownInstruction(instruction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use framebuffer::PIXEL_ON;
+
+    // Draws the 4x4 block at 0x208 with V0 and V1 as its position.
+    const DRAW_BLOCK: [u8; 12] = [
+        0xA2, 0x08,             // LD I, 0x208
+        0xD0, 0x14,             // DRW V0, V1, 4
+        0x12, 0x04,             // JP 0x204
+        0x00, 0x00,
+        0xF0, 0xF0, 0xF0, 0xF0
+    ];
+
+    fn draw_block_at(x: u8, y: u8, wrap_sprites: bool, lit: &[(usize, usize)]) -> Cpu {
+        let mut mmu = Mmu::new();
+        mmu.load_rom(&DRAW_BLOCK).unwrap();
+
+        let mut quirks = Quirks::new();
+        quirks.wrap_sprites = wrap_sprites;
+
+        let mut cpu = Cpu::new(mmu);
+        cpu.set_quirks(quirks);
+        cpu.set_register(0, x);
+        cpu.set_register(1, y);
+
+        for &(x, y) in lit.iter() {
+            cpu.framebuffer_mut().set_pixel(x, y, PIXEL_ON);
+        }
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        cpu
+    }
+
+    fn lit_pixels(cpu: &Cpu) -> Vec<(usize, usize)> {
+        let frame = cpu.framebuffer();
+        let mut lit = Vec::new();
+
+        for y in 0..frame.height() {
+            for x in 0..frame.width() {
+                if frame.is_lit(x, y) {
+                    lit.push((x, y));
+                }
+            }
+        }
+
+        lit
+    }
+
+    #[test]
+    fn draws_sprites_inside_the_screen_whole() {
+        let cpu = draw_block_at(10, 5, false, &[]);
+
+        assert_eq!(lit_pixels(&cpu).len(), 16);
+        assert_eq!(cpu.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn clips_at_the_right_and_bottom_edges() {
+        let cpu = draw_block_at(62, 30, false, &[]);
+
+        assert_eq!(lit_pixels(&cpu), vec![(62, 30), (63, 30), (62, 31), (63, 31)]);
+    }
+
+    #[test]
+    fn wraps_the_origin_whatever_the_quirk() {
+        let wrapped = draw_block_at(62 + 64, 30 + 32, false, &[]);
+        let direct  = draw_block_at(62, 30, false, &[]);
+
+        assert_eq!(lit_pixels(&wrapped), lit_pixels(&direct));
+
+        let cpu = draw_block_at(200, 100, false, &[]);
+
+        // 200 % 64 = 8 and 100 % 32 = 4, well inside the screen.
+        assert_eq!(lit_pixels(&cpu).len(), 16);
+        assert!(cpu.framebuffer().is_lit(8, 4));
+        assert!(cpu.framebuffer().is_lit(11, 7));
+    }
+
+    #[test]
+    fn wraps_edge_pixels_with_the_quirk() {
+        let cpu = draw_block_at(62, 30, true, &[]);
+
+        assert_eq!(lit_pixels(&cpu), vec![
+            (0, 0), (1, 0), (62, 0), (63, 0),
+            (0, 1), (1, 1), (62, 1), (63, 1),
+            (0, 30), (1, 30), (62, 30), (63, 30),
+            (0, 31), (1, 31), (62, 31), (63, 31)
+        ]);
+        assert_eq!(cpu.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn sets_vf_for_collisions_on_wrapped_pixels() {
+        let cpu = draw_block_at(62, 30, true, &[(1, 1)]);
+
+        assert_eq!(cpu.registers()[0xF], 1);
+        assert!(!cpu.framebuffer().is_lit(1, 1));
+    }
+
+    #[test]
+    fn leaves_pixels_past_a_clipped_edge_alone() {
+        let cpu = draw_block_at(62, 30, false, &[(1, 1)]);
+
+        assert_eq!(cpu.registers()[0xF], 0);
+        assert!(cpu.framebuffer().is_lit(1, 1));
+    }
 }
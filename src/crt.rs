@@ -0,0 +1,303 @@
+use std::str::FromStr;
+use std::vec::Vec;
+
+use framebuffer::Framebuffer;
+use palette::{Color, Palette};
+
+// Strength of each CRT effect, from 0.0 (off) to 1.0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CrtSettings {
+    // How much every other output row is darkened.
+    pub scanlines: f32,
+    // How much the last row and column of each emulated pixel is darkened.
+    pub gaps: f32,
+    // How much lit pixels glow into their neighbours.
+    pub bloom: f32,
+    // Barrel distortion; around 0.1 looks like a curved tube.
+    pub curvature: f32
+}
+
+impl CrtSettings {
+    pub fn off() -> CrtSettings {
+        CrtSettings {
+            scanlines: 0.0,
+            gaps: 0.0,
+            bloom: 0.0,
+            curvature: 0.0
+        }
+    }
+
+    pub fn all() -> CrtSettings {
+        CrtSettings {
+            scanlines: 0.35,
+            gaps: 0.5,
+            bloom: 0.25,
+            curvature: 0.08
+        }
+    }
+
+    pub fn is_off(&self) -> bool {
+        *self == CrtSettings::off()
+    }
+}
+
+// Parses "all", or a comma separated list of effects, each with an optional
+// strength: "scanlines,bloom=0.5".
+impl FromStr for CrtSettings {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CrtSettings, String> {
+        if s == "all" {
+            return Ok(CrtSettings::all());
+        }
+
+        let defaults = CrtSettings::all();
+        let mut settings = CrtSettings::off();
+
+        for effect in s.split(',') {
+            let mut parts = effect.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+
+            let strength = match parts.next() {
+                Some(value) => {
+                    let strength = try!(value.trim().parse::<f32>()
+                        .map_err(|_| format!("Invalid strength for {}: {}", name, value)));
+
+                    // Written to catch NaN too.
+                    if !(strength >= 0.0 && strength <= 1.0) {
+                        return Err(format!("Strength for {} out of range: {} (expected 0.0 to 1.0)", name, value));
+                    }
+
+                    strength
+                },
+                None => {
+                    match name {
+                        "scanlines" => defaults.scanlines,
+                        "gaps"      => defaults.gaps,
+                        "bloom"     => defaults.bloom,
+                        "curvature" => defaults.curvature,
+                        _           => 0.0
+                    }
+                }
+            };
+
+            match name {
+                "scanlines" => settings.scanlines = strength,
+                "gaps"      => settings.gaps      = strength,
+                "bloom"     => settings.bloom     = strength,
+                "curvature" => settings.curvature = strength,
+                _ => {
+                    return Err(format!("Unknown CRT effect: {} (expected scanlines, gaps, bloom, curvature or all)", name));
+                }
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+// Upscales frames into RGB24 images with CRT effects, entirely on the CPU.
+pub struct Crt {
+    settings: CrtSettings,
+    scale: usize,
+    size: (usize, usize),
+    // For each output pixel, the undistorted point to sample, or None for the
+    // black border outside the curved screen.
+    samples: Vec<Option<(usize, usize)>>,
+    glow: Vec<f32>,
+    pixels: Vec<u8>
+}
+
+impl Crt {
+    pub fn new(settings: CrtSettings, scale: usize) -> Crt {
+        Crt {
+            settings: settings,
+            scale: scale,
+            size: (0, 0),
+            samples: Vec::new(),
+            glow: Vec::new(),
+            pixels: Vec::new()
+        }
+    }
+
+    // The output size for a frame, in pixels.
+    pub fn output_size(&self, frame: &Framebuffer) -> (usize, usize) {
+        (frame.width() * self.scale, frame.height() * self.scale)
+    }
+
+    pub fn render(&mut self, frame: &Framebuffer, palette: &Palette) -> &[u8] {
+        let (width, height) = self.output_size(frame);
+
+        if self.size != (width, height) {
+            self.size    = (width, height);
+            self.samples = distortion_map(width, height, self.settings.curvature);
+        }
+
+        self.update_glow(frame);
+        self.pixels.clear();
+
+        for sample in self.samples.iter() {
+            let color = match *sample {
+                Some((x, y)) => self.shade(frame, palette, x, y),
+                None         => Color::new(0, 0, 0)
+            };
+
+            self.pixels.push(color.r);
+            self.pixels.push(color.g);
+            self.pixels.push(color.b);
+        }
+
+        &self.pixels
+    }
+
+    // Blurs the frame with a 3x3 box at its own resolution; that's all the
+    // bloom needs and keeps it cheap at large scales.
+    fn update_glow(&mut self, frame: &Framebuffer) {
+        self.glow.clear();
+
+        if self.settings.bloom <= 0.0 {
+            return;
+        }
+
+        let (width, height) = (frame.width() as isize, frame.height() as isize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = 0.0;
+
+                for dy in -1..2 {
+                    for dx in -1..2 {
+                        let (nx, ny) = (x + dx, y + dy);
+
+                        if nx >= 0 && ny >= 0 && nx < width && ny < height {
+                            sum += frame.pixel(nx as usize, ny as usize) as f32 / 255.0;
+                        }
+                    }
+                }
+
+                self.glow.push(sum / 9.0);
+            }
+        }
+    }
+
+    fn shade(&self, frame: &Framebuffer, palette: &Palette, x: usize, y: usize) -> Color {
+        let (column, row) = (x / self.scale, y / self.scale);
+
+        let mut brightness = 1.0;
+
+        if self.settings.gaps > 0.0 && self.scale >= 3 {
+            if x % self.scale == self.scale - 1 || y % self.scale == self.scale - 1 {
+                brightness *= 1.0 - self.settings.gaps;
+            }
+        }
+
+        if self.settings.scanlines > 0.0 && self.scale >= 2 && y % 2 == 1 {
+            brightness *= 1.0 - self.settings.scanlines;
+        }
+
+        let color = palette.shade(frame.pixel(column, row));
+
+        let glow = if self.glow.is_empty() {
+            0.0
+        } else {
+            self.glow[row * frame.width() + column] * self.settings.bloom
+        };
+
+        let foreground = palette.foreground();
+
+        let channel = |value: u8, glow_value: u8| {
+            let value = value as f32 * brightness + glow_value as f32 * glow;
+
+            if value > 255.0 { 255 } else { value as u8 }
+        };
+
+        Color::new(channel(color.r, foreground.r),
+                   channel(color.g, foreground.g),
+                   channel(color.b, foreground.b))
+    }
+}
+
+// Maps every output pixel through a barrel distortion back to the flat image.
+fn distortion_map(width: usize, height: usize, curvature: f32) -> Vec<Option<(usize, usize)>> {
+    let mut samples = Vec::with_capacity(width * height);
+
+    for y in 0..height {
+        for x in 0..width {
+            if curvature <= 0.0 {
+                samples.push(Some((x, y)));
+                continue;
+            }
+
+            // Centre on the screen in -1..1, push points outwards the further
+            // they are from the middle, then map back.
+            let u = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+
+            let bend = 1.0 + curvature * (u * u + v * v);
+
+            let su = (u * bend + 1.0) / 2.0 * width as f32;
+            let sv = (v * bend + 1.0) / 2.0 * height as f32;
+
+            if su < 0.0 || sv < 0.0 || su >= width as f32 || sv >= height as f32 {
+                samples.push(None);
+            } else {
+                samples.push(Some((su as usize, sv as usize)));
+            }
+        }
+    }
+
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<CrtSettings, String> {
+        s.parse()
+    }
+
+    #[test]
+    fn turns_everything_on_with_all() {
+        assert_eq!(parse("all"), Ok(CrtSettings::all()));
+        assert!(!CrtSettings::all().is_off());
+        assert!(CrtSettings::off().is_off());
+    }
+
+    #[test]
+    fn uses_default_strengths_for_bare_names() {
+        let settings = parse("scanlines, bloom").unwrap();
+
+        assert_eq!(settings.scanlines, CrtSettings::all().scanlines);
+        assert_eq!(settings.bloom, CrtSettings::all().bloom);
+        assert_eq!(settings.gaps, 0.0);
+        assert_eq!(settings.curvature, 0.0);
+    }
+
+    #[test]
+    fn takes_strengths_after_an_equals_sign() {
+        let settings = parse("scanlines=0.5,curvature = 0.1,gaps=1,bloom=0").unwrap();
+
+        assert_eq!(settings, CrtSettings { scanlines: 0.5, gaps: 1.0, bloom: 0.0, curvature: 0.1 });
+        assert!(parse("bloom=0").unwrap().is_off());
+    }
+
+    #[test]
+    fn rejects_strengths_outside_zero_to_one() {
+        assert_eq!(parse("bloom=50"), Err(String::from("Strength for bloom out of range: 50 (expected 0.0 to 1.0)")));
+        assert_eq!(parse("scanlines=-0.1"),
+                   Err(String::from("Strength for scanlines out of range: -0.1 (expected 0.0 to 1.0)")));
+        assert!(parse("gaps=NaN").is_err());
+        assert!(parse("gaps=inf").is_err());
+        assert_eq!(parse("gaps=lots"), Err(String::from("Invalid strength for gaps: lots")));
+    }
+
+    #[test]
+    fn rejects_unknown_effects() {
+        let expected = "Unknown CRT effect: glow (expected scanlines, gaps, bloom, curvature or all)";
+
+        assert_eq!(parse("scanlines,glow"), Err(String::from(expected)));
+        assert!(parse("").is_err());
+        assert!(parse("ALL").is_err());
+    }
+}
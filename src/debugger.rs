@@ -0,0 +1,501 @@
+use std::vec::Vec;
+
+use cpu::Cpu;
+use disassembler;
+use font;
+use framebuffer::{self, Framebuffer, PIXEL_OFF, PIXEL_ON};
+use frontend::Command;
+use mmu::MEMORY_SIZE;
+use overlay;
+
+// Room for the panels beside and below the game, in overlay pixels.
+pub const SIDE_WIDTH: usize    = 120;
+pub const BOTTOM_HEIGHT: usize = 104;
+
+// Space around the panels' contents.
+const GAP: usize = 4;
+
+// Headings are dimmer than what's under them, and the cursor's line and the
+// bytes I points at sit on a dim background.
+const HEADING: u8   = 0x90;
+const HIGHLIGHT: u8 = 0x48;
+
+// Four lines of V registers, then I and SP, then the timers.
+const REGISTER_LINES: usize = 6;
+const STACK_LINES: usize    = 6;
+
+const BYTES_PER_ROW: usize = 16;
+
+// The hex keypad as it's laid out on the VIP.
+const KEYPAD: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF]
+];
+
+// Each key's box, with a pixel between boxes.
+const KEY_CELL: (usize, usize) = (font::GLYPH_WIDTH + 6, font::GLYPH_HEIGHT + 4);
+
+// The size of the debugger's panels for a display with CHIP-8 pixels `scale`
+// pixels wide, in overlay pixels. The panels fill the window, leaving the top
+// left corner for the game. At small scales the memory and keypad are wider
+// than the game, and the side panel grows to match.
+pub fn panel_size(scale: usize) -> (usize, usize) {
+    let (width, height) = game_area(scale);
+
+    ((width + SIDE_WIDTH).max(bottom_width()), height + BOTTOM_HEIGHT)
+}
+
+// The memory rows: an address, then a byte every three characters.
+fn memory_width() -> usize {
+    GAP + font::width_of("000:") + BYTES_PER_ROW * 3 * font::ADVANCE
+}
+
+fn keypad_left() -> usize {
+    memory_width() + 2 * GAP
+}
+
+// Room for the memory and keypad side by side.
+fn bottom_width() -> usize {
+    keypad_left() + KEYPAD[0].len() * KEY_CELL.0 + GAP
+}
+
+fn game_area(scale: usize) -> (usize, usize) {
+    let size = overlay::pixel_size(scale);
+
+    ((framebuffer::WIDTH * scale + size - 1) / size, (framebuffer::HEIGHT * scale + size - 1) / size)
+}
+
+// Breakpoints, plus what the panels are looking at: a disassembly around PC,
+// or around a cursor moved away from it, the registers and call stack, the
+// memory around I, and the keypad.
+pub struct Debugger {
+    game: (usize, usize),
+    breakpoints: Vec<u16>,
+    cursor: Option<u16>,
+    memory_offset: isize,
+    resuming_from: Option<u16>
+}
+
+impl Debugger {
+    // `scale` is the display's, as for `panel_size`.
+    pub fn new(scale: usize) -> Debugger {
+        Debugger {
+            game: game_area(scale),
+            breakpoints: Vec::new(),
+            cursor: None,
+            memory_offset: 0,
+            resuming_from: None
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[u16] {
+        &self.breakpoints
+    }
+
+    pub fn has_breakpoints(&self) -> bool {
+        !self.breakpoints.is_empty()
+    }
+
+    pub fn set_breakpoint(&mut self, address: u16) {
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+    }
+
+    pub fn clear_breakpoint(&mut self, address: u16) {
+        self.breakpoints.retain(|&breakpoint| breakpoint != address);
+    }
+
+    // Sets a breakpoint, or clears the one already there. Returns whether
+    // one is set now.
+    pub fn toggle_breakpoint(&mut self, address: u16) -> bool {
+        if self.breakpoints.contains(&address) {
+            self.clear_breakpoint(address);
+            false
+        } else {
+            self.set_breakpoint(address);
+            true
+        }
+    }
+
+    // Called when execution carries on from `pc`, so that a breakpoint there
+    // doesn't stop it again straight away.
+    pub fn resume(&mut self, pc: u16) {
+        self.resuming_from = Some(pc);
+    }
+
+    // Whether to stop before running the instruction at `pc`.
+    pub fn should_break(&mut self, pc: u16) -> bool {
+        let resuming = self.resuming_from.take() == Some(pc);
+
+        !resuming && self.breakpoints.contains(&pc)
+    }
+
+    // Puts the disassembly back on PC, after the program has moved.
+    pub fn follow_pc(&mut self) {
+        self.cursor = None;
+    }
+
+    // Acts on the commands that drive the panels, returning a message worth
+    // showing, if any. Stepping and running are up to the caller.
+    pub fn command(&mut self, command: Command, cpu: &Cpu) -> Option<String> {
+        let cursor = self.cursor.unwrap_or(cpu.pc());
+
+        match command {
+            Command::Up   => self.cursor = Some(cursor.saturating_sub(2)),
+            Command::Down => self.cursor = Some((cursor + 2).min(0xFFE)),
+            Command::ToggleBreakpoint => {
+                return Some(self.describe_toggle(cursor));
+            },
+            Command::MemoryUp   => self.scroll_memory(cpu, -(self.memory_rows() as isize)),
+            Command::MemoryDown => self.scroll_memory(cpu, self.memory_rows() as isize),
+            Command::Click(x, y) => {
+                if let Some(address) = self.disassembly_line_at(x, y, cpu) {
+                    return Some(self.describe_toggle(address));
+                }
+            },
+            _ => { }
+        }
+
+        None
+    }
+
+    fn describe_toggle(&mut self, address: u16) -> String {
+        if self.toggle_breakpoint(address) {
+            format!("Set breakpoint at {:#05X}", address)
+        } else {
+            format!("Cleared breakpoint at {:#05X}", address)
+        }
+    }
+
+    // Draws the panels into `panels`, which should be `panel_size` big and
+    // start out clear.
+    pub fn draw(&self, cpu: &Cpu, panels: &mut Framebuffer) {
+        self.draw_disassembly(cpu, panels);
+        self.draw_registers(cpu, panels);
+        self.draw_stack(cpu, panels);
+        self.draw_memory(cpu, panels);
+        self.draw_keypad(cpu, panels);
+    }
+
+    fn side(&self) -> usize {
+        self.game.0 + GAP
+    }
+
+    fn disassembly_top(&self) -> usize {
+        GAP + font::LINE_HEIGHT + 1
+    }
+
+    // The side panel holds as much disassembly as fits above the registers
+    // and stack.
+    fn disassembly_lines(&self) -> usize {
+        let height = self.game.1 + BOTTOM_HEIGHT;
+        let below  = (REGISTER_LINES + STACK_LINES + 2) * font::LINE_HEIGHT + 2 + 2 * GAP;
+
+        height.saturating_sub(self.disassembly_top() + below + GAP) / font::LINE_HEIGHT
+    }
+
+    // The address at the top of the disassembly, chosen to put the cursor in
+    // the middle.
+    fn first_line(&self, cpu: &Cpu) -> u16 {
+        let center = self.cursor.unwrap_or(cpu.pc()) as usize;
+        let lines  = self.disassembly_lines();
+
+        // Keep lines on the same side of word boundaries as the cursor.
+        let first = center.saturating_sub(lines / 2 * 2);
+        let last  = 0x1000usize.saturating_sub(lines * 2);
+
+        first.min(last + center % 2) as u16
+    }
+
+    fn disassembly_line_at(&self, x: usize, y: usize, cpu: &Cpu) -> Option<u16> {
+        let top = self.disassembly_top();
+
+        if x < self.side() || y < top {
+            return None;
+        }
+
+        let line = (y - top) / font::LINE_HEIGHT;
+
+        if line >= self.disassembly_lines() {
+            return None;
+        }
+
+        Some(self.first_line(cpu) + line as u16 * 2)
+    }
+
+    fn draw_disassembly(&self, cpu: &Cpu, panels: &mut Framebuffer) {
+        let x = self.side();
+
+        font::draw_text(panels, x, GAP, "DISASSEMBLY", HEADING);
+
+        let cursor = self.cursor.unwrap_or(cpu.pc());
+        let first  = self.first_line(cpu);
+
+        for line in 0..self.disassembly_lines() {
+            let address = first + line as u16 * 2;
+            let y = self.disassembly_top() + line * font::LINE_HEIGHT;
+
+            if address > 0xFFF {
+                break;
+            }
+
+            if address == cursor {
+                font::fill_rect(panels, x - 1, y - 1, SIDE_WIDTH - GAP, font::LINE_HEIGHT, HIGHLIGHT);
+            }
+
+            let instruction = cpu.mmu().read_word(address as usize).unwrap_or(0);
+
+            let breakpoint = if self.breakpoints.contains(&address) { '*' } else { ' ' };
+            let pc         = if address == cpu.pc() { '>' } else { ' ' };
+
+            let text = format!("{}{}{:03X} {:04X} {}", breakpoint, pc, address, instruction,
+                               disassembler::disassemble(instruction));
+
+            font::draw_text(panels, x, y, &text, PIXEL_ON);
+        }
+    }
+
+    fn draw_registers(&self, cpu: &Cpu, panels: &mut Framebuffer) {
+        let x = self.side();
+        let mut y = self.disassembly_top() + self.disassembly_lines() * font::LINE_HEIGHT + GAP;
+
+        font::draw_text(panels, x, y, "REGISTERS", HEADING);
+        y += font::LINE_HEIGHT + 1;
+
+        let registers = cpu.registers();
+
+        for row in 0..4 {
+            let text: Vec<String> = (row * 4..row * 4 + 4)
+                .map(|register| format!("V{:X} {:02X}", register, registers[register]))
+                .collect();
+
+            font::draw_text(panels, x, y, &text.join(" "), PIXEL_ON);
+            y += font::LINE_HEIGHT;
+        }
+
+        font::draw_text(panels, x, y, &format!("I  {:03X}  SP {:X}", cpu.i(), cpu.sp()), PIXEL_ON);
+        y += font::LINE_HEIGHT;
+
+        font::draw_text(panels, x, y, &format!("DT {:02X}   ST {:02X}", cpu.delay_timer(), cpu.sound_timer()), PIXEL_ON);
+    }
+
+    // Innermost call first, each with the instruction that made it.
+    fn draw_stack(&self, cpu: &Cpu, panels: &mut Framebuffer) {
+        let x = self.side();
+        let mut y = self.disassembly_top() + self.disassembly_lines() * font::LINE_HEIGHT + GAP
+                  + (REGISTER_LINES + 1) * font::LINE_HEIGHT + 1 + GAP;
+
+        font::draw_text(panels, x, y, "STACK", HEADING);
+        y += font::LINE_HEIGHT + 1;
+
+        let stack = cpu.stack();
+
+        if stack.is_empty() {
+            font::draw_text(panels, x, y, "EMPTY", PIXEL_ON);
+            return;
+        }
+
+        for (line, &address) in stack.iter().rev().enumerate() {
+            // Leave the last line to say how many more there are.
+            if line == STACK_LINES - 1 && stack.len() > STACK_LINES {
+                font::draw_text(panels, x, y, &format!("{} MORE", stack.len() - line), PIXEL_ON);
+                break;
+            }
+
+            let instruction = cpu.mmu().read_word(address as usize).unwrap_or(0);
+            let text = format!("{:03X} {}", address, disassembler::disassemble(instruction));
+
+            font::draw_text(panels, x, y, &text, PIXEL_ON);
+            y += font::LINE_HEIGHT;
+        }
+    }
+
+    fn memory_rows(&self) -> usize {
+        (BOTTOM_HEIGHT - 2 * GAP - font::LINE_HEIGHT - 1) / font::LINE_HEIGHT
+    }
+
+    // The first row of memory shown, kept within memory however far it's
+    // scrolled.
+    fn first_memory_row(&self, cpu: &Cpu) -> usize {
+        let max_row = MEMORY_SIZE / BYTES_PER_ROW - self.memory_rows();
+
+        (memory_home(cpu) + self.memory_offset).max(0).min(max_row as isize) as usize
+    }
+
+    // Scrolling stops at either end, so scrolling back the other way takes
+    // effect straight away.
+    fn scroll_memory(&mut self, cpu: &Cpu, rows: isize) {
+        self.memory_offset += rows;
+        self.memory_offset = self.first_memory_row(cpu) as isize - memory_home(cpu);
+    }
+
+    // Rows of 16 bytes, starting just above I's row unless scrolled, with the
+    // bytes the instruction at PC would use through I highlighted.
+    fn draw_memory(&self, cpu: &Cpu, panels: &mut Framebuffer) {
+        let mut y = self.game.1 + GAP;
+
+        font::draw_text(panels, GAP, y, "MEMORY", HEADING);
+        y += font::LINE_HEIGHT + 1;
+
+        let rows  = self.memory_rows();
+        let first = self.first_memory_row(cpu);
+
+        // I past the end of memory is taken the way the Mmu takes it by
+        // default, wrapped round, and the highlight stops at the end.
+        let i_start = cpu.i() as usize % MEMORY_SIZE;
+        let i_end   = (i_start + i_length(cpu)).min(MEMORY_SIZE);
+
+        for row in first..first + rows {
+            let address = row * BYTES_PER_ROW;
+
+            let mut x = font::draw_text(panels, GAP, y, &format!("{:03X}:", address), HEADING);
+
+            for offset in 0..BYTES_PER_ROW {
+                let byte = cpu.mmu().read_byte(address + offset).unwrap_or(0);
+
+                x += font::ADVANCE;
+
+                if address + offset >= i_start && address + offset < i_end {
+                    font::fill_rect(panels, x - 1, y - 1, 2 * font::ADVANCE + 1, font::LINE_HEIGHT, HIGHLIGHT);
+                }
+
+                x = font::draw_text(panels, x, y, &format!("{:02X}", byte), PIXEL_ON);
+            }
+
+            y += font::LINE_HEIGHT;
+        }
+    }
+
+    // Pressed keys are drawn inverted.
+    fn draw_keypad(&self, cpu: &Cpu, panels: &mut Framebuffer) {
+        let left = keypad_left();
+        let top  = self.game.1 + GAP;
+
+        font::draw_text(panels, left, top, "KEYPAD", HEADING);
+
+        let cell = KEY_CELL;
+
+        for (row, keys) in KEYPAD.iter().enumerate() {
+            for (column, &key) in keys.iter().enumerate() {
+                let x = left + column * cell.0;
+                let y = top + font::LINE_HEIGHT + 1 + row * cell.1;
+
+                let brightness = if cpu.is_key_down(key) {
+                    font::fill_rect(panels, x, y, cell.0 - 1, cell.1 - 1, PIXEL_ON);
+                    PIXEL_OFF
+                } else {
+                    PIXEL_ON
+                };
+
+                font::draw_text(panels, x + 3, y + 2, &format!("{:X}", key), brightness);
+            }
+        }
+    }
+}
+
+// The row shown first when the memory panel isn't scrolled: the one above
+// I's.
+fn memory_home(cpu: &Cpu) -> isize {
+    (cpu.i() as usize % MEMORY_SIZE / BYTES_PER_ROW) as isize - 1
+}
+
+// How many bytes from I the instruction at PC reads or writes.
+fn i_length(cpu: &Cpu) -> usize {
+    let instruction = cpu.mmu().read_word(cpu.pc() as usize).unwrap_or(0);
+    let x = (instruction as usize >> 8) & 0xF;
+
+    match instruction & 0xF0FF {
+        0xF033          => 3,
+        0xF055 | 0xF065 => x + 1,
+        _ if instruction & 0xF000 == 0xD000 => (instruction as usize & 0xF).max(1),
+        _ => 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpu::Cpu;
+    use frontend::Command;
+    use mmu::{Mmu, MEMORY_SIZE};
+
+    use super::*;
+
+    #[test]
+    fn scrolls_memory_only_as_far_as_there_is() {
+        let cpu = Cpu::new(Mmu::new());
+        let mut debugger = Debugger::new(10);
+
+        let last_row = MEMORY_SIZE / BYTES_PER_ROW - debugger.memory_rows();
+
+        for _ in 0..1000 {
+            debugger.command(Command::MemoryDown, &cpu);
+        }
+
+        assert_eq!(debugger.first_memory_row(&cpu), last_row);
+
+        debugger.command(Command::MemoryUp, &cpu);
+
+        assert_eq!(debugger.first_memory_row(&cpu), last_row - debugger.memory_rows());
+
+        for _ in 0..1000 {
+            debugger.command(Command::MemoryUp, &cpu);
+        }
+
+        debugger.command(Command::MemoryDown, &cpu);
+
+        assert_eq!(debugger.first_memory_row(&cpu), debugger.memory_rows());
+    }
+
+    #[test]
+    fn keeps_i_past_the_end_within_memory() {
+        let mut cpu = Cpu::new(Mmu::new());
+        let debugger = Debugger::new(10);
+
+        cpu.set_i(0xFFFF);
+
+        let mut panels = Framebuffer::new(panel_size(10).0, panel_size(10).1);
+
+        debugger.draw_memory(&cpu, &mut panels);
+
+        // I wraps round to 0xFFF, which is on the last row, and highlighted.
+        assert_eq!(debugger.first_memory_row(&cpu), MEMORY_SIZE / BYTES_PER_ROW - debugger.memory_rows());
+        assert!(panels.pixels().contains(&HIGHLIGHT));
+    }
+
+    #[test]
+    fn fits_the_memory_and_keypad_at_every_scale() {
+        for scale in 1..21 {
+            let (width, height) = panel_size(scale);
+            let debugger = Debugger::new(scale);
+
+            assert!(width >= debugger.side() + SIDE_WIDTH - GAP, "scale {}", scale);
+            assert!(width >= keypad_left() + 4 * KEY_CELL.0, "scale {}", scale);
+            assert!(height >= debugger.game.1 + GAP + font::LINE_HEIGHT + 1 + 4 * KEY_CELL.1, "scale {}", scale);
+        }
+    }
+
+    #[test]
+    fn draws_the_last_key_at_scale_1() {
+        let mut cpu = Cpu::new(Mmu::new());
+        let debugger = Debugger::new(1);
+
+        let mut keys = [false; 16];
+        keys[0xF] = true;
+        cpu.set_keys(&keys);
+
+        let (width, height) = panel_size(1);
+        let mut panels = Framebuffer::new(width, height);
+
+        debugger.draw_keypad(&cpu, &mut panels);
+
+        // F is bottom right, drawn inverted.
+        let x = keypad_left() + 3 * KEY_CELL.0;
+        let y = debugger.game.1 + GAP + font::LINE_HEIGHT + 1 + 3 * KEY_CELL.1;
+
+        assert_eq!(panels.pixel(x, y), PIXEL_ON);
+        assert_eq!(panels.pixel(x + KEY_CELL.0 - 2, y), PIXEL_ON);
+    }
+}
@@ -0,0 +1,422 @@
+use std::vec::Vec;
+
+use mmu::{MAX_ROM_SIZE, ROM_START};
+use platform::Platform;
+use quirks::Quirks;
+
+// A guess at what an unknown ROM was written for, from its bytes alone.
+#[derive(Debug, Clone)]
+pub struct Detection {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    // How sure the guess is, from 0 to 1.
+    pub confidence: f32,
+    // The evidence, a line each, for showing to the user.
+    pub reasons: Vec<String>
+}
+
+// Instructions only SUPER-CHIP and XO-CHIP understand, with what they do.
+const SUPER_CHIP_PATTERNS: [(u16, u16, &'static str); 9] = [
+    (0xFFFF, 0x00FB, "00FB (scroll right)"),
+    (0xFFFF, 0x00FC, "00FC (scroll left)"),
+    (0xFFFF, 0x00FD, "00FD (exit)"),
+    (0xFFFF, 0x00FE, "00FE (lores)"),
+    (0xFFFF, 0x00FF, "00FF (hires)"),
+    (0xFFF0, 0x00C0, "00Cn (scroll down)"),
+    (0xF0FF, 0xF030, "Fx30 (big font)"),
+    (0xF0FF, 0xF075, "Fx75 (save flags)"),
+    (0xF0FF, 0xF085, "Fx85 (load flags)")
+];
+
+const XO_CHIP_PATTERNS: [(u16, u16, &'static str); 6] = [
+    (0xFFF0, 0x00D0, "00Dn (scroll up)"),
+    (0xF00F, 0x5002, "5xy2 (save range)"),
+    (0xF00F, 0x5003, "5xy3 (load range)"),
+    (0xFFFF, 0xF000, "F000 nnnn (long I)"),
+    (0xF0FF, 0xF001, "Fn01 (select planes)"),
+    (0xFFFF, 0xF002, "F002 (load audio)")
+];
+
+// Guesses at least this sure are worth acting on.
+pub const CONFIDENT: f32 = 0.6;
+
+// How far past an Fx55 or Fx65 to look for what the program does with I.
+const LOOKAHEAD: usize = 8;
+
+pub fn detect(rom: &[u8]) -> Detection {
+    let code = trace(rom);
+
+    let instructions: Vec<(usize, u16)> = code.iter().enumerate()
+        .filter(|&(_, is_code)| *is_code)
+        .map(|(offset, _)| (offset, word_at(rom, offset)))
+        .collect();
+
+    let mut reasons = Vec::new();
+
+    let super_chip = matches(&instructions, &SUPER_CHIP_PATTERNS);
+    let xo_chip    = matches(&instructions, &XO_CHIP_PATTERNS);
+    let too_large  = rom.len() > MAX_ROM_SIZE;
+
+    if !xo_chip.is_empty() {
+        reasons.push(format!("uses XO-CHIP instructions: {}", xo_chip.join(", ")));
+    }
+
+    if !super_chip.is_empty() {
+        reasons.push(format!("uses SUPER-CHIP instructions: {}", super_chip.join(", ")));
+    }
+
+    if too_large {
+        reasons.push(format!("is {} bytes, more than the {} a 4 KiB machine has room for",
+                             rom.len(), MAX_ROM_SIZE));
+    }
+
+    // Each distinct instruction adds to the evidence; a ROM too big for
+    // anything but XO-CHIP is strong evidence on its own.
+    let (platform, mut confidence) = if !xo_chip.is_empty() || too_large {
+        let size_evidence = if too_large { 0.3 } else { 0.0 };
+
+        (Platform::XoChip, 0.6 + 0.1 * xo_chip.len() as f32 + size_evidence)
+    } else if !super_chip.is_empty() {
+        (Platform::SuperChip, 0.6 + 0.1 * super_chip.len() as f32)
+    } else if instructions.len() < 16 {
+        reasons.push(String::from("has too little reachable code to be sure of anything"));
+
+        (Platform::Chip8, 0.4)
+    } else {
+        reasons.push(format!("has {} reachable instructions, all of them CHIP-8", instructions.len()));
+
+        (Platform::Chip8, 0.7)
+    };
+
+    let quirks = match platform {
+        // XO-CHIP went back to the VIP's shifts and loads, and wraps sprites.
+        Platform::XoChip => {
+            Quirks {
+                wrap_sprites: true,
+                display_wait: false,
+                shift_vy: true,
+                load_store_i: true
+            }
+        },
+        Platform::SuperChip => Quirks::new(),
+        Platform::Chip8 => {
+            let mut quirks = Quirks::new();
+
+            let (from_vy, in_place) = shift_votes(&instructions);
+            let (advancing, resetting) = load_store_votes(rom, &code);
+
+            if from_vy > 0 || in_place > 0 {
+                reasons.push(format!("shifts from another register {} and in place {}",
+                                     times(from_vy), times(in_place)));
+            }
+
+            if advancing > 0 || resetting > 0 {
+                reasons.push(format!("uses Fx55/Fx65 as if they advance I {} and as if they don't {}",
+                                     times(advancing), times(resetting)));
+            }
+
+            quirks.shift_vy     = from_vy > in_place;
+            quirks.load_store_i = advancing > resetting;
+
+            // Mixed signals make the whole guess less certain.
+            if from_vy > 0 && in_place > 0 {
+                confidence -= 0.1;
+            }
+
+            if advancing > 0 && resetting > 0 {
+                confidence -= 0.1;
+            }
+
+            quirks
+        }
+    };
+
+    Detection {
+        platform: platform,
+        quirks: quirks,
+        confidence: confidence.max(0.0).min(0.95),
+        reasons: reasons
+    }
+}
+
+// Follows the program from its entry point through jumps, calls and skips,
+// marking the offsets into `rom` where instructions start. Computed jumps
+// (Bnnn) can't be followed, so code only reached through them is missed.
+pub fn trace(rom: &[u8]) -> Vec<bool> {
+    trace_from(rom, &[ROM_START])
+}
+
+// The same, following the program from each of `entries`, e.g. addresses
+// seen running that a trace from the entry point alone can't reach.
+pub fn trace_from(rom: &[u8], entries: &[usize]) -> Vec<bool> {
+    let mut code = vec![false; rom.len()];
+    let mut pending = entries.to_vec();
+
+    while let Some(address) = pending.pop() {
+        if address < ROM_START || address - ROM_START + 1 >= rom.len() {
+            continue;
+        }
+
+        let offset = address - ROM_START;
+
+        if code[offset] {
+            continue;
+        }
+
+        code[offset] = true;
+
+        let instruction = word_at(rom, offset);
+        let next = address + if instruction == 0xF000 { 4 } else { 2 };
+
+        match instruction {
+            0x00EE | 0x00FD => { },
+            0x1000 ... 0x1FFF => pending.push((instruction & 0x0FFF) as usize),
+            0x2000 ... 0x2FFF => {
+                pending.push((instruction & 0x0FFF) as usize);
+                pending.push(next);
+            },
+            0xB000 ... 0xBFFF => { },
+            0x3000 ... 0x5FFF | 0x9000 ... 0x9FFF => {
+                pending.push(next);
+                pending.push(next + 2);
+            },
+            _ if instruction & 0xF0FF == 0xE09E || instruction & 0xF0FF == 0xE0A1 => {
+                pending.push(next);
+                pending.push(next + 2);
+            },
+            _ => pending.push(next)
+        }
+    }
+
+    code
+}
+
+fn word_at(rom: &[u8], offset: usize) -> u16 {
+    (rom[offset] as u16) << 8 | rom[offset + 1] as u16
+}
+
+// The descriptions of the patterns that turn up, with how often.
+fn matches(instructions: &[(usize, u16)], patterns: &[(u16, u16, &'static str)]) -> Vec<String> {
+    patterns.iter().filter_map(|&(mask, value, description)| {
+        let count = instructions.iter()
+            .filter(|&&(_, instruction)| instruction & mask == value)
+            .count();
+
+        match count {
+            0 => None,
+            1 => Some(description.to_string()),
+            _ => Some(format!("{} x{}", description, count))
+        }
+    }).collect()
+}
+
+// VIP programs shift VY into VX, so 8xy6 with distinct registers hints at
+// them; CHIP-48 programs shift in place and usually leave Y as 0.
+fn shift_votes(instructions: &[(usize, u16)]) -> (usize, usize) {
+    let mut from_vy  = 0;
+    let mut in_place = 0;
+
+    for &(_, instruction) in instructions {
+        let is_shift = instruction & 0xF00F == 0x8006 || instruction & 0xF00F == 0x800E;
+        let x = (instruction & 0x0F00) >> 8;
+        let y = (instruction & 0x00F0) >> 4;
+
+        if !is_shift || x == y {
+            continue;
+        }
+
+        if y == 0 {
+            in_place += 1;
+        } else {
+            from_vy += 1;
+        }
+    }
+
+    (from_vy, in_place)
+}
+
+// Two loads or two stores in a row without setting I in between count on I
+// advancing past the first, as on the VIP. A load followed by a store back to
+// the same place, or adding to I with Fx1E, counts on it staying put.
+fn load_store_votes(rom: &[u8], code: &[bool]) -> (usize, usize) {
+    let mut advancing = 0;
+    let mut resetting = 0;
+
+    let is_load_store = |instruction: u16| {
+        instruction & 0xF0FF == 0xF055 || instruction & 0xF0FF == 0xF065
+    };
+
+    for offset in 0..code.len() {
+        if !code[offset] || !is_load_store(word_at(rom, offset)) {
+            continue;
+        }
+
+        let kind = word_at(rom, offset) & 0x00FF;
+
+        for step in 1..LOOKAHEAD + 1 {
+            let next = offset + step * 2;
+
+            if next + 1 >= rom.len() || !code[next] {
+                break;
+            }
+
+            let instruction = word_at(rom, next);
+
+            if is_load_store(instruction) {
+                if instruction & 0x00FF == kind {
+                    advancing += 1;
+                } else {
+                    resetting += 1;
+                }
+
+                break;
+            }
+
+            if instruction & 0xF0FF == 0xF01E {
+                resetting += 1;
+                break;
+            }
+
+            // Anything else that sets I, or leaves this straight line of
+            // code, ends the search.
+            match instruction & 0xF000 {
+                0xA000 | 0x1000 | 0x2000 | 0xB000 => break,
+                _ if instruction == 0x00EE || instruction & 0xF0FF == 0xF029 => break,
+                _ => { }
+            }
+        }
+    }
+
+    (advancing, resetting)
+}
+
+fn times(count: usize) -> String {
+    if count == 1 {
+        String::from("once")
+    } else {
+        format!("{} times", count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use mmu::MAX_ROM_SIZE;
+    use platform::Platform;
+
+    use super::*;
+
+    // A ROM from instructions, each followed by the next.
+    fn assemble(instructions: &[u16]) -> Vec<u8> {
+        instructions.iter().flat_map(|&instruction| vec![(instruction >> 8) as u8, instruction as u8]).collect()
+    }
+
+    fn has_reason(detection: &Detection, reason: &str) -> bool {
+        detection.reasons.iter().any(|r| r.contains(reason))
+    }
+
+    #[test]
+    fn spots_super_chip_instructions() {
+        // hires, then loop forever.
+        let detection = detect(&assemble(&[0x00FF, 0x1202]));
+
+        assert_eq!(detection.platform, Platform::SuperChip);
+        assert!(has_reason(&detection, "uses SUPER-CHIP instructions: 00FF (hires)"));
+        assert!(detection.confidence >= CONFIDENT);
+    }
+
+    #[test]
+    fn steps_over_the_address_after_f000() {
+        // I := 0x1234, loop. Read as an instruction, 1234 would be a jump
+        // somewhere else entirely.
+        let rom = assemble(&[0xF000, 0x1234, 0x1204]);
+
+        assert_eq!(trace(&rom), [true, false, false, false, true, false]);
+
+        let detection = detect(&rom);
+
+        assert_eq!(detection.platform, Platform::XoChip);
+        assert!(has_reason(&detection, "uses XO-CHIP instructions: F000 nnnn (long I)"));
+        assert!(detection.quirks.shift_vy && detection.quirks.load_store_i && detection.quirks.wrap_sprites);
+    }
+
+    #[test]
+    fn takes_a_rom_too_big_to_load_for_xo_chip() {
+        let detection = detect(&vec![0; MAX_ROM_SIZE + 2]);
+
+        assert_eq!(detection.platform, Platform::XoChip);
+        assert!(has_reason(&detection, &format!("is {} bytes", MAX_ROM_SIZE + 2)));
+        assert!(detection.confidence >= CONFIDENT);
+    }
+
+    #[test]
+    fn ignores_code_it_cant_reach() {
+        // Jump over a SUPER-CHIP instruction that never runs.
+        let detection = detect(&assemble(&[0x1204, 0x00FF, 0x1204]));
+
+        assert_eq!(detection.platform, Platform::Chip8);
+    }
+
+    #[test]
+    fn shifts_from_vy_when_y_is_given() {
+        // V1 := V2 >> 1, loop.
+        let detection = detect(&assemble(&[0x8126, 0x1202]));
+
+        assert_eq!(detection.platform, Platform::Chip8);
+        assert!(detection.quirks.shift_vy);
+        assert!(has_reason(&detection, "shifts from another register once and in place 0 times"));
+    }
+
+    #[test]
+    fn shifts_in_place_when_y_is_left_out() {
+        // V1 >>= 1 and V1 <<= 1, with Y as 0, loop.
+        let detection = detect(&assemble(&[0x8106, 0x810E, 0x1204]));
+
+        assert!(!detection.quirks.shift_vy);
+        assert!(has_reason(&detection, "shifts from another register 0 times and in place 2 times"));
+    }
+
+    #[test]
+    fn counts_mixed_shifts_against_the_guess() {
+        let sure   = detect(&assemble(&[0x8126, 0x8136, 0x1204]));
+        let unsure = detect(&assemble(&[0x8126, 0x8106, 0x1204]));
+
+        assert!(unsure.confidence < sure.confidence);
+    }
+
+    #[test]
+    fn expects_i_to_advance_after_back_to_back_stores() {
+        // I := 300, store V0-V1 twice, loop.
+        let detection = detect(&assemble(&[0xA300, 0xF155, 0x6005, 0xF155, 0x1208]));
+
+        assert!(detection.quirks.load_store_i);
+        assert!(has_reason(&detection, "as if they advance I once and as if they don't 0 times"));
+    }
+
+    #[test]
+    fn expects_i_to_stay_put_after_a_load_stored_back() {
+        // I := 300, load V0-V1, V0 += 1, store them back, loop.
+        let detection = detect(&assemble(&[0xA300, 0xF165, 0x7001, 0xF155, 0x1208]));
+
+        assert!(!detection.quirks.load_store_i);
+        assert!(has_reason(&detection, "as if they advance I 0 times and as if they don't once"));
+    }
+
+    #[test]
+    fn expects_i_to_stay_put_when_added_to() {
+        // I := 300, load V0-V1, I += V2, loop.
+        let detection = detect(&assemble(&[0xA300, 0xF165, 0xF21E, 0x1206]));
+
+        assert!(!detection.quirks.load_store_i);
+        assert!(has_reason(&detection, "as if they don't once"));
+    }
+
+    #[test]
+    fn stops_looking_ahead_where_i_is_set() {
+        // I := 300, store, I := 310, store: says nothing about I.
+        let detection = detect(&assemble(&[0xA300, 0xF155, 0xA310, 0xF155, 0x1208]));
+
+        assert!(!has_reason(&detection, "Fx55/Fx65"));
+    }
+}
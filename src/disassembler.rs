@@ -0,0 +1,126 @@
+// Turns instructions into assembly in the mnemonics of Cowgod's CHIP-8
+// technical reference, e.g. "DRW V0, V1, 5". Words the CPU wouldn't run come
+// out as data, e.g. "DW 0x5AB3".
+pub fn disassemble(instruction: u16) -> String {
+    let x   = (instruction >> 8) & 0xF;
+    let y   = (instruction >> 4) & 0xF;
+    let n   = instruction & 0xF;
+    let kk  = instruction & 0xFF;
+    let nnn = instruction & 0xFFF;
+
+    match instruction {
+        0x00E0 => String::from("CLS"),
+        0x00EE => String::from("RET"),
+        0x1000 ... 0x1FFF => format!("JP {:#05X}", nnn),
+        0x2000 ... 0x2FFF => format!("CALL {:#05X}", nnn),
+        0x3000 ... 0x3FFF => format!("SE V{:X}, {:#04X}", x, kk),
+        0x4000 ... 0x4FFF => format!("SNE V{:X}, {:#04X}", x, kk),
+        0x5000 ... 0x5FFF => format!("SE V{:X}, V{:X}", x, y),
+        0x6000 ... 0x6FFF => format!("LD V{:X}, {:#04X}", x, kk),
+        0x7000 ... 0x7FFF => format!("ADD V{:X}, {:#04X}", x, kk),
+        0x8000 ... 0x8FFF => {
+            let mnemonic = match n {
+                0x0 => "LD",
+                0x1 => "OR",
+                0x2 => "AND",
+                0x3 => "XOR",
+                0x4 => "ADD",
+                0x5 => "SUB",
+                0x6 => "SHR",
+                0x7 => "SUBN",
+                0xE => "SHL",
+                _   => return data(instruction)
+            };
+
+            format!("{} V{:X}, V{:X}", mnemonic, x, y)
+        },
+        0x9000 ... 0x9FFF => format!("SNE V{:X}, V{:X}", x, y),
+        0xA000 ... 0xAFFF => format!("LD I, {:#05X}", nnn),
+        0xB000 ... 0xBFFF => format!("JP V0, {:#05X}", nnn),
+        0xC000 ... 0xCFFF => format!("RND V{:X}, {:#04X}", x, kk),
+        0xD000 ... 0xDFFF => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE000 ... 0xEFFF => {
+            match kk {
+                0x9E => format!("SKP V{:X}", x),
+                0xA1 => format!("SKNP V{:X}", x),
+                _    => data(instruction)
+            }
+        },
+        0xF000 ... 0xFFFF => {
+            match kk {
+                0x07 => format!("LD V{:X}, DT", x),
+                0x0A => format!("LD V{:X}, K", x),
+                0x15 => format!("LD DT, V{:X}", x),
+                0x18 => format!("LD ST, V{:X}", x),
+                0x1E => format!("ADD I, V{:X}", x),
+                0x29 => format!("LD F, V{:X}", x),
+                0x33 => format!("LD B, V{:X}", x),
+                0x55 => format!("LD [I], V{:X}", x),
+                0x65 => format!("LD V{:X}, [I]", x),
+                _    => data(instruction)
+            }
+        },
+        _ => data(instruction)
+    }
+}
+
+fn data(instruction: u16) -> String {
+    format!("DW {:#06X}", instruction)
+}
+
+// The general form of an instruction, e.g. "ADD Vx, Vy", for counting
+// instructions by kind.
+pub fn form(instruction: u16) -> &'static str {
+    match instruction {
+        0x00E0 => "CLS",
+        0x00EE => "RET",
+        0x1000 ... 0x1FFF => "JP addr",
+        0x2000 ... 0x2FFF => "CALL addr",
+        0x3000 ... 0x3FFF => "SE Vx, byte",
+        0x4000 ... 0x4FFF => "SNE Vx, byte",
+        0x5000 ... 0x5FFF => "SE Vx, Vy",
+        0x6000 ... 0x6FFF => "LD Vx, byte",
+        0x7000 ... 0x7FFF => "ADD Vx, byte",
+        0x8000 ... 0x8FFF => {
+            match instruction & 0xF {
+                0x0 => "LD Vx, Vy",
+                0x1 => "OR Vx, Vy",
+                0x2 => "AND Vx, Vy",
+                0x3 => "XOR Vx, Vy",
+                0x4 => "ADD Vx, Vy",
+                0x5 => "SUB Vx, Vy",
+                0x6 => "SHR Vx, Vy",
+                0x7 => "SUBN Vx, Vy",
+                0xE => "SHL Vx, Vy",
+                _   => "DW"
+            }
+        },
+        0x9000 ... 0x9FFF => "SNE Vx, Vy",
+        0xA000 ... 0xAFFF => "LD I, addr",
+        0xB000 ... 0xBFFF => "JP V0, addr",
+        0xC000 ... 0xCFFF => "RND Vx, byte",
+        0xD000 ... 0xDFFF => "DRW Vx, Vy, n",
+        0xE000 ... 0xEFFF => {
+            match instruction & 0xFF {
+                0x9E => "SKP Vx",
+                0xA1 => "SKNP Vx",
+                _    => "DW"
+            }
+        },
+        0xF000 ... 0xFFFF => {
+            match instruction & 0xFF {
+                0x07 => "LD Vx, DT",
+                0x0A => "LD Vx, K",
+                0x15 => "LD DT, Vx",
+                0x18 => "LD ST, Vx",
+                0x1E => "ADD I, Vx",
+                0x29 => "LD F, Vx",
+                0x33 => "LD B, Vx",
+                0x55 => "LD [I], Vx",
+                0x65 => "LD Vx, [I]",
+                _    => "DW"
+            }
+        },
+        _ => "DW"
+    }
+}
@@ -0,0 +1,679 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::vec::Vec;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cheats::Cheats;
+use coverage::Coverage;
+use cpu::Cpu;
+use debugger::Debugger;
+use error::Error;
+use gdb::{Action, GdbStub};
+use frontend::{Command, Frontend};
+use mmu::ROM_START;
+use overlay::Overlay;
+use phosphor::Phosphor;
+use profiler::Profiler;
+use recording::Recorder;
+use screenshot::Screenshots;
+use timing::{Scheduler, Timing, FRAMES_PER_SECOND};
+
+// Frames run for each one presented while fast forwarding.
+const FAST_FORWARD_FRAMES: u32 = 4;
+
+// The machine plus everything that happens to a frame between the CPU and a
+// frontend.
+pub struct Emulator {
+    cpu: Cpu,
+    scheduler: Scheduler,
+    phosphor: Option<Phosphor>,
+    screenshots: Option<Screenshots>,
+    screenshot_at: Option<u64>,
+    recorder: Option<Recorder>,
+    rom: Option<Vec<u8>>,
+    overlay: Overlay,
+    overlay_scale: Option<usize>,
+    debugger: Option<Debugger>,
+    gdb: Option<GdbStub>,
+    cheats: Option<Cheats>,
+    profile_path: Option<String>,
+    coverage_path: Option<String>,
+    messages_shown: bool,
+    unshown: Vec<String>,
+    paused: bool,
+    frames: u64,
+    stats_started: Instant,
+    stats_frames: u32,
+    stats_instructions: u64
+}
+
+impl Emulator {
+    pub fn new(cpu: Cpu, scheduler: Scheduler) -> Emulator {
+        Emulator {
+            cpu: cpu,
+            scheduler: scheduler,
+            phosphor: None,
+            screenshots: None,
+            screenshot_at: None,
+            recorder: None,
+            rom: None,
+            overlay: Overlay::new(),
+            overlay_scale: None,
+            debugger: None,
+            gdb: None,
+            cheats: None,
+            profile_path: None,
+            coverage_path: None,
+            messages_shown: false,
+            unshown: Vec::new(),
+            paused: false,
+            frames: 0,
+            stats_started: Instant::now(),
+            stats_frames: 0,
+            stats_instructions: 0
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    // The ROM to load again on a hard reset. The Cpu's memory should already
+    // hold it.
+    pub fn set_rom(&mut self, rom: Vec<u8>) {
+        self.rom = Some(rom);
+    }
+
+    // The messages the frontend couldn't show, such as what was saved when
+    // the program ended, oldest first. Print them once the frontend has
+    // closed and the console is back to normal.
+    pub fn take_messages(&mut self) -> Vec<String> {
+        mem::replace(&mut self.unshown, Vec::new())
+    }
+
+    pub fn overlay_mut(&mut self) -> &mut Overlay {
+        &mut self.overlay
+    }
+
+    // Draws the overlay into screenshots and recordings, blowing frames up
+    // to `scale` to make room for it as the SDL frontend does. Their
+    // renderer shouldn't scale frames again, so should have a scale of 1.
+    pub fn set_overlay_in_captures(&mut self, scale: Option<usize>) {
+        self.overlay_scale = scale;
+    }
+
+    // Debugging starts paused, so there's a chance to set breakpoints.
+    pub fn set_debugger(&mut self, debugger: Option<Debugger>) {
+        if debugger.is_some() {
+            self.set_paused(true);
+        }
+
+        self.debugger = debugger;
+    }
+
+    pub fn debugger(&self) -> Option<&Debugger> {
+        self.debugger.as_ref()
+    }
+
+    // Lets a GDB client drive the program. Its breakpoints are the
+    // debugger's, so there has to be one.
+    pub fn set_gdb(&mut self, gdb: Option<GdbStub>) {
+        self.gdb = gdb;
+    }
+
+    // Frozen values are put back before every frame.
+    pub fn set_cheats(&mut self, cheats: Option<Cheats>) {
+        self.cheats = cheats;
+    }
+
+    // Profiles the program as it runs, writing a report to `path` when it
+    // ends.
+    pub fn set_profile_report(&mut self, path: Option<String>) {
+        self.cpu.set_profiler(path.as_ref().map(|_| Profiler::new()));
+        self.profile_path = path;
+    }
+
+    // Records what the program runs, reads and writes, saving it to `path`
+    // when it ends: a heatmap for a .png, an annotated listing otherwise.
+    pub fn set_coverage_report(&mut self, path: Option<String>) {
+        self.cpu.set_coverage(path.as_ref().map(|_| Coverage::new()));
+        self.coverage_path = path;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_phosphor(&mut self, phosphor: Option<Phosphor>) {
+        self.phosphor = phosphor;
+    }
+
+    pub fn set_screenshots(&mut self, screenshots: Option<Screenshots>) {
+        self.screenshots = screenshots;
+    }
+
+    // Takes a screenshot once the given frame has been presented.
+    pub fn set_screenshot_at(&mut self, frame: Option<u64>) {
+        self.screenshot_at = frame;
+    }
+
+    // Records every frame presented from now on.
+    pub fn start_recording(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    // Finishes the recording, if there is one, and returns where it went.
+    pub fn stop_recording(&mut self) -> io::Result<Option<String>> {
+        match self.recorder.take() {
+            Some(mut recorder) => {
+                try!(recorder.finish());
+                Ok(Some(recorder.path().to_string()))
+            },
+            None => Ok(None)
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    // How many frames have been presented so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Runs the machine one 60 Hz frame at a time until the frontend asks to
+    // quit or go back, or the program faults.
+    pub fn run<F: Frontend + ?Sized>(&mut self, frontend: &mut F) -> Result<(), Error> {
+        let frame = Duration::new(0, 1000000000 / FRAMES_PER_SECOND);
+
+        let mut keys = [false; 16];
+        let mut beeping = false;
+        let mut result = Ok(());
+
+        self.overlay.set_profile(&self.cpu.quirks().to_string());
+        self.messages_shown = frontend.shows_messages();
+
+        while !frontend.quit_requested() {
+            let started = Instant::now();
+
+            frontend.poll_input(&mut keys);
+            self.cpu.set_keys(&keys);
+
+            let commands = frontend.commands();
+
+            if commands.contains(&Command::Back) {
+                break;
+            }
+
+            let fast_forward = frontend.is_held(Command::FastForward) && !self.paused;
+
+            let mut frames = if self.paused { 0 } else if fast_forward { FAST_FORWARD_FRAMES } else { 1 };
+
+            // Whether the program stopped at a breakpoint or after a step,
+            // rather than being paused.
+            let mut trapped = false;
+
+            for command in commands.iter() {
+                match *command {
+                    Command::Pause           => self.toggle_pause(),
+                    Command::FrameAdvance    => if self.paused { frames += 1 },
+                    Command::HardReset       => self.hard_reset(),
+                    Command::SoftReset       => self.soft_reset(),
+                    Command::SpeedUp         => self.change_speed(true),
+                    Command::SpeedDown       => self.change_speed(false),
+                    Command::ToggleRecording => self.toggle_recording(),
+                    Command::ToggleControls  => self.overlay.toggle_controls(),
+                    Command::ToggleOverlay   => self.toggle_overlay(),
+                    Command::NewSearch       |
+                    Command::SearchChanged   |
+                    Command::SearchUnchanged |
+                    Command::SearchIncreased |
+                    Command::SearchDecreased |
+                    Command::Freeze          => self.cheat_command(*command),
+                    Command::Step            => {
+                        match self.step() {
+                            Ok(())  => trapped = true,
+                            Err(e)  => result = Err(e)
+                        }
+                    },
+                    _ => self.debugger_command(*command)
+                }
+            }
+
+            match self.serve_gdb() {
+                Ok(stepped) => trapped |= stepped,
+                Err(e)      => result = Err(e)
+            }
+
+            if result.is_err() {
+                break;
+            }
+
+            self.overlay.set_fast_forward(fast_forward);
+
+            // Frames advanced while paused start where the last one stopped,
+            // which may be on a breakpoint.
+            if self.paused && frames > 0 {
+                self.resume();
+            }
+
+            let mut frames_run = 0;
+
+            for _ in 0..frames {
+                match self.run_frame() {
+                    Ok(true)  => {
+                        frames_run += 1;
+                        trapped = true;
+                        break;
+                    },
+                    Ok(false) => frames_run += 1,
+                    Err(e)    => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+
+            if result.is_err() {
+                break;
+            }
+
+            if self.paused {
+                if let Some(ref mut gdb) = self.gdb {
+                    gdb.report_stop(trapped);
+                }
+            }
+
+            // The buzzer stays quiet while paused.
+            let sounding = self.cpu.sound_timer() > 0 && !self.paused;
+
+            if sounding != beeping {
+                if sounding {
+                    frontend.play_tone();
+                } else {
+                    frontend.stop_tone();
+                }
+
+                beeping = sounding;
+            }
+
+            frontend.show_status(&format!("PC: {:#05X}", self.cpu.pc()));
+
+            self.update_stats();
+            self.overlay.tick();
+            frontend.show_overlay(&self.overlay);
+
+            if let Some(ref debugger) = self.debugger {
+                frontend.show_debugger(debugger, &self.cpu);
+            }
+
+            self.present(frontend, commands.contains(&Command::Screenshot), frames_run);
+
+            let elapsed = started.elapsed();
+
+            if frontend.realtime() && elapsed < frame {
+                thread::sleep(frame - elapsed);
+            }
+        }
+
+        if beeping {
+            frontend.stop_tone();
+        }
+
+        // The frontend won't draw again, so what's saved below has to wait
+        // for it to close.
+        self.messages_shown = false;
+
+        // After a fault, keep what was recorded up to it; it's likely to be
+        // the interesting part.
+        self.finish_recording();
+        self.write_profile();
+        self.write_coverage();
+
+        result
+    }
+
+    // Runs a frame, stopping short at any breakpoint. Returns whether one was
+    // hit, after pausing on it.
+    fn run_frame(&mut self) -> Result<bool, Error> {
+        if let Some(ref cheats) = self.cheats {
+            cheats.apply(&mut self.cpu);
+        }
+
+        let hit = match self.debugger {
+            Some(ref mut debugger) if debugger.has_breakpoints() => {
+                try!(self.scheduler.run_frame_until(&mut self.cpu, |cpu| debugger.should_break(cpu.pc())))
+            },
+            _ => {
+                try!(self.scheduler.run_frame(&mut self.cpu));
+                false
+            }
+        };
+
+        if let Some(ref mut debugger) = self.debugger {
+            debugger.follow_pc();
+        }
+
+        if hit {
+            self.set_paused(true);
+
+            let message = format!("Stopped at breakpoint {:#05X}", self.cpu.pc());
+            self.overlay.notify(&message);
+        }
+
+        Ok(hit)
+    }
+
+    // Runs a single instruction, pausing first if need be. A display wait
+    // has no instruction to run, so stepping through one ends the frame.
+    fn step(&mut self) -> Result<(), Error> {
+        self.set_paused(true);
+
+        if self.cpu.is_waiting_for_vblank() {
+            self.cpu.tick();
+        } else {
+            try!(self.cpu.step());
+        }
+
+        if let Some(ref mut debugger) = self.debugger {
+            debugger.follow_pc();
+        }
+
+        Ok(())
+    }
+
+    fn resume(&mut self) {
+        let pc = self.cpu.pc();
+
+        if let Some(ref mut debugger) = self.debugger {
+            debugger.resume(pc);
+        }
+    }
+
+    // Answers the GDB client and does what it asks. Returns whether that was
+    // a step.
+    fn serve_gdb(&mut self) -> Result<bool, Error> {
+        let action = match (self.gdb.as_mut(), self.debugger.as_mut()) {
+            (Some(gdb), Some(debugger)) => gdb.poll(&mut self.cpu, debugger),
+            _                           => None
+        };
+
+        match action {
+            Some(Action::Attach) => {
+                self.report("GDB attached");
+                self.set_paused(true);
+            },
+            Some(Action::Detach) => {
+                self.report("GDB detached");
+                self.set_paused(false);
+            },
+            Some(Action::Continue)  => self.set_paused(false),
+            Some(Action::Interrupt) => self.set_paused(true),
+            Some(Action::Step) => {
+                try!(self.step());
+                return Ok(true);
+            },
+            None => { }
+        }
+
+        Ok(false)
+    }
+
+    fn cheat_command(&mut self, command: Command) {
+        let message = match self.cheats {
+            Some(ref mut cheats) => cheats.command(command, &self.cpu),
+            None                 => None
+        };
+
+        if let Some(message) = message {
+            self.report(&message);
+        }
+    }
+
+    fn debugger_command(&mut self, command: Command) {
+        let message = match self.debugger {
+            Some(ref mut debugger) => debugger.command(command, &self.cpu),
+            None                   => None
+        };
+
+        if let Some(message) = message {
+            self.overlay.notify(&message);
+        }
+    }
+
+    // Shows a message in the overlay, keeping it for `take_messages` if the
+    // frontend can't show it.
+    fn report(&mut self, message: &str) {
+        if !self.messages_shown {
+            self.unshown.push(message.to_string());
+        }
+
+        self.overlay.notify(message);
+    }
+
+    fn toggle_overlay(&mut self) {
+        let visible = !self.overlay.is_visible();
+
+        self.overlay.set_visible(visible);
+    }
+
+    // Works out frames and instructions per second once a second.
+    fn update_stats(&mut self) {
+        self.stats_frames += 1;
+
+        let elapsed = self.stats_started.elapsed();
+
+        if elapsed.as_secs() < 1 {
+            return;
+        }
+
+        let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+
+        // A hard reset starts the count of instructions over.
+        let instructions = self.cpu.instructions().saturating_sub(self.stats_instructions);
+
+        self.overlay.set_stats((self.stats_frames as f64 / seconds).round() as u32,
+                               (instructions as f64 / seconds).round() as u64);
+
+        self.stats_started      = Instant::now();
+        self.stats_frames       = 0;
+        self.stats_instructions = self.cpu.instructions();
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        if self.paused != paused {
+            self.toggle_pause();
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.overlay.set_paused(self.paused);
+
+        if !self.paused {
+            self.resume();
+        }
+    }
+
+    // Power cycles the machine: fresh memory with the ROM loaded again, and
+    // the CPU as it was at power on. Quirks, timing and the memory policy
+    // are settings rather than state, so they stay.
+    fn hard_reset(&mut self) {
+        let rom = match self.rom {
+            Some(ref rom) => rom,
+            None          => return self.overlay.notify("No ROM to reload")
+        };
+
+        self.cpu.reset();
+        self.cpu.mmu_mut().reset();
+
+        match self.cpu.mmu_mut().load_rom(rom) {
+            Ok(()) => self.overlay.notify("Hard reset"),
+            Err(e) => self.overlay.notify(&format!("Couldn't reload the ROM: {}", e))
+        }
+
+        let timing = self.scheduler.timing();
+        self.scheduler.set_timing(timing);
+    }
+
+    // Jumps back to the start of the program, leaving memory, registers and
+    // the display as they are.
+    fn soft_reset(&mut self) {
+        self.cpu.set_pc(ROM_START as u16);
+        self.overlay.notify("Soft reset");
+    }
+
+    fn change_speed(&mut self, faster: bool) {
+        match self.scheduler.timing().step(faster) {
+            Some(timing) => {
+                self.scheduler.set_timing(timing);
+                self.overlay.notify(&format!("{} instructions per frame", timing.per_frame().unwrap()));
+            },
+            None => {
+                if self.scheduler.timing() == Timing::CosmacVip {
+                    self.overlay.notify("VIP timing runs at the VIP's speed");
+                } else if faster {
+                    self.overlay.notify("Already at full speed");
+                } else {
+                    self.overlay.notify("Already at the lowest speed");
+                }
+            }
+        }
+    }
+
+    // Hotkey recordings are GIFs named like screenshots.
+    fn toggle_recording(&mut self) {
+        if self.is_recording() {
+            self.finish_recording();
+            return;
+        }
+
+        if let Some(ref screenshots) = self.screenshots {
+            let path = screenshots.path(self.frames + 1, "gif");
+
+            let message = match Recorder::start(&path, screenshots.renderer()) {
+                Ok(recorder) => {
+                    self.recorder = Some(recorder);
+                    format!("Recording to {}", path)
+                },
+                Err(e) => format!("Couldn't start recording: {}", e)
+            };
+
+            self.report(&message);
+        }
+    }
+
+    fn finish_recording(&mut self) {
+        match self.stop_recording() {
+            Ok(Some(path)) => self.report(&format!("Saved recording to {}", path)),
+            Ok(None)       => { },
+            Err(e)         => self.report(&format!("Couldn't save recording: {}", e))
+        }
+    }
+
+    fn write_profile(&mut self) {
+        let written = match (self.profile_path.as_ref(), self.cpu.profiler()) {
+            (Some(path), Some(profiler)) => {
+                let mmu = self.cpu.mmu();
+
+                Some(File::create(path)
+                     .and_then(|file| {
+                         let mut out = BufWriter::new(file);
+
+                         try!(profiler.write_report(mmu, &mut out));
+
+                         out.flush()
+                     })
+                     .map(|_| format!("Saved a profile of {} instructions to {}", profiler.instructions(), path)))
+            },
+            _ => None
+        };
+
+        match written {
+            Some(Ok(message)) => self.report(&message),
+            Some(Err(e))      => self.report(&format!("Couldn't save profile: {}", e)),
+            None              => { }
+        }
+    }
+
+    fn write_coverage(&mut self) {
+        let saved = match (self.coverage_path.as_ref(), self.cpu.coverage()) {
+            (Some(path), Some(coverage)) => {
+                let rom = self.rom.as_ref().map_or(&[][..], |rom| &rom[..]);
+
+                Some(coverage.save(path, rom).map(|_| path.clone()))
+            },
+            _ => None
+        };
+
+        match saved {
+            Some(Ok(path)) => self.report(&format!("Saved coverage to {}", path)),
+            Some(Err(e))   => self.report(&format!("Couldn't save coverage: {}", e)),
+            None           => { }
+        }
+    }
+
+    // `frames_run` is how many frames ran since the last present, so a
+    // recording keeps to the game's time while fast forwarding. It counts as
+    // one while paused, so pauses are kept as they happened.
+    fn present<F: Frontend + ?Sized>(&mut self, frontend: &mut F, screenshot: bool, frames_run: u32) {
+        self.frames += 1;
+
+        let screenshot = screenshot || self.screenshot_at == Some(self.frames);
+        let mut messages = Vec::new();
+        let mut failed = false;
+
+        {
+            let frame = match self.phosphor {
+                Some(ref mut phosphor) => phosphor.apply(self.cpu.framebuffer()),
+                None                   => self.cpu.framebuffer()
+            };
+
+            frontend.present(frame);
+
+            let composite;
+
+            let captured = match self.overlay_scale {
+                Some(scale) if screenshot || self.recorder.is_some() => {
+                    composite = self.overlay.draw_over(frame, scale);
+                    &composite
+                },
+                _ => frame
+            };
+
+            if let Some(ref mut recorder) = self.recorder {
+                if let Err(e) = recorder.record(captured, frames_run.max(1) as u64) {
+                    messages.push(format!("Stopped recording: {}", e));
+                    failed = true;
+                }
+            }
+
+            // A failed screenshot isn't worth stopping the game for.
+            if screenshot {
+                if let Some(ref screenshots) = self.screenshots {
+                    match screenshots.save(captured, self.frames) {
+                        Ok(path) => messages.push(format!("Saved screenshot to {}", path)),
+                        Err(e)   => messages.push(format!("Couldn't save screenshot: {}", e))
+                    }
+                }
+            }
+        }
+
+        if failed {
+            self.recorder = None;
+        }
+
+        for message in messages.iter() {
+            self.report(message);
+        }
+
+        self.cpu.framebuffer_mut().mark_clean();
+    }
+}
@@ -0,0 +1,37 @@
+use std::error;
+use std::fmt;
+
+use mmu::MAX_ROM_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    StackOverflow,
+    StackUnderflow,
+    UnknownInstruction(u16),
+    MemoryOutOfBounds(usize),
+    RomTooLarge(usize)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::StackOverflow => {
+                write!(f, "Stack overflow: more than 16 nested calls")
+            },
+            Error::StackUnderflow => {
+                write!(f, "Stack underflow: return without a matching call")
+            },
+            Error::UnknownInstruction(instruction) => {
+                write!(f, "Missing instruction: {:#X} (HEX) / {} (DEC)", instruction, instruction)
+            },
+            Error::MemoryOutOfBounds(address) => {
+                write!(f, "Memory access out of bounds: {:#X}", address)
+            },
+            Error::RomTooLarge(size) => {
+                write!(f, "ROM is {} bytes, but at most {} bytes fit in memory", size, MAX_ROM_SIZE)
+            }
+        }
+    }
+}
+
+impl error::Error for Error { }
@@ -0,0 +1,131 @@
+use framebuffer::Framebuffer;
+
+// A 3x5 pixel font for drawing text into framebuffers: menus, the overlay
+// and debugger panels. Only upper case; lower case letters are drawn as
+// upper case and anything else missing as '?'.
+
+pub const GLYPH_WIDTH: usize  = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+
+// Glyphs plus a pixel of space after them.
+pub const ADVANCE: usize     = GLYPH_WIDTH + 1;
+pub const LINE_HEIGHT: usize = GLYPH_HEIGHT + 1;
+
+// One row per byte, top first, with the leftmost pixel in bit 2.
+const GLYPHS: [(char, [u8; 5]); 66] = [
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b110, 0b001, 0b010, 0b100, 0b111]),
+    ('3', [0b110, 0b001, 0b010, 0b001, 0b110]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b110, 0b001, 0b110]),
+    ('6', [0b011, 0b100, 0b110, 0b101, 0b010]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b010, 0b101, 0b010, 0b101, 0b010]),
+    ('9', [0b010, 0b101, 0b011, 0b001, 0b110]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    (';', [0b000, 0b010, 0b000, 0b010, 0b100]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+    ('[', [0b011, 0b010, 0b010, 0b010, 0b011]),
+    (']', [0b110, 0b010, 0b010, 0b010, 0b110]),
+    ('<', [0b001, 0b010, 0b100, 0b010, 0b001]),
+    ('>', [0b100, 0b010, 0b001, 0b010, 0b100]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+    ('\'', [0b010, 0b010, 0b000, 0b000, 0b000]),
+    ('"', [0b101, 0b101, 0b000, 0b000, 0b000]),
+    ('#', [0b101, 0b111, 0b101, 0b111, 0b101]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('*', [0b000, 0b101, 0b010, 0b101, 0b000]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('&', [0b010, 0b101, 0b010, 0b101, 0b011]),
+    ('@', [0b010, 0b101, 0b111, 0b100, 0b011]),
+    ('$', [0b011, 0b110, 0b010, 0b011, 0b110]),
+    ('|', [0b010, 0b010, 0b010, 0b010, 0b010]),
+    ('^', [0b010, 0b101, 0b000, 0b000, 0b000]),
+    ('~', [0b000, 0b011, 0b110, 0b000, 0b000]),
+    ('\u{2026}', [0b000, 0b000, 0b000, 0b000, 0b101])
+];
+
+pub fn glyph(c: char) -> [u8; 5] {
+    let c = c.to_ascii_uppercase();
+
+    GLYPHS.iter()
+        .find(|&&(glyph, _)| glyph == c)
+        .or_else(|| GLYPHS.iter().find(|&&(glyph, _)| glyph == '?'))
+        .map(|&(_, rows)| rows)
+        .unwrap()
+}
+
+// How wide `text` is when drawn, in pixels.
+pub fn width_of(text: &str) -> usize {
+    text.chars().count() * ADVANCE
+}
+
+// Draws `text` with its top left corner at (x, y), setting the glyphs' pixels
+// to `brightness` and leaving the rest alone. Anything past the edges is
+// clipped. Returns the x just past the last character.
+pub fn draw_text(frame: &mut Framebuffer, x: usize, y: usize, text: &str, brightness: u8) -> usize {
+    let mut left = x;
+
+    for c in text.chars() {
+        let rows = glyph(c);
+
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                let (px, py) = (left + column, y + row);
+
+                if bits & (0b100 >> column) != 0 && px < frame.width() && py < frame.height() {
+                    frame.set_pixel(px, py, brightness);
+                }
+            }
+        }
+
+        left += ADVANCE;
+    }
+
+    left
+}
+
+// Fills a rectangle, clipped to the frame.
+pub fn fill_rect(frame: &mut Framebuffer, x: usize, y: usize, width: usize, height: usize, brightness: u8) {
+    for py in y..(y + height).min(frame.height()) {
+        for px in x..(x + width).min(frame.width()) {
+            frame.set_pixel(px, py, brightness);
+        }
+    }
+}
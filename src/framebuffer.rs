@@ -0,0 +1,98 @@
+use std::vec::Vec;
+
+pub const WIDTH: usize  = 64;
+pub const HEIGHT: usize = 32;
+
+// Pixels are brightness values so that display filters can hand frontends
+// partly lit pixels. The CPU itself only ever draws them fully off or on.
+pub const PIXEL_OFF: u8 = 0x00;
+pub const PIXEL_ON: u8  = 0xFF;
+
+// The display, one byte per pixel in rows. Frontends borrow it each frame and
+// can skip redrawing while it isn't dirty.
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    dirty: bool,
+    frame: u64
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width: width,
+            height: height,
+            pixels: vec![PIXEL_OFF; width * height],
+            dirty: true,
+            frame: 0
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn is_lit(&self, x: usize, y: usize) -> bool {
+        self.pixel(x, y) != PIXEL_OFF
+    }
+
+    // Sets a pixel's brightness directly, for filters building their output.
+    pub fn set_pixel(&mut self, x: usize, y: usize, value: u8) {
+        let index = y * self.width + x;
+
+        if self.pixels[index] != value {
+            self.pixels[index] = value;
+            self.dirty = true;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = PIXEL_OFF;
+        }
+
+        self.dirty = true;
+    }
+
+    // XORs a pixel on, returning true if it was lit and got turned off.
+    pub fn toggle(&mut self, x: usize, y: usize) -> bool {
+        let index = y * self.width + x;
+        let was_lit = self.pixels[index] != PIXEL_OFF;
+
+        self.pixels[index] = if was_lit { PIXEL_OFF } else { PIXEL_ON };
+        self.dirty = true;
+
+        was_lit
+    }
+
+    // True if the pixels changed since the last `mark_clean`.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
+
+    // The number of 60 Hz frames that have ended since power on.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+}
@@ -0,0 +1,118 @@
+use std::vec::Vec;
+
+use cpu::Cpu;
+use debugger::Debugger;
+use framebuffer::Framebuffer;
+use overlay::Overlay;
+
+// Requests from the user that aren't CHIP-8 keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    // Leave the game: back to the ROM browser if it started the game, or
+    // out of the emulator if not.
+    Back,
+    // Menu navigation.
+    Up,
+    Down,
+    Select,
+    Screenshot,
+    ToggleRecording,
+    Pause,
+    // Runs a single frame while paused.
+    FrameAdvance,
+    // Reloads the ROM into fresh memory, as if switched off and on.
+    HardReset,
+    // Sends the program back to its first instruction and leaves the rest
+    // of the machine alone.
+    SoftReset,
+    SpeedUp,
+    SpeedDown,
+    // Runs several frames per frame for as long as it's held.
+    FastForward,
+    // Shows or hides the list of hotkeys.
+    ToggleControls,
+    ToggleOverlay,
+    // Debugger controls: run one instruction, set or clear a breakpoint at
+    // the disassembly cursor, and scroll the memory viewer.
+    Step,
+    ToggleBreakpoint,
+    MemoryUp,
+    MemoryDown,
+    // A mouse click on the debugger's panels, in panel pixels.
+    Click(usize, usize),
+    // Cheat search: start over with every address, keep those whose bytes
+    // changed in some way since the last step, and freeze the one left.
+    NewSearch,
+    SearchChanged,
+    SearchUnchanged,
+    SearchIncreased,
+    SearchDecreased,
+    Freeze
+}
+
+impl Command {
+    // Whether holding the key down should give the command again and again,
+    // as for scrolling, rather than once.
+    pub fn repeats(&self) -> bool {
+        match *self {
+            Command::Up | Command::Down | Command::FrameAdvance |
+            Command::SpeedUp | Command::SpeedDown | Command::Step |
+            Command::MemoryUp | Command::MemoryDown => true,
+            _ => false
+        }
+    }
+}
+
+// A place to show the display, read the keypad and play the buzzer. The run
+// loop in `emulator` drives any implementation the same way.
+pub trait Frontend {
+    // Called once per frame. The frame's dirty flag says whether anything was
+    // drawn since the last call, so unchanged frames needn't be redrawn.
+    fn present(&mut self, frame: &Framebuffer);
+
+    // Updates `keys` with which of the 16 hex keys are held down.
+    fn poll_input(&mut self, keys: &mut [bool; 16]);
+
+    // Called when the sound timer starts and stops running.
+    fn play_tone(&mut self);
+    fn stop_tone(&mut self);
+
+    // True once the user has asked to leave.
+    fn quit_requested(&self) -> bool;
+
+    // The commands given since the last call, oldest first. Called once per
+    // frame, after `poll_input`.
+    fn commands(&mut self) -> Vec<Command> {
+        Vec::new()
+    }
+
+    // Whether the key for a command is being held down right now, for
+    // commands like fast forward that last as long as the key.
+    fn is_held(&self, _command: Command) -> bool {
+        false
+    }
+
+    // Text to draw over the display, for frontends that can. Called once per
+    // frame before `present`.
+    fn show_overlay(&mut self, _overlay: &Overlay) { }
+
+    // Whether the notifications in the overlay passed to `show_overlay`
+    // reach the user. Frontends that can't show them have the run loop keep
+    // them for printing once the frontend has closed.
+    fn shows_messages(&self) -> bool {
+        false
+    }
+
+    // The debugger's panels, for frontends with room to show them beside the
+    // display. Called once per frame before `present` while debugging.
+    fn show_debugger(&mut self, _debugger: &Debugger, _cpu: &Cpu) { }
+
+    // A line describing the machine, for frontends with room to show it.
+    fn show_status(&mut self, _status: &str) { }
+
+    // Whether the run loop should hold frames to 60 per second. Frontends
+    // nobody is watching can run as fast as the host allows.
+    fn realtime(&self) -> bool {
+        true
+    }
+}
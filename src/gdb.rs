@@ -0,0 +1,808 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str;
+use std::vec::Vec;
+
+use cpu::Cpu;
+use debugger::Debugger;
+use mmu::MEMORY_SIZE;
+
+// The signals stop replies carry: SIGTRAP after a breakpoint or a step, and
+// SIGINT when the program was stopped any other way.
+const SIGINT: u8  = 2;
+const SIGTRAP: u8 = 5;
+
+// What the client sends to stop a running program, outside of any packet.
+const INTERRUPT: u8 = 0x03;
+
+// The largest packet we take, and so the most memory read at once.
+const MAX_PACKET: usize = 0x4000;
+
+// The most unread input kept: a whole packet and then some. A client that
+// sends more without a break is read as it's dealt with.
+const MAX_INPUT: usize = 2 * MAX_PACKET;
+
+// V0 to VF, then I, PC, SP, DT and ST, in the order `g` sends them.
+const REGISTERS: usize = 21;
+const REGISTER_NAMES: [&'static str; 5] = ["i", "pc", "sp", "dt", "st"];
+
+// What the program should do next, as asked for by the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Attach,
+    Continue,
+    Step,
+    Interrupt,
+    Detach
+}
+
+enum Input {
+    Packet(String),
+    Garbled,
+    Resend,
+    Interrupt
+}
+
+// A GDB remote serial protocol server, for debugging the program from GDB or
+// anything else that speaks the protocol. It serves one client at a time on
+// a local TCP port, without ever blocking the emulator: `poll` it once a
+// frame and carry out what it returns. Registers, memory and breakpoints are
+// dealt with as packets arrive; running and stopping are left to the caller,
+// who reports stops with `report_stop`.
+pub struct GdbStub {
+    listener: TcpListener,
+    connection: Option<TcpStream>,
+    input: Vec<u8>,
+    last_packet: Vec<u8>,
+    acks: bool,
+    running: bool
+}
+
+impl GdbStub {
+    // Listens on `port` of the loopback interface only; the protocol has no
+    // authentication, so it's no business of anyone else's.
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = try!(TcpListener::bind(("127.0.0.1", port)));
+
+        try!(listener.set_nonblocking(true));
+
+        Ok(GdbStub {
+            listener: listener,
+            connection: None,
+            input: Vec::new(),
+            last_packet: Vec::new(),
+            acks: true,
+            running: false
+        })
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.listener.local_addr().ok().map(|address| address.port())
+    }
+
+    // Takes a new client or reads from the current one, answering what can
+    // be answered straight away. A lost client is the same as one detaching.
+    pub fn poll(&mut self, cpu: &mut Cpu, debugger: &mut Debugger) -> Option<Action> {
+        if self.connection.is_none() {
+            return self.accept();
+        }
+
+        match self.serve(cpu, debugger) {
+            Ok(action) => action,
+            Err(_) => {
+                self.disconnect();
+                Some(Action::Detach)
+            }
+        }
+    }
+
+    // Tells a client waiting on a `c` or `s` that the program has stopped,
+    // at a breakpoint or after the step if `trapped`.
+    pub fn report_stop(&mut self, trapped: bool) {
+        if !self.running || self.connection.is_none() {
+            return;
+        }
+
+        self.running = false;
+
+        let signal = if trapped { SIGTRAP } else { SIGINT };
+
+        if self.send(&format!("S{:02x}", signal)).is_err() {
+            self.disconnect();
+        }
+    }
+
+    fn accept(&mut self) -> Option<Action> {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(_)          => return None
+        };
+
+        if stream.set_nonblocking(true).is_err() {
+            return None;
+        }
+
+        // Packets are small and each waits on an answer.
+        let _ = stream.set_nodelay(true);
+
+        self.connection = Some(stream);
+        self.input.clear();
+        self.last_packet.clear();
+        self.acks = true;
+        self.running = false;
+
+        Some(Action::Attach)
+    }
+
+    fn disconnect(&mut self) {
+        self.connection = None;
+        self.running = false;
+    }
+
+    fn serve(&mut self, cpu: &mut Cpu, debugger: &mut Debugger) -> io::Result<Option<Action>> {
+        try!(self.receive());
+
+        while let Some(input) = self.next_input() {
+            match input {
+                Input::Interrupt => return Ok(Some(Action::Interrupt)),
+                Input::Resend => {
+                    let packet = self.last_packet.clone();
+
+                    try!(self.write(&packet));
+                },
+                Input::Garbled => {
+                    if self.acks {
+                        try!(self.write(b"-"));
+                    }
+                },
+                Input::Packet(packet) => {
+                    if self.acks {
+                        try!(self.write(b"+"));
+                    }
+
+                    let action = try!(self.handle(&packet, cpu, debugger));
+
+                    // Anything after a resume is read once it has happened.
+                    if action.is_some() {
+                        return Ok(action);
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn receive(&mut self) -> io::Result<()> {
+        let mut buffer = [0; 1024];
+
+        while self.input.len() < MAX_INPUT {
+            let read = match self.connection {
+                Some(ref mut stream) => stream.read(&mut buffer),
+                None                 => return Ok(())
+            };
+
+            match read {
+                Ok(0)  => return Err(io::Error::new(ErrorKind::UnexpectedEof, "GDB client went away")),
+                Ok(n)  => self.input.extend_from_slice(&buffer[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock  => return Ok(()),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => { },
+                Err(e) => return Err(e)
+            }
+        }
+
+        Ok(())
+    }
+
+    // The next thing the client sent, once all of it has arrived. Acks are
+    // dropped along with anything else outside a packet, and a packet too
+    // long to take is dropped as garbled before it's all arrived.
+    fn next_input(&mut self) -> Option<Input> {
+        loop {
+            match self.input.first().cloned() {
+                None => return None,
+                Some(INTERRUPT) => {
+                    self.input.remove(0);
+                    return Some(Input::Interrupt);
+                },
+                Some(b'-') => {
+                    self.input.remove(0);
+                    return Some(Input::Resend);
+                },
+                Some(b'$') => break,
+                Some(_) => {
+                    self.input.remove(0);
+                }
+            }
+        }
+
+        // A packet is $, the data, # and a two digit checksum.
+        let end = match self.input.iter().position(|&b| b == b'#') {
+            Some(end) if end + 2 < self.input.len() => end,
+            None if self.input.len() > MAX_PACKET + 1 => {
+                self.input.clear();
+                return Some(Input::Garbled);
+            },
+            _ => return None
+        };
+
+        let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+        let data = &packet[1..end];
+
+        let checksum = str::from_utf8(&packet[end + 1..]).ok()
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+
+        if checksum != Some(checksum_of(data)) || data.len() > MAX_PACKET {
+            return Some(Input::Garbled);
+        }
+
+        Some(Input::Packet(String::from_utf8_lossy(data).into_owned()))
+    }
+
+    // Answers a packet, returning what the program should do if it's to
+    // carry on running or stop.
+    fn handle(&mut self, packet: &str, cpu: &mut Cpu, debugger: &mut Debugger) -> io::Result<Option<Action>> {
+        let mut chars = packet.chars();
+        let kind = chars.next();
+        let args = chars.as_str();
+
+        let reply = match kind {
+            Some('?') => format!("S{:02x}", SIGTRAP),
+            Some('g') => (0..REGISTERS).map(|register| encode_register(cpu, register)).collect(),
+            Some('G') => or_error(write_registers(cpu, args)),
+            Some('p') => or_error(parse_hex(args).filter(|&register| register < REGISTERS)
+                                                 .map(|register| encode_register(cpu, register))),
+            Some('P') => or_error(write_register(cpu, args)),
+            Some('m') => or_error(read_memory(cpu, args)),
+            Some('M') => or_error(write_memory(cpu, args)),
+            Some('Z') => change_breakpoint(debugger, args, true),
+            Some('z') => change_breakpoint(debugger, args, false),
+            Some('c') | Some('s') => {
+                // Either may give an address to carry on from.
+                if !args.is_empty() {
+                    match parse_hex(args) {
+                        Some(address) => cpu.set_pc(address as u16),
+                        None          => return self.send("E01").map(|_| None)
+                    }
+                }
+
+                self.running = true;
+
+                return Ok(Some(if kind == Some('c') { Action::Continue } else { Action::Step }));
+            },
+            Some('D') => {
+                try!(self.send("OK"));
+                self.disconnect();
+
+                return Ok(Some(Action::Detach));
+            },
+            Some('k') => {
+                self.disconnect();
+
+                return Ok(Some(Action::Detach));
+            },
+            // There's only the one thread.
+            Some('H') | Some('T') => String::from("OK"),
+            Some('q') | Some('Q') => self.query(packet),
+            _ => String::new()
+        };
+
+        try!(self.send(&reply));
+
+        Ok(None)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        const FEATURES: &'static str = "qXfer:features:read:target.xml:";
+
+        if packet.starts_with("qSupported") {
+            format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+", MAX_PACKET)
+        } else if packet == "QStartNoAckMode" {
+            // The client acks this reply; nothing after it is.
+            self.acks = false;
+            String::from("OK")
+        } else if packet == "qAttached" {
+            String::from("1")
+        } else if packet == "qC" {
+            String::from("QC1")
+        } else if packet == "qfThreadInfo" {
+            String::from("m1")
+        } else if packet == "qsThreadInfo" {
+            String::from("l")
+        } else if packet.starts_with(FEATURES) {
+            or_error(address_and_length(&packet[FEATURES.len()..])
+                .map(|(offset, length)| read_part(&target_description(), offset, length)))
+        } else {
+            String::new()
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes())).into_bytes();
+
+        try!(self.write(&packet));
+
+        self.last_packet = packet;
+
+        Ok(())
+    }
+
+    // Writes block, so replies go out whole; reads don't.
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self.connection {
+            Some(ref mut stream) => {
+                try!(stream.set_nonblocking(false));
+                try!(stream.write_all(bytes));
+                stream.set_nonblocking(true)
+            },
+            None => Ok(())
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, &b| sum.wrapping_add(b))
+}
+
+fn or_error(reply: Option<String>) -> String {
+    reply.unwrap_or(String::from("E01"))
+}
+
+fn parse_hex(digits: &str) -> Option<usize> {
+    usize::from_str_radix(digits, 16).ok()
+}
+
+fn decode_hex(digits: &str) -> Option<Vec<u8>> {
+    if digits.len() % 2 != 0 || !digits.is_ascii() {
+        return None;
+    }
+
+    (0..digits.len()).step_by(2)
+        .map(|index| u8::from_str_radix(&digits[index..index + 2], 16).ok())
+        .collect()
+}
+
+// "address,length", as in `m` and `M` packets.
+fn address_and_length(args: &str) -> Option<(usize, usize)> {
+    let mut parts = args.splitn(2, ',');
+
+    match (parts.next().and_then(parse_hex), parts.next().and_then(parse_hex)) {
+        (Some(address), Some(length)) => Some((address, length)),
+        _                             => None
+    }
+}
+
+// The V registers, SP and the timers are a byte each; I and PC are two.
+fn register_size(register: usize) -> usize {
+    match register {
+        16 | 17 => 2,
+        _       => 1
+    }
+}
+
+fn read_register(cpu: &Cpu, register: usize) -> u16 {
+    match register {
+        0 ... 15 => cpu.registers()[register] as u16,
+        16       => cpu.i(),
+        17       => cpu.pc(),
+        18       => cpu.sp(),
+        19       => cpu.delay_timer() as u16,
+        _        => cpu.sound_timer() as u16
+    }
+}
+
+fn set_register(cpu: &mut Cpu, register: usize, value: u16) {
+    match register {
+        0 ... 15 => cpu.set_register(register, value as u8),
+        16       => cpu.set_i(value),
+        17       => cpu.set_pc(value),
+        18       => cpu.set_sp(value),
+        19       => cpu.set_delay_timer(value as u8),
+        _        => cpu.set_sound_timer(value as u8)
+    }
+}
+
+// Registers go over the wire as hex bytes, least significant first.
+fn encode_register(cpu: &Cpu, register: usize) -> String {
+    let value = read_register(cpu, register);
+
+    (0..register_size(register))
+        .map(|byte| format!("{:02x}", (value >> (8 * byte)) & 0xFF))
+        .collect()
+}
+
+fn decode_register(bytes: &[u8]) -> u16 {
+    bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as u16)
+}
+
+fn write_registers(cpu: &mut Cpu, args: &str) -> Option<String> {
+    let bytes = match decode_hex(args) {
+        Some(bytes) => bytes,
+        None        => return None
+    };
+
+    if bytes.len() != (0..REGISTERS).map(register_size).sum::<usize>() {
+        return None;
+    }
+
+    let mut offset = 0;
+
+    for register in 0..REGISTERS {
+        let size = register_size(register);
+
+        set_register(cpu, register, decode_register(&bytes[offset..offset + size]));
+        offset += size;
+    }
+
+    Some(String::from("OK"))
+}
+
+// "register=value", as in `P` packets.
+fn write_register(cpu: &mut Cpu, args: &str) -> Option<String> {
+    let mut parts = args.splitn(2, '=');
+
+    let register = match parts.next().and_then(parse_hex) {
+        Some(register) if register < REGISTERS => register,
+        _ => return None
+    };
+
+    match parts.next().and_then(decode_hex) {
+        Some(ref bytes) if bytes.len() == register_size(register) => {
+            set_register(cpu, register, decode_register(bytes));
+            Some(String::from("OK"))
+        },
+        _ => None
+    }
+}
+
+// Reads as much as memory allows, failing only if it starts past the end.
+fn read_memory(cpu: &Cpu, args: &str) -> Option<String> {
+    let (address, length) = match address_and_length(args) {
+        Some((address, length)) if address < MEMORY_SIZE => (address, length),
+        _ => return None
+    };
+
+    let mut reply = String::new();
+
+    for offset in 0..length.min(MEMORY_SIZE - address).min(MAX_PACKET / 2) {
+        match cpu.mmu().read_byte(address + offset) {
+            Ok(byte) => reply.push_str(&format!("{:02x}", byte)),
+            Err(_)   => break
+        }
+    }
+
+    if reply.is_empty() && length > 0 {
+        None
+    } else {
+        Some(reply)
+    }
+}
+
+// "address,length:bytes", as in `M` packets.
+fn write_memory(cpu: &mut Cpu, args: &str) -> Option<String> {
+    let mut parts = args.splitn(2, ':');
+
+    let (address, length) = match parts.next().and_then(address_and_length) {
+        Some((address, length)) if address.checked_add(length).map_or(false, |end| end <= MEMORY_SIZE) => {
+            (address, length)
+        },
+        _ => return None
+    };
+
+    let bytes = match parts.next().and_then(decode_hex) {
+        Some(ref bytes) if bytes.len() == length => bytes.clone(),
+        _ => return None
+    };
+
+    for (offset, &byte) in bytes.iter().enumerate() {
+        if cpu.mmu_mut().write_byte(address + offset, byte).is_err() {
+            return None;
+        }
+    }
+
+    Some(String::from("OK"))
+}
+
+// "type,address,kind", as in `Z` and `z` packets. Software and hardware
+// breakpoints are one and the same here; watchpoints aren't supported.
+fn change_breakpoint(debugger: &mut Debugger, args: &str, set: bool) -> String {
+    let mut parts = args.split(',');
+
+    match parts.next() {
+        Some("0") | Some("1") => { },
+        _                     => return String::new()
+    }
+
+    match parts.next().and_then(parse_hex) {
+        Some(address) if address <= 0xFFF => {
+            if set {
+                debugger.set_breakpoint(address as u16);
+            } else {
+                debugger.clear_breakpoint(address as u16);
+            }
+
+            String::from("OK")
+        },
+        _ => String::from("E01")
+    }
+}
+
+// A piece of an object read with qXfer: "m" and the piece if there's more to
+// come, "l" and the piece if it's the last.
+fn read_part(object: &str, offset: usize, length: usize) -> String {
+    if offset >= object.len() {
+        return String::from("l");
+    }
+
+    let end = offset + length.min(object.len() - offset);
+    let marker = if end < object.len() { 'm' } else { 'l' };
+
+    format!("{}{}", marker, &object[offset..end])
+}
+
+// Tells the client what registers there are. GDB knows no CHIP-8
+// architecture, so there's none to name.
+fn target_description() -> String {
+    let mut registers = String::new();
+
+    for register in 0..REGISTERS {
+        let name = if register < 16 {
+            format!("v{:x}", register)
+        } else {
+            REGISTER_NAMES[register - 16].to_string()
+        };
+
+        let kind = match register {
+            16 => "data_ptr",
+            17 => "code_ptr",
+            _  => "uint8"
+        };
+
+        registers.push_str(&format!("<reg name=\"{}\" bitsize=\"{}\" type=\"{}\"/>",
+                                    name, 8 * register_size(register), kind));
+    }
+
+    format!("<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+             <target version=\"1.0\"><feature name=\"org.rustychip8.chip8\">{}</feature></target>",
+            registers)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use std::vec::Vec;
+
+    use cpu::Cpu;
+    use debugger::Debugger;
+    use mmu::Mmu;
+
+    use super::*;
+
+    // A client on the other end of a socket from a stub, polling the stub
+    // between reads the way the emulator would once a frame.
+    struct Session {
+        stub: GdbStub,
+        cpu: Cpu,
+        debugger: Debugger,
+        client: TcpStream,
+        received: Vec<u8>
+    }
+
+    impl Session {
+        fn new() -> Session {
+            let stub   = GdbStub::listen(0).unwrap();
+            let client = TcpStream::connect(("127.0.0.1", stub.port().unwrap())).unwrap();
+
+            client.set_read_timeout(Some(Duration::from_millis(5))).unwrap();
+
+            let mut session = Session {
+                stub: stub,
+                cpu: Cpu::new(Mmu::new()),
+                debugger: Debugger::new(1),
+                client: client,
+                received: Vec::new()
+            };
+
+            assert_eq!(session.poll_for_action(), Some(Action::Attach));
+
+            session
+        }
+
+        fn send_raw(&mut self, bytes: &[u8]) {
+            self.client.write_all(bytes).unwrap();
+        }
+
+        fn send(&mut self, data: &str) {
+            let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+
+            self.send_raw(packet.as_bytes());
+        }
+
+        fn poll(&mut self) -> Option<Action> {
+            let action = self.stub.poll(&mut self.cpu, &mut self.debugger);
+            let mut buffer = [0; 1024];
+
+            match self.client.read(&mut buffer) {
+                Ok(n) => self.received.extend_from_slice(&buffer[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => { },
+                Err(e) => panic!("{}", e)
+            }
+
+            action
+        }
+
+        fn poll_for_action(&mut self) -> Option<Action> {
+            for _ in 0..200 {
+                if let Some(action) = self.poll() {
+                    return Some(action);
+                }
+            }
+
+            None
+        }
+
+        // The next byte outside a packet, like an ack.
+        fn next_byte(&mut self) -> u8 {
+            for _ in 0..200 {
+                if !self.received.is_empty() {
+                    return self.received.remove(0);
+                }
+
+                self.poll();
+            }
+
+            panic!("Nothing came back");
+        }
+
+        // The data of the next packet, skipping acks.
+        fn reply(&mut self) -> String {
+            for _ in 0..200 {
+                while self.received.first() == Some(&b'+') {
+                    self.received.remove(0);
+                }
+
+                let end = self.received.iter().position(|&b| b == b'#');
+
+                if let Some(end) = end.filter(|&end| end + 2 < self.received.len()) {
+                    assert_eq!(self.received[0], b'$');
+
+                    let packet: Vec<u8> = self.received.drain(..end + 3).collect();
+
+                    return String::from_utf8(packet[1..end].to_vec()).unwrap();
+                }
+
+                self.poll();
+            }
+
+            panic!("No reply came back");
+        }
+
+        fn ask(&mut self, data: &str) -> String {
+            self.send(data);
+            self.reply()
+        }
+    }
+
+    #[test]
+    fn reports_why_it_stopped() {
+        let mut session = Session::new();
+
+        assert_eq!(session.ask("?"), "S05");
+    }
+
+    #[test]
+    fn reads_registers() {
+        let mut session = Session::new();
+
+        session.cpu.set_register(3, 0xAB);
+        session.cpu.set_i(0x0123);
+        session.cpu.set_pc(0x0234);
+
+        let registers = session.ask("g");
+
+        assert_eq!(registers.len(), 2 * 23);
+        assert_eq!(&registers[6..8], "ab");
+        assert_eq!(&registers[32..36], "2301");
+        assert_eq!(&registers[36..40], "3402");
+    }
+
+    #[test]
+    fn reads_memory_up_to_the_end() {
+        let mut session = Session::new();
+
+        session.cpu.mmu_mut().write_byte(0x300, 0x12).unwrap();
+        session.cpu.mmu_mut().write_byte(0x301, 0x34).unwrap();
+        session.cpu.mmu_mut().write_byte(0xFFF, 0x56).unwrap();
+
+        assert_eq!(session.ask("m300,2"), "1234");
+        assert_eq!(session.ask("mfff,10"), "56");
+        assert_eq!(session.ask("m1000,1"), "E01");
+        assert_eq!(session.ask("mffffffffffffffff,10"), "E01");
+        assert_eq!(session.ask("m300,ffffffffffffffff").len(), 2 * (0x1000 - 0x300));
+    }
+
+    #[test]
+    fn rejects_writes_past_the_end() {
+        let mut session = Session::new();
+
+        assert_eq!(session.ask("M300,2:abcd"), "OK");
+        assert_eq!(session.cpu.mmu().read_byte(0x301).unwrap(), 0xCD);
+
+        assert_eq!(session.ask("Mfff,2:abcd"), "E01");
+        assert_eq!(session.ask("Mffffffffffffffff,2:abcd"), "E01");
+        assert_eq!(session.ask("qXfer:features:read:target.xml:ffffffffffffffff,ffffffffffffffff"), "l");
+        assert!(session.ask("qXfer:features:read:target.xml:1,ffffffffffffffff").starts_with("l?xml"));
+    }
+
+    #[test]
+    fn sets_and_clears_breakpoints() {
+        let mut session = Session::new();
+
+        assert_eq!(session.ask("Z0,300,2"), "OK");
+        assert_eq!(session.debugger.breakpoints(), &[0x300]);
+
+        assert_eq!(session.ask("z0,300,2"), "OK");
+        assert!(session.debugger.breakpoints().is_empty());
+
+        assert_eq!(session.ask("Z0,1000,2"), "E01");
+    }
+
+    #[test]
+    fn continues_and_steps_until_told_of_a_stop() {
+        let mut session = Session::new();
+
+        session.send("c");
+        assert_eq!(session.poll_for_action(), Some(Action::Continue));
+
+        session.stub.report_stop(false);
+        assert_eq!(session.reply(), "S02");
+
+        session.send("s300");
+        assert_eq!(session.poll_for_action(), Some(Action::Step));
+        assert_eq!(session.cpu.pc(), 0x300);
+
+        session.stub.report_stop(true);
+        assert_eq!(session.reply(), "S05");
+    }
+
+    #[test]
+    fn nacks_garbled_packets_until_acks_are_off() {
+        let mut session = Session::new();
+
+        session.send_raw(b"$?#00");
+        assert_eq!(session.next_byte(), b'-');
+
+        assert_eq!(session.ask("QStartNoAckMode"), "OK");
+
+        session.send_raw(b"$?#00");
+        session.send("?");
+        assert_eq!(session.next_byte(), b'$');
+    }
+
+    #[test]
+    fn drops_packets_that_are_too_long() {
+        let mut session = Session::new();
+
+        let mut flood = vec![b'$'];
+        flood.extend(vec![b'0'; MAX_INPUT * 2]);
+
+        session.send_raw(&flood);
+        assert_eq!(session.next_byte(), b'-');
+
+        assert!(session.stub.input.len() <= MAX_INPUT);
+
+        // What's left of it is dropped as it arrives, and the stub still
+        // answers afterwards.
+        session.send_raw(b"#00");
+        session.received.clear();
+
+        for _ in 0..20 {
+            session.poll();
+        }
+
+        session.received.clear();
+        assert_eq!(session.ask("?"), "S05");
+    }
+}
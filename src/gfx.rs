@@ -1,65 +1,354 @@
+use std::mem;
 use std::vec::Vec;
 
-use sdl2::render::{Renderer, Texture, TextureAccess};
-use sdl2::pixels::PixelFormatEnum::BGR24;
-use sdl2::rect::{Rect};
-use sdl2::{Sdl};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::render::{BlendMode, Renderer, Texture, TextureAccess};
+use sdl2::pixels::PixelFormatEnum::{self, ARGB8888, RGB24};
+use sdl2::rect::Rect;
+use sdl2::{EventPump, Sdl};
 use sdl2;
 
-const SCREEN_WIDTH: u32  = 640;
-const SCREEN_HEIGHT: u32 = 320;
+use cpu::Cpu;
+use crt::{Crt, CrtSettings};
+use debugger::{self, Debugger};
+use framebuffer::{self, Framebuffer, PIXEL_OFF};
+use frontend::{Command, Frontend};
+use hotkeys::Hotkeys;
+use keypad::Keymap;
+use overlay::{self, Overlay};
+use palette::Palette;
+
+const TONE_FREQUENCY: f32 = 440.0;
+const TONE_VOLUME: f32    = 0.25;
+
+// The overlay's backdrop is three quarters opaque.
+const BACKDROP_ALPHA: u8 = 0xC0;
 
 pub struct Gfx<'a> {
     pub renderer: Renderer<'a>,
     pub texture: Texture,
-    pub scale: usize
+    pub scale: usize,
+    palette: Palette,
+    keymap: Keymap,
+    hotkeys: Hotkeys,
+    crt: Option<Crt>,
+    texture_size: (usize, usize),
+    events: EventPump,
+    tone: Option<AudioDevice<SquareWave>>,
+    keys: [bool; 16],
+    quit: bool,
+    commands: Vec<Command>,
+    held: Vec<Command>,
+    pixels: Vec<u8>,
+    overlay_texture: Texture,
+    overlay_layer: Framebuffer,
+    overlay_scratch: Framebuffer,
+    overlay_shown: bool,
+    panels: Option<(Texture, Framebuffer)>,
+    _sdl: Sdl
 }
 
 impl<'a> Gfx<'a> {
-    pub fn new(scale: usize) -> (Gfx<'a>, Sdl) {
-        let sdl = sdl2::init().unwrap();
-        let video = sdl.video().unwrap();
+    // `scale` is the size of each CHIP-8 pixel in window pixels. With CRT
+    // effects on, frames are upscaled in software to that size so the effects
+    // have room to draw; otherwise the renderer stretches the bare frame.
+    // With `debug` set, the window grows to make room for the debugger's
+    // panels, which are drawn at the overlay's resolution.
+    pub fn new(scale: usize, palette: Palette, crt: CrtSettings, keymap: Keymap,
+               hotkeys: Hotkeys, debug: bool) -> Result<Gfx<'a>, String> {
+        let sdl = try!(sdl2::init());
+        let video = try!(sdl.video());
+
+        let size = overlay::pixel_size(scale);
+
+        let window_size = if debug {
+            let (width, height) = debugger::panel_size(scale);
+
+            (width * size, height * size)
+        } else {
+            (framebuffer::WIDTH * scale, framebuffer::HEIGHT * scale)
+        };
+
+        let mut window_builder = video.window("RustyChip8", window_size.0 as u32, window_size.1 as u32);
+
+        let window   = try!(window_builder.position_centered().build().map_err(|e| e.to_string()));
+        let renderer = try!(window.renderer().accelerated().present_vsync().build().map_err(|e| e.to_string()));
+
+        let texture_size = (framebuffer::WIDTH, framebuffer::HEIGHT);
+        let texture = try!(create_texture(&renderer, RGB24, texture_size));
+
+        // The overlay is drawn at its own resolution into a texture that's
+        // blended over the game's.
+        let overlay_size = (framebuffer::WIDTH * scale / size, framebuffer::HEIGHT * scale / size);
+
+        let mut overlay_texture = try!(create_texture(&renderer, ARGB8888, overlay_size));
+        overlay_texture.set_blend_mode(BlendMode::Blend);
+
+        let panels = if debug {
+            let panel_size = debugger::panel_size(scale);
+            let texture = try!(create_texture(&renderer, RGB24, panel_size));
 
-        let mut window_builder = video.window("RustyChip8",
-                                              (SCREEN_WIDTH as usize * scale) as u32,
-                                              (SCREEN_HEIGHT as usize * scale) as u32);
+            Some((texture, Framebuffer::new(panel_size.0, panel_size.1)))
+        } else {
+            None
+        };
 
-        let window   = window_builder.position_centered().build().unwrap();
-        let renderer = window.renderer().accelerated().present_vsync().build().unwrap();
+        let events = try!(sdl.event_pump());
 
-        let texture = renderer.create_texture(BGR24,
-                                              TextureAccess::Streaming,
-                                              SCREEN_WIDTH, SCREEN_HEIGHT).unwrap();
+        // A missing sound card shouldn't stop the game.
+        let tone = sdl.audio().ok().and_then(|audio| {
+            let desired = AudioSpecDesired {
+                freq: Some(44100),
+                channels: Some(1),
+                samples: None
+            };
 
-        (Gfx {
+            audio.open_playback(None, &desired, |spec| {
+                SquareWave {
+                    phase_inc: TONE_FREQUENCY / spec.freq as f32,
+                    phase: 0.0,
+                    volume: TONE_VOLUME
+                }
+            }).ok()
+        });
+
+        Ok(Gfx {
             renderer: renderer,
             texture: texture,
-            scale: scale
-        }, sdl)
+            scale: scale,
+            palette: palette,
+            keymap: keymap,
+            hotkeys: hotkeys,
+            crt: if crt.is_off() { None } else { Some(Crt::new(crt, scale)) },
+            texture_size: texture_size,
+            events: events,
+            tone: tone,
+            keys: [false; 16],
+            quit: false,
+            commands: Vec::new(),
+            held: Vec::new(),
+            pixels: Vec::new(),
+            overlay_texture: overlay_texture,
+            overlay_layer: Framebuffer::new(overlay_size.0, overlay_size.1),
+            overlay_scratch: Framebuffer::new(overlay_size.0, overlay_size.1),
+            overlay_shown: false,
+            panels: panels,
+            _sdl: sdl
+        })
+    }
+
+    pub fn blit(&mut self, frame: &Framebuffer) {
+        let size = match self.crt {
+            Some(ref crt) => crt.output_size(frame),
+            None          => (frame.width(), frame.height())
+        };
+
+        if size != self.texture_size {
+            self.texture      = create_texture(&self.renderer, RGB24, size).unwrap();
+            self.texture_size = size;
+        }
+
+        if let Some(ref mut crt) = self.crt {
+            let pixels = crt.render(frame, &self.palette);
+
+            self.texture.update(None, pixels, size.0 * 3).unwrap();
+            return;
+        }
+
+        self.pixels.clear();
+
+        for pixel in frame.pixels() {
+            let color = self.palette.shade(*pixel);
+
+            self.pixels.push(color.r);
+            self.pixels.push(color.g);
+            self.pixels.push(color.b);
+        }
+
+        self.texture.update(None, &self.pixels, frame.width() * 3).unwrap();
+    }
+
+    fn blit_overlay(&mut self) {
+        let background = self.palette.background();
+        let foreground = self.palette.foreground();
+
+        self.pixels.clear();
+
+        for pixel in self.overlay_layer.pixels() {
+            let (color, alpha) = match *pixel {
+                PIXEL_OFF         => (background, 0),
+                overlay::BACKDROP => (background, BACKDROP_ALPHA),
+                _                 => (foreground, 0xFF)
+            };
+
+            let argb = (alpha as u32) << 24 | (color.r as u32) << 16 | (color.g as u32) << 8 | color.b as u32;
+
+            self.pixels.extend_from_slice(&argb.to_ne_bytes());
+        }
+
+        let width = self.overlay_layer.width();
+
+        self.overlay_texture.update(None, &self.pixels, width * 4).unwrap();
+    }
+}
+
+impl<'a> Frontend for Gfx<'a> {
+    fn present(&mut self, frame: &Framebuffer) {
+        if frame.is_dirty() {
+            self.blit(frame);
+        }
+
+        self.renderer.clear();
+
+        // The game keeps its size in the top left corner when the debugger's
+        // panels fill the rest of the window.
+        let game = Rect::new(0, 0, (framebuffer::WIDTH * self.scale) as u32, (framebuffer::HEIGHT * self.scale) as u32);
+
+        if let Some((ref texture, _)) = self.panels {
+            self.renderer.copy(texture, None, None);
+        }
+
+        self.renderer.copy(&self.texture, None, Some(game));
+
+        // The overlay only lasts the frame it was given for.
+        if self.overlay_shown {
+            self.renderer.copy(&self.overlay_texture, None, Some(game));
+            self.overlay_shown = false;
+        }
+
+        self.renderer.present();
+    }
+
+    fn poll_input(&mut self, keys: &mut [bool; 16]) {
+        for event in self.events.poll_iter() {
+            match event {
+                Event::Quit { .. } => {
+                    self.quit = true;
+                },
+                Event::KeyDown { keycode: Some(keycode), repeat, .. } => {
+                    if let Some(command) = self.hotkeys.command_for(&keycode.name()) {
+                        // Holding a key scrolls menus, but shouldn't take a
+                        // screenshot every frame.
+                        if !repeat || command.repeats() {
+                            self.commands.push(command);
+                        }
+
+                        if !self.held.contains(&command) {
+                            self.held.push(command);
+                        }
+                    } else if let Some(key) = key_for(&self.keymap, keycode) {
+                        self.keys[key] = true;
+                    }
+                },
+                Event::MouseButtonDown { x, y, .. } => {
+                    let size = overlay::pixel_size(self.scale) as i32;
+
+                    self.commands.push(Command::Click((x / size) as usize, (y / size) as usize));
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(command) = self.hotkeys.command_for(&keycode.name()) {
+                        self.held.retain(|&held| held != command);
+                    } else if let Some(key) = key_for(&self.keymap, keycode) {
+                        self.keys[key] = false;
+                    }
+                },
+                _ => { }
+            }
+        }
+
+        keys.copy_from_slice(&self.keys);
+    }
+
+    fn play_tone(&mut self) {
+        if let Some(ref tone) = self.tone {
+            tone.resume();
+        }
+    }
+
+    fn stop_tone(&mut self) {
+        if let Some(ref tone) = self.tone {
+            tone.pause();
+        }
+    }
+
+    fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    fn commands(&mut self) -> Vec<Command> {
+        mem::replace(&mut self.commands, Vec::new())
+    }
+
+    fn is_held(&self, command: Command) -> bool {
+        self.held.contains(&command)
+    }
+
+    fn shows_messages(&self) -> bool {
+        true
     }
 
-    pub fn composite(&mut self, buffer: Vec<u8>) {
-        let mut pixel: u8;
+    // Redrawn every frame, but only uploaded when it changes.
+    fn show_overlay(&mut self, overlay: &Overlay) {
+        self.overlay_scratch.clear();
+        overlay.draw(&mut self.overlay_scratch);
 
-        self.renderer.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+        self.overlay_shown = self.overlay_scratch.pixels().iter().any(|&pixel| pixel != PIXEL_OFF);
+
+        if self.overlay_scratch.pixels() != self.overlay_layer.pixels() {
+            mem::swap(&mut self.overlay_scratch, &mut self.overlay_layer);
+            self.blit_overlay();
+        }
+    }
 
-        for y in 0..32 {
-            for x in 0..64 {
-                pixel = &buffer[y * 64 + x] * 255;
-                self.renderer.fill_rect(
-                    Rect::new(x as i32, y as i32, self.scale as u32, self.scale as u32));
+    fn show_debugger(&mut self, debugger: &Debugger, cpu: &Cpu) {
+        if let Some((ref mut texture, ref mut panels)) = self.panels {
+            panels.clear();
+            debugger.draw(cpu, panels);
+
+            self.pixels.clear();
+
+            for pixel in panels.pixels() {
+                let color = self.palette.shade(*pixel);
+
+                self.pixels.push(color.r);
+                self.pixels.push(color.g);
+                self.pixels.push(color.b);
             }
+
+            texture.update(None, &self.pixels, panels.width() * 3).unwrap();
         }
     }
+}
 
-    // pub fn composite(&mut self, buffer: Vec<u8>) {
-    //     self.blit(buffer);
-    //     self.renderer.clear();
-    //     self.renderer.copy(&self.texture, None, None);
-    //     self.renderer.present();
-    // }
+fn create_texture(renderer: &Renderer, format: PixelFormatEnum, size: (usize, usize)) -> Result<Texture, String> {
+    renderer.create_texture(format, TextureAccess::Streaming, size.0 as u32, size.1 as u32)
+        .map_err(|e| format!("{:?}", e))
+}
+
+fn key_for(keymap: &Keymap, keycode: Keycode) -> Option<usize> {
+    let name = keycode.name();
+
+    match name.chars().next() {
+        Some(c) if name.len() == 1 => keymap.key_for(c),
+        _                          => None
+    }
+}
 
-    // pub fn blit(&mut self, buffer: Vec<u8>) {
-    //     self.texture.update(None, &buffer, SCREEN_WIDTH as usize * 3).unwrap();
-    // }
+struct SquareWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = if self.phase < 0.5 { self.volume } else { -self.volume };
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+        }
+    }
 }
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::vec::Vec;
+
+use palette::Color;
+
+// A minimal animated GIF encoder: one 256 color global table, full frames,
+// LZW compressed, looping forever.
+
+const MIN_CODE_SIZE: u32 = 8;
+const MAX_CODE_SIZE: u32 = 12;
+const CLEAR_CODE: u16    = 1 << MIN_CODE_SIZE;
+const END_CODE: u16      = CLEAR_CODE + 1;
+
+pub struct GifWriter<W: Write> {
+    out: W,
+    width: usize,
+    height: usize
+}
+
+impl<W: Write> GifWriter<W> {
+    // Writes the header; `colors` fills the global table and is padded with
+    // black up to 256 entries.
+    pub fn new(mut out: W, width: usize, height: usize, colors: &[Color]) -> io::Result<GifWriter<W>> {
+        let mut header = Vec::new();
+
+        header.extend_from_slice(b"GIF89a");
+        push_u16(&mut header, width as u16);
+        push_u16(&mut header, height as u16);
+        // A global table of 2^(7+1) colors, with 8 bits per channel.
+        header.extend_from_slice(&[0xF7, 0, 0]);
+
+        for index in 0..256 {
+            let color = colors.get(index).cloned().unwrap_or(Color::new(0, 0, 0));
+
+            header.extend_from_slice(&[color.r, color.g, color.b]);
+        }
+
+        // The NETSCAPE2.0 extension, asking viewers to loop forever.
+        header.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+        header.extend_from_slice(b"NETSCAPE2.0");
+        header.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+
+        try!(out.write_all(&header));
+
+        Ok(GifWriter {
+            out: out,
+            width: width,
+            height: height
+        })
+    }
+
+    // Writes a frame of color table indices, shown for `delay` hundredths of
+    // a second.
+    pub fn write_frame(&mut self, indices: &[u8], delay: u16) -> io::Result<()> {
+        assert_eq!(indices.len(), self.width * self.height);
+
+        let mut block = Vec::new();
+
+        // Graphic control extension: no disposal or transparency, just the
+        // delay.
+        block.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00]);
+        push_u16(&mut block, delay);
+        block.extend_from_slice(&[0x00, 0x00]);
+
+        // Image descriptor covering the whole screen, using the global table.
+        block.push(0x2C);
+        push_u16(&mut block, 0);
+        push_u16(&mut block, 0);
+        push_u16(&mut block, self.width as u16);
+        push_u16(&mut block, self.height as u16);
+        block.push(0x00);
+
+        block.push(MIN_CODE_SIZE as u8);
+
+        // The compressed data goes out in sub-blocks of up to 255 bytes.
+        for chunk in lzw(indices).chunks(255) {
+            block.push(chunk.len() as u8);
+            block.extend_from_slice(chunk);
+        }
+
+        block.push(0x00);
+
+        self.out.write_all(&block)
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        try!(self.out.write_all(&[0x3B]));
+        self.out.flush()
+    }
+}
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+    out.push(value as u8);
+    out.push((value >> 8) as u8);
+}
+
+fn lzw(indices: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::new();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+
+    let mut code_size = MIN_CODE_SIZE + 1;
+    let mut next_code = END_CODE + 1;
+
+    bits.write(CLEAR_CODE, code_size);
+
+    let mut current = match indices.first() {
+        Some(index) => *index as u16,
+        None => {
+            bits.write(END_CODE, code_size);
+            return bits.finish();
+        }
+    };
+
+    for index in indices[1..].iter() {
+        if let Some(code) = table.get(&(current, *index)) {
+            current = *code;
+            continue;
+        }
+
+        bits.write(current, code_size);
+
+        if next_code as u32 == 1 << MAX_CODE_SIZE {
+            // The table is full; start a new one.
+            bits.write(CLEAR_CODE, code_size);
+            table.clear();
+
+            code_size = MIN_CODE_SIZE + 1;
+            next_code = END_CODE + 1;
+        } else {
+            table.insert((current, *index), next_code);
+
+            // Decoders add each entry a code later than we do, so widen only
+            // once a code needs the extra bit.
+            if next_code as u32 == 1 << code_size {
+                code_size += 1;
+            }
+
+            next_code += 1;
+        }
+
+        current = *index as u16;
+    }
+
+    bits.write(current, code_size);
+    bits.write(END_CODE, code_size);
+
+    bits.finish()
+}
+
+// Packs codes least significant bit first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            buffer: 0,
+            count: 0
+        }
+    }
+
+    fn write(&mut self, code: u16, size: u32) {
+        self.buffer |= (code as u32) << self.count;
+        self.count += size;
+
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+
+        self.bytes
+    }
+}
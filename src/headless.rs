@@ -0,0 +1,45 @@
+use framebuffer::Framebuffer;
+use frontend::Frontend;
+
+// Runs without a display, input or sound, for scripted runs. Quits after a
+// fixed number of frames, or never if there's no limit.
+pub struct Headless {
+    frames: u64,
+    max_frames: Option<u64>
+}
+
+impl Headless {
+    pub fn new(max_frames: Option<u64>) -> Headless {
+        Headless {
+            frames: 0,
+            max_frames: max_frames
+        }
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+impl Frontend for Headless {
+    fn present(&mut self, _frame: &Framebuffer) {
+        self.frames += 1;
+    }
+
+    fn poll_input(&mut self, _keys: &mut [bool; 16]) { }
+
+    fn play_tone(&mut self) { }
+
+    fn stop_tone(&mut self) { }
+
+    fn quit_requested(&self) -> bool {
+        match self.max_frames {
+            Some(max_frames) => self.frames >= max_frames,
+            None             => false
+        }
+    }
+
+    fn realtime(&self) -> bool {
+        false
+    }
+}
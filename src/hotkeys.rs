@@ -0,0 +1,149 @@
+use std::str::FromStr;
+use std::vec::Vec;
+
+use frontend::Command;
+
+// Every command with its name in `--hotkeys`, its default key and what it
+// does, for the list of controls. Keys are named as SDL names them: letters,
+// digits and symbols as themselves, and the rest like "Escape", "Tab",
+// "Space" or "F5".
+const COMMANDS: [(Command, &'static str, &'static str, &'static str); 25] = [
+    (Command::Back,             "back",             "Escape",   "leave the game"),
+    (Command::Up,               "up",               "Up",       "move up in menus"),
+    (Command::Down,             "down",             "Down",     "move down in menus"),
+    (Command::Select,           "select",           "Return",   "choose in menus"),
+    (Command::Pause,            "pause",            "P",        "pause or resume"),
+    (Command::FrameAdvance,     "frame-advance",    "N",        "next frame while paused"),
+    (Command::FastForward,      "fast-forward",     "Tab",      "fast forward while held"),
+    (Command::SpeedUp,          "speed-up",         "=",        "more instructions per frame"),
+    (Command::SpeedDown,        "speed-down",       "-",        "fewer instructions per frame"),
+    (Command::SoftReset,        "soft-reset",       "F6",       "restart the program"),
+    (Command::HardReset,        "hard-reset",       "F5",       "reload the ROM"),
+    (Command::Screenshot,       "screenshot",       "F12",      "save a screenshot"),
+    (Command::ToggleRecording,  "record",           "F10",      "start or stop recording"),
+    (Command::ToggleControls,   "controls",         "F1",       "show or hide these controls"),
+    (Command::ToggleOverlay,    "overlay",          "F2",       "show or hide the overlay"),
+    (Command::Step,             "step",             "F7",       "run one instruction"),
+    (Command::ToggleBreakpoint, "breakpoint",       "F9",       "set or clear a breakpoint"),
+    (Command::MemoryUp,         "memory-up",        "PageUp",   "scroll the memory view up"),
+    (Command::MemoryDown,       "memory-down",      "PageDown", "scroll the memory view down"),
+    (Command::NewSearch,        "search",           "F3",       "start a cheat search"),
+    (Command::SearchChanged,    "search-changed",   "F4",       "keep bytes that changed"),
+    (Command::SearchUnchanged,  "search-unchanged", "F8",       "keep bytes that stayed the same"),
+    (Command::SearchIncreased,  "search-increased", "]",        "keep bytes that went up"),
+    (Command::SearchDecreased,  "search-decreased", "[",        "keep bytes that went down"),
+    (Command::Freeze,           "freeze",           "F11",      "freeze or thaw the byte found")
+];
+
+// Which keyboard key gives each command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hotkeys {
+    keys: Vec<String>
+}
+
+impl Hotkeys {
+    pub fn new() -> Hotkeys {
+        Hotkeys {
+            keys: COMMANDS.iter().map(|&(_, _, key, _)| key.to_string()).collect()
+        }
+    }
+
+    // The command for the named key, if it has one. Names are compared
+    // ignoring case.
+    pub fn command_for(&self, key: &str) -> Option<Command> {
+        self.keys.iter()
+            .position(|k| k.eq_ignore_ascii_case(key))
+            .map(|index| COMMANDS[index].0)
+    }
+
+    // A line for each command, giving its key and what it does.
+    pub fn describe(&self) -> Vec<String> {
+        COMMANDS.iter().zip(self.keys.iter())
+            .map(|(&(_, _, _, description), key)| format!("{:<9}{}", key, description))
+            .collect()
+    }
+}
+
+// Parses a comma separated list of command=key pairs, e.g.
+// "pause=Space,fast-forward=F3". Commands left out keep their default keys.
+impl FromStr for Hotkeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Hotkeys, String> {
+        let mut hotkeys = Hotkeys::new();
+
+        for binding in s.split(',').map(|binding| binding.trim()).filter(|binding| !binding.is_empty()) {
+            let mut parts = binding.splitn(2, '=');
+
+            let name = parts.next().unwrap().trim();
+
+            // '=' is a key too, as in "speed-up==".
+            let key = match parts.next().map(|key| key.trim()) {
+                Some(key) if !key.is_empty() => key,
+                _ => return Err(format!("Expected command=key in hotkeys: {}", binding))
+            };
+
+            match COMMANDS.iter().position(|&(_, n, _, _)| n == name) {
+                Some(index) => hotkeys.keys[index] = key.to_string(),
+                None        => return Err(format!("Unknown hotkey command: {}", name))
+            }
+        }
+
+        for (index, key) in hotkeys.keys.iter().enumerate() {
+            if hotkeys.keys[..index].iter().any(|k| k.eq_ignore_ascii_case(key)) {
+                return Err(format!("Hotkeys use {} twice: {}", key, s));
+            }
+        }
+
+        Ok(hotkeys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_from_the_defaults() {
+        let hotkeys: Hotkeys = "".parse().unwrap();
+
+        assert_eq!(hotkeys, Hotkeys::new());
+        assert_eq!(hotkeys.command_for("Escape"), Some(Command::Back));
+        assert_eq!(hotkeys.command_for("p"), Some(Command::Pause));
+        assert_eq!(hotkeys.command_for("Q"), None);
+    }
+
+    #[test]
+    fn rebinds_only_the_commands_given() {
+        let hotkeys: Hotkeys = " pause = Space , record=F9x,".parse().unwrap();
+
+        assert_eq!(hotkeys.command_for("space"), Some(Command::Pause));
+        assert_eq!(hotkeys.command_for("F9x"), Some(Command::ToggleRecording));
+        assert_eq!(hotkeys.command_for("P"), None);
+        assert_eq!(hotkeys.command_for("F10"), None);
+        assert_eq!(hotkeys.command_for("N"), Some(Command::FrameAdvance));
+    }
+
+    #[test]
+    fn takes_equals_as_a_key() {
+        let hotkeys: Hotkeys = "speed-up=+,speed-down==,search-decreased=-".parse().unwrap();
+
+        assert_eq!(hotkeys.command_for("+"), Some(Command::SpeedUp));
+        assert_eq!(hotkeys.command_for("="), Some(Command::SpeedDown));
+        assert_eq!(hotkeys.command_for("-"), Some(Command::SearchDecreased));
+    }
+
+    #[test]
+    fn rejects_keys_used_twice() {
+        assert_eq!("speed-down==".parse::<Hotkeys>().err().unwrap(), "Hotkeys use = twice: speed-down==");
+        assert_eq!("pause=n".parse::<Hotkeys>().err().unwrap(), "Hotkeys use N twice: pause=n");
+        assert!("pause=n,frame-advance=m".parse::<Hotkeys>().is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_commands_and_missing_keys() {
+        assert_eq!("jump=Space".parse::<Hotkeys>().err().unwrap(), "Unknown hotkey command: jump");
+        assert_eq!("pause=".parse::<Hotkeys>().err().unwrap(), "Expected command=key in hotkeys: pause=");
+        assert_eq!("pause".parse::<Hotkeys>().err().unwrap(), "Expected command=key in hotkeys: pause");
+    }
+}
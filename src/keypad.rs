@@ -0,0 +1,69 @@
+use std::str::FromStr;
+use std::vec::Vec;
+
+// Keyboard layout for the 16-key hex keypad, indexed by CHIP-8 key. The
+// keypad's 4x4 grid sits on the left of a QWERTY keyboard:
+//
+//   1 2 3 C        1 2 3 4
+//   4 5 6 D   <-   q w e r
+//   7 8 9 E        a s d f
+//   A 0 B F        z x c v
+pub const LAYOUT: [char; 16] = [
+    'x', '1', '2', '3',
+    'q', 'w', 'e', 'a',
+    's', 'd', 'z', 'c',
+    '4', 'r', 'f', 'v'
+];
+
+// Which keyboard key stands in for each CHIP-8 key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [char; 16]
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        Keymap {
+            keys: LAYOUT
+        }
+    }
+
+    // The keyboard key for a CHIP-8 key.
+    pub fn char_for(&self, key: usize) -> char {
+        self.keys[key]
+    }
+
+    pub fn key_for(&self, c: char) -> Option<usize> {
+        let c = c.to_ascii_lowercase();
+
+        self.keys.iter().position(|&k| k == c)
+    }
+}
+
+// Parses sixteen keys in CHIP-8 key order, 0 to F, e.g. "x123qweasdzc4rfv"
+// for the default layout.
+impl FromStr for Keymap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Keymap, String> {
+        let chars: Vec<char> = s.chars().map(|c| c.to_ascii_lowercase()).collect();
+
+        if chars.len() != 16 {
+            return Err(format!("A keymap needs 16 keys, one for each of 0 to F: {}", s));
+        }
+
+        let mut keys = [' '; 16];
+
+        for (key, c) in chars.iter().enumerate() {
+            if chars[..key].contains(c) {
+                return Err(format!("Keymap uses {} twice: {}", c, s));
+            }
+
+            keys[key] = *c;
+        }
+
+        Ok(Keymap {
+            keys: keys
+        })
+    }
+}
@@ -0,0 +1,506 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+use cpu::Cpu;
+use detect;
+use font;
+use framebuffer::{Framebuffer, PIXEL_OFF, PIXEL_ON};
+use frontend::{Command, Frontend};
+use mmu::{self, Mmu};
+use options::DEFAULT_IPS;
+use platform::Platform;
+use quirks::Quirks;
+use romdb::RomInfo;
+use timing::{Scheduler, Timing, FRAMES_PER_SECOND};
+
+// The menu is drawn at twice CHIP-8's resolution, with the list of ROMs down
+// the left and the selected ROM's preview and details on the right.
+const WIDTH: usize        = 128;
+const HEIGHT: usize       = 64;
+const LIST_WIDTH: usize   = 64;
+const LIST_ROWS: usize    = HEIGHT / font::LINE_HEIGHT;
+const DETAILS_TOP: usize  = 34;
+const DETAILS_ROWS: usize = (HEIGHT - DETAILS_TOP) / font::LINE_HEIGHT;
+
+// Ends text that had to be cut short.
+const ELLIPSIS: char = '\u{2026}';
+
+// Previews loop over the first few seconds of each game.
+const PREVIEW_FRAMES: u32 = 5 * FRAMES_PER_SECOND;
+
+// A ROM found in the directory being browsed.
+pub struct Entry {
+    pub path: PathBuf,
+    pub title: String,
+    pub info: Option<&'static RomInfo>,
+    pub platform: Platform,
+    pub rom: Vec<u8>
+}
+
+// A menu of the ROMs in a directory, drawn into a framebuffer so any frontend
+// can show it.
+pub struct Launcher {
+    entries: Vec<Entry>,
+    selected: usize,
+    top: usize,
+    preview: Option<Preview>,
+    screen: Framebuffer,
+    message: Option<String>
+}
+
+impl Launcher {
+    // Lists the files in `directory`. Anything that can't be read, or is too
+    // big to load, is left out rather than keeping the rest from the menu.
+    pub fn open(directory: &str) -> io::Result<Launcher> {
+        let mut entries = Vec::new();
+
+        for entry in try!(fs::read_dir(directory)) {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(_)    => continue
+            };
+
+            let hidden = path.file_name()
+                .map(|name| name.to_string_lossy().starts_with("."))
+                .unwrap_or(true);
+
+            if hidden || !path.is_file() {
+                continue;
+            }
+
+            let rom = match mmu::read_rom(path.to_string_lossy().into_owned()) {
+                Ok(rom) => rom,
+                Err(_)  => continue
+            };
+
+            let mut mmu = Mmu::new();
+
+            if mmu.load_rom(&rom).is_err() {
+                continue;
+            }
+
+            let info = mmu.rom_info();
+
+            let title = match info {
+                Some(info) => info.title.to_string(),
+                None       => path.file_name().unwrap().to_string_lossy().into_owned()
+            };
+
+            let platform = match info {
+                Some(info) => info.platform,
+                None       => detect::detect(&rom).platform
+            };
+
+            entries.push(Entry {
+                path: path,
+                title: title,
+                info: info,
+                platform: platform,
+                rom: rom
+            });
+        }
+
+        if entries.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("no ROMs in {}", directory)));
+        }
+
+        entries.sort_by_key(|entry| entry.title.to_lowercase());
+
+        Ok(Launcher {
+            entries: entries,
+            selected: 0,
+            top: 0,
+            preview: None,
+            screen: Framebuffer::new(WIDTH, HEIGHT),
+            message: None
+        })
+    }
+
+    pub fn entry(&self, index: usize) -> &Entry {
+        &self.entries[index]
+    }
+
+    // Shown in place of the selected ROM's details until the selection moves,
+    // e.g. to explain why a game stopped.
+    pub fn set_message(&mut self, message: &str) {
+        self.message = Some(message.to_string());
+    }
+
+    // Shows the menu until a ROM is chosen, returning its index, or until the
+    // user quits or goes back.
+    pub fn run<F: Frontend + ?Sized>(&mut self, frontend: &mut F) -> Option<usize> {
+        let frame = Duration::new(0, 1000000000 / FRAMES_PER_SECOND);
+
+        let mut keys = [false; 16];
+
+        // Start the preview over, and draw everything afresh in case a game
+        // has been using the frontend.
+        self.preview = None;
+        self.screen  = Framebuffer::new(WIDTH, HEIGHT);
+
+        while !frontend.quit_requested() {
+            let started = Instant::now();
+
+            frontend.poll_input(&mut keys);
+
+            for command in frontend.commands() {
+                match command {
+                    Command::Up     => self.select(self.selected.saturating_sub(1)),
+                    Command::Down   => self.select(self.selected + 1),
+                    Command::Select => return Some(self.selected),
+                    Command::Back   => return None,
+                    _               => { }
+                }
+            }
+
+            if self.preview.is_none() {
+                self.preview = Some(Preview::new(&self.entries[self.selected]));
+            }
+
+            if let Some(ref mut preview) = self.preview {
+                preview.run_frame();
+            }
+
+            self.draw();
+
+            frontend.show_status("Up/Down: choose  Enter: play  Esc: leave");
+            frontend.present(&self.screen);
+            self.screen.mark_clean();
+
+            let elapsed = started.elapsed();
+
+            if frontend.realtime() && elapsed < frame {
+                thread::sleep(frame - elapsed);
+            }
+        }
+
+        None
+    }
+
+    fn select(&mut self, index: usize) {
+        let index = index.min(self.entries.len() - 1);
+
+        if index == self.selected {
+            return;
+        }
+
+        self.selected = index;
+        self.preview  = None;
+        self.message  = None;
+
+        // Scroll just enough to keep the selection in view.
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + LIST_ROWS {
+            self.top = self.selected + 1 - LIST_ROWS;
+        }
+    }
+
+    fn draw(&mut self) {
+        self.screen.clear();
+
+        let visible = self.entries.len().min(self.top + LIST_ROWS);
+
+        for (row, index) in (self.top..visible).enumerate() {
+            let y = row * font::LINE_HEIGHT;
+
+            let brightness = if index == self.selected {
+                font::fill_rect(&mut self.screen, 0, y, LIST_WIDTH - 1, font::LINE_HEIGHT, PIXEL_ON);
+                PIXEL_OFF
+            } else {
+                PIXEL_ON
+            };
+
+            let title = fit(&self.entries[index].title, LIST_WIDTH - 2);
+
+            font::draw_text(&mut self.screen, 1, y + 1, &title, brightness);
+        }
+
+        match self.preview {
+            Some(ref preview) if !preview.failed => {
+                let frame = preview.cpu.framebuffer();
+
+                for y in 0..frame.height().min(HEIGHT) {
+                    for x in 0..frame.width().min(WIDTH - LIST_WIDTH) {
+                        self.screen.set_pixel(LIST_WIDTH + x, y, frame.pixel(x, y));
+                    }
+                }
+            },
+            _ => {
+                font::draw_text(&mut self.screen, LIST_WIDTH + 12, 13, "NO PREVIEW", PIXEL_ON);
+            }
+        }
+
+        let lines = match self.message {
+            Some(ref message) => wrap(message, WIDTH - LIST_WIDTH - 2),
+            None              => self.details()
+        };
+
+        let lines = clip(lines, DETAILS_ROWS, WIDTH - LIST_WIDTH - 2);
+
+        for (line, text) in lines.iter().enumerate() {
+            let y = DETAILS_TOP + line * font::LINE_HEIGHT;
+
+            font::draw_text(&mut self.screen, LIST_WIDTH + 2, y, &fit(text, WIDTH - LIST_WIDTH - 2), PIXEL_ON);
+        }
+    }
+
+    fn details(&self) -> Vec<String> {
+        let entry = &self.entries[self.selected];
+
+        let mut lines = Vec::new();
+
+        match entry.info.and_then(|info| info.author) {
+            Some(author) => lines.push(author.to_string()),
+            None         => lines.push(String::from("Unknown author"))
+        }
+
+        lines.push(entry.platform.to_string());
+
+        if let Some(name) = entry.path.file_name() {
+            lines.push(name.to_string_lossy().into_owned());
+        }
+
+        lines.push(format!("{} bytes", entry.rom.len()));
+
+        lines
+    }
+}
+
+// A game running with no display of its own, for the menu to show.
+struct Preview {
+    cpu: Cpu,
+    scheduler: Scheduler,
+    frames: u32,
+    failed: bool,
+    rom: Vec<u8>
+}
+
+impl Preview {
+    // Runs with the quirks and timing the game would get if launched with no
+    // other settings.
+    fn new(entry: &Entry) -> Preview {
+        let mut mmu = Mmu::new();
+        let failed = mmu.load_rom(&entry.rom).is_err();
+
+        let (quirks, timing) = match entry.info {
+            Some(info) => (info.quirks, info.timing.unwrap_or(Timing::Flat(DEFAULT_IPS))),
+            None => {
+                let detection = detect::detect(&entry.rom);
+
+                if detection.confidence >= detect::CONFIDENT {
+                    (detection.quirks, Timing::Flat(DEFAULT_IPS))
+                } else {
+                    (Quirks::new(), Timing::Flat(DEFAULT_IPS))
+                }
+            }
+        };
+
+        let mut cpu = Cpu::new(mmu);
+        cpu.set_quirks(quirks);
+
+        Preview {
+            cpu: cpu,
+            scheduler: Scheduler::new(timing),
+            frames: 0,
+            failed: failed,
+            rom: entry.rom.clone()
+        }
+    }
+
+    fn run_frame(&mut self) {
+        if self.failed {
+            return;
+        }
+
+        if self.frames == PREVIEW_FRAMES {
+            let quirks = self.cpu.quirks();
+            let timing = self.scheduler.timing();
+
+            let mut mmu = Mmu::new();
+            let _ = mmu.load_rom(&self.rom);
+
+            self.cpu = Cpu::new(mmu);
+            self.cpu.set_quirks(quirks);
+            self.scheduler = Scheduler::new(timing);
+            self.frames = 0;
+        }
+
+        // A preview that faults stops for good, and the menu says there's no
+        // preview.
+        if self.scheduler.run_frame(&mut self.cpu).is_err() {
+            self.failed = true;
+        }
+
+        self.frames += 1;
+    }
+}
+
+// Cuts `text` down to what fits in `width` pixels, ending it with an
+// ellipsis if anything had to go.
+fn fit(text: &str, width: usize) -> String {
+    let columns = width / font::ADVANCE;
+
+    if text.chars().count() <= columns {
+        return text.to_string();
+    }
+
+    let mut fitted: String = text.chars().take(columns.saturating_sub(1)).collect();
+    fitted.push(ELLIPSIS);
+    fitted
+}
+
+// Keeps the first `rows` of `lines`, ending the last one kept with an
+// ellipsis if any more had to go.
+fn clip(mut lines: Vec<String>, rows: usize, width: usize) -> Vec<String> {
+    if lines.len() > rows && rows > 0 {
+        lines.truncate(rows);
+
+        let last = lines.pop().unwrap();
+        let columns = width / font::ADVANCE;
+
+        let mut clipped: String = last.chars().take(columns.saturating_sub(1)).collect();
+        clipped.push(ELLIPSIS);
+        lines.push(clipped);
+    }
+
+    lines
+}
+
+// Breaks `text` into lines that fit in `width` pixels, at spaces where it
+// can.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let columns = width / font::ADVANCE;
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > columns {
+            lines.push(line);
+            line = String::new();
+        }
+
+        if !line.is_empty() {
+            line.push(' ');
+        }
+
+        line.push_str(word);
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_entries(count: usize) -> Launcher {
+        let entries = (0..count)
+            .map(|index| Entry {
+                path: PathBuf::from(format!("rom{}.ch8", index)),
+                title: format!("ROM {}", index),
+                info: None,
+                platform: Platform::Chip8,
+                rom: vec![0x12, 0x00]
+            })
+            .collect();
+
+        Launcher {
+            entries: entries,
+            selected: 0,
+            top: 0,
+            preview: None,
+            screen: Framebuffer::new(WIDTH, HEIGHT),
+            message: None
+        }
+    }
+
+    #[test]
+    fn fit_leaves_text_that_fits() {
+        assert_eq!(fit("ABCD", 16), "ABCD");
+        assert_eq!(fit("ABCD", 19), "ABCD");
+        assert_eq!(fit("", 0), "");
+    }
+
+    #[test]
+    fn fit_marks_text_it_cuts() {
+        assert_eq!(fit("ABCDE", 16), "ABC\u{2026}");
+        assert_eq!(fit("ABCDE", 4), "\u{2026}");
+    }
+
+    #[test]
+    fn wrap_breaks_at_spaces() {
+        assert_eq!(wrap("ONE TWO THREE", 28), vec!["ONE TWO", "THREE"]);
+        assert_eq!(wrap("  ONE   TWO ", 28), vec!["ONE TWO"]);
+        assert_eq!(wrap("", 28), Vec::<String>::new());
+    }
+
+    #[test]
+    fn wrap_puts_long_words_on_their_own_lines() {
+        assert_eq!(wrap("A LONGERWORD B", 16), vec!["A", "LONGERWORD", "B"]);
+    }
+
+    #[test]
+    fn clip_marks_the_last_line_kept() {
+        let lines = wrap("ONE TWO THREE FOUR FIVE", 16);
+
+        assert_eq!(clip(lines.clone(), 5, 16), lines);
+        assert_eq!(clip(lines.clone(), 2, 16), vec!["ONE", "TWO\u{2026}"]);
+        assert_eq!(clip(vec![String::from("ABCD"), String::from("E")], 1, 16), vec!["ABC\u{2026}"]);
+    }
+
+    #[test]
+    fn select_scrolls_the_selection_into_view() {
+        let mut launcher = with_entries(20);
+
+        launcher.select(LIST_ROWS - 1);
+        assert_eq!(launcher.top, 0);
+
+        launcher.select(LIST_ROWS);
+        assert_eq!(launcher.top, 1);
+
+        launcher.select(15);
+        assert_eq!(launcher.top, 15 + 1 - LIST_ROWS);
+
+        launcher.select(8);
+        assert_eq!(launcher.top, 6);
+
+        launcher.select(2);
+        assert_eq!(launcher.top, 2);
+    }
+
+    #[test]
+    fn select_stops_at_the_last_entry() {
+        let mut launcher = with_entries(20);
+
+        launcher.select(100);
+        assert_eq!(launcher.selected, 19);
+        assert_eq!(launcher.top, 20 - LIST_ROWS);
+
+        let mut short = with_entries(3);
+
+        short.select(5);
+        assert_eq!(short.selected, 2);
+        assert_eq!(short.top, 0);
+    }
+
+    #[test]
+    fn select_clears_the_message_when_it_moves() {
+        let mut launcher = with_entries(3);
+
+        launcher.set_message("GAME OVER");
+        launcher.select(0);
+        assert_eq!(launcher.message, Some(String::from("GAME OVER")));
+
+        launcher.select(1);
+        assert_eq!(launcher.message, None);
+    }
+}
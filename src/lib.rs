@@ -0,0 +1,40 @@
+extern crate rand;
+#[cfg(feature = "sdl")]
+extern crate sdl2;
+
+pub mod cheats;
+pub mod config;
+pub mod error;
+pub mod mmu;
+pub mod cpu;
+pub mod coverage;
+pub mod debugger;
+pub mod detect;
+pub mod disassembler;
+pub mod crt;
+pub mod emulator;
+pub mod font;
+pub mod framebuffer;
+pub mod gdb;
+pub mod gif;
+pub mod frontend;
+pub mod hotkeys;
+pub mod launcher;
+pub mod options;
+pub mod overlay;
+pub mod palette;
+pub mod phosphor;
+pub mod platform;
+pub mod profiler;
+pub mod png;
+pub mod quirks;
+pub mod recording;
+pub mod romdb;
+pub mod screenshot;
+pub mod sha1;
+pub mod timing;
+#[cfg(feature = "sdl")]
+pub mod gfx;
+pub mod headless;
+pub mod keypad;
+pub mod term_gfx;
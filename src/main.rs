@@ -1,34 +1,320 @@
-extern crate rand;
-extern crate sdl2;
+extern crate rustychip8;
 
-mod mmu;
-mod cpu;
-mod gfx;
-mod term_gfx;
+use std::env;
+use std::path::Path;
+use std::process;
 
-use std::time::Duration;
-use std::thread;
-
-use mmu::Mmu;
-use cpu::Cpu;
-use gfx::Gfx;
+use rustychip8::cheats::{self, Cheats};
+use rustychip8::config::{self, Config};
+use rustychip8::mmu::{self, Mmu};
+use rustychip8::cpu::Cpu;
+use rustychip8::debugger::Debugger;
+use rustychip8::detect::{self, Detection};
+use rustychip8::error::Error;
+use rustychip8::gdb::GdbStub;
+use rustychip8::emulator::Emulator;
+use rustychip8::frontend::Frontend;
+#[cfg(feature = "sdl")]
+use rustychip8::gfx::Gfx;
+use rustychip8::headless::Headless;
+use rustychip8::launcher::{Entry, Launcher};
+use rustychip8::term_gfx::TermGfx;
+use rustychip8::options::{FrontendKind, Options};
+use rustychip8::phosphor::Phosphor;
+use rustychip8::recording::Recorder;
+use rustychip8::platform::Platform;
+use rustychip8::romdb::RomInfo;
+use rustychip8::screenshot::{Renderer, Screenshots};
+use rustychip8::sha1;
+use rustychip8::timing::Scheduler;
 
 fn main() {
-    let filename = String::from("./roms/UFO");
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let options = match Options::parse(args.iter().cloned()) {
+        Ok(options) => options,
+        Err(message) => exit_with(&message)
+    };
+
+    if Path::new(&options.rom).is_dir() {
+        browse(options, &args);
+        return;
+    }
+
+    let rom = match mmu::read_rom(options.rom.clone()) {
+        Ok(rom) => rom,
+        Err(e)  => exit_with(&format!("Error: {}", e))
+    };
+
+    // The guess is made from the file as read, so there's one even for a
+    // ROM too big to load, which is most likely for a bigger machine.
+    let detection = detect::detect(&rom);
+
+    let mut mmu = Mmu::new();
+
+    if let Err(e) = mmu.load_rom(&rom) {
+        if let Error::RomTooLarge(_) = e {
+            describe_guess(&detection);
+        }
+
+        exit_with(&format!("Error: {}", e));
+    }
+
+    describe_rom(mmu.rom_info(), &detection);
+
+    let defaults = rom_defaults(mmu.rom_info(), &detection);
+
+    let options = match apply_settings(options, &args, &rom, defaults) {
+        Ok(options) => options,
+        Err(message) => exit_with(&message)
+    };
+
+    let mut emulator = match build_emulator(&options, mmu, &rom) {
+        Ok(emulator) => emulator,
+        Err(message) => exit_with(&message)
+    };
+
+    // The frontend is dropped before exiting so the terminal gets restored.
+    let result = {
+        let mut frontend = match open_frontend(&options) {
+            Ok(frontend) => frontend,
+            Err(e)       => exit_with(&format!("Error: {}", e))
+        };
+
+        emulator.run(&mut *frontend)
+    };
+
+    for message in emulator.take_messages() {
+        println!("{}", message);
+    }
+
+    if let Err(e) = result {
+        exit_with(&format!("Error: {}", e));
+    }
+}
+
+// Shows a menu of the ROMs in the directory named on the command line and
+// runs the chosen one, coming back to the menu when it ends. The frontend is
+// opened once, so its settings (scale, palette, keymap and so on) come from
+// the command line and the config file's global settings; the rest are
+// worked out afresh for each game.
+fn browse(options: Options, args: &[String]) {
+    let mut launcher = match Launcher::open(&options.rom) {
+        Ok(launcher) => launcher,
+        Err(e)       => exit_with(&format!("Error: {}", e))
+    };
+
+    let mut frontend = match open_frontend(&options) {
+        Ok(frontend) => frontend,
+        Err(e)       => exit_with(&format!("Error: {}", e))
+    };
+
+    while let Some(index) = launcher.run(&mut *frontend) {
+        // Every game gets a new machine, so nothing carries over from the
+        // last one.
+        let result = launch(args, launcher.entry(index)).and_then(|mut emulator| {
+            let result = emulator.run(&mut *frontend).map_err(|e| format!("Error: {}", e));
+
+            result.map(|_| emulator.take_messages().join("; "))
+        });
+
+        // What was saved when the game ended shows until the selection moves,
+        // unless the game stopped with an error.
+        match result {
+            Ok(ref messages) if messages.is_empty() => { },
+            Ok(message) | Err(message)              => launcher.set_message(&message)
+        }
+
+        if frontend.quit_requested() {
+            break;
+        }
+    }
+}
+
+// Builds a machine for a ROM from the browser, as if it had been named on the
+// command line in place of the directory.
+fn launch(args: &[String], entry: &Entry) -> Result<Emulator, String> {
+    let path = entry.path.to_string_lossy().into_owned();
+
+    let args: Vec<String> = args.iter().cloned().chain(Some(path)).collect();
+
+    let options = try!(Options::parse(args.iter().cloned()));
 
     let mut mmu = Mmu::new();
 
-    mmu.load_rom(filename);
+    try!(mmu.load_rom(&entry.rom).map_err(|e| format!("Error: {}", e)));
+
+    let defaults = rom_defaults(mmu.rom_info(), &detect::detect(&entry.rom));
+    let options  = try!(apply_settings(options, &args, &entry.rom, defaults));
+
+    build_emulator(&options, mmu, &entry.rom)
+}
+
+fn describe_rom(info: Option<&RomInfo>, detection: &Detection) {
+    let info = match info {
+        Some(info) => info,
+        None       => return describe_guess(detection)
+    };
+
+    match info.author {
+        Some(author) => println!("{} by {} ({})", info.title, author, info.platform),
+        None         => println!("{} ({})", info.title, info.platform)
+    }
+}
+
+fn describe_guess(detection: &Detection) {
+    println!("Unknown ROM; it looks like {} ({:.0}% sure):", detection.platform, detection.confidence * 100.0);
+
+    for reason in detection.reasons.iter() {
+        println!("  it {}", reason);
+    }
+
+    if detection.platform != Platform::Chip8 {
+        println!("Only CHIP-8 is emulated, so expect trouble.");
+    }
+}
+
+// Known ROMs bring their own settings; for the rest, go with a guess if it's
+// a good one.
+fn rom_defaults(info: Option<&RomInfo>, detection: &Detection) -> Vec<String> {
+    if let Some(info) = info {
+        return info.args();
+    }
+
+    if detection.confidence < detect::CONFIDENT {
+        return Vec::new();
+    }
+
+    detection.quirks.args()
+}
+
+// Reparses the command line after the ROM's default settings and the config
+// file's settings for it, so the config file beats the defaults and the
+// command line has the last word.
+fn apply_settings(options: Options, args: &[String], rom: &[u8], defaults: Vec<String>) -> Result<Options, String> {
+    let mut settings = defaults;
+
+    if let Some(path) = options.config.clone().or_else(config::default_path) {
+        let config = try!(Config::load(&path));
+
+        let file_name = Path::new(&options.rom).file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or(options.rom.clone());
+
+        let config_args = config.args_for(&file_name, &sha1::hex_digest(rom));
+
+        // Check the file's settings alone first, so mistakes in it are blamed
+        // on it rather than the command line.
+        let rom_arg = Some(options.rom.clone());
+
+        try!(Options::parse(config_args.iter().cloned().chain(rom_arg))
+            .map_err(|e| format!("Error in {}: {}", config.path(), e)));
+
+        settings.extend(config_args);
+    }
+
+    if settings.is_empty() {
+        return Ok(options);
+    }
+
+    settings.extend(args.iter().cloned());
+
+    Options::parse(settings.into_iter())
+}
+
+fn build_emulator(options: &Options, mut mmu: Mmu, rom: &[u8]) -> Result<Emulator, String> {
+    mmu.set_policy(options.memory_policy);
 
     let mut cpu = Cpu::new(mmu);
 
-    let (mut gfx, sdl) = Gfx::new(1);
+    cpu.set_quirks(options.quirks);
+
+    let scheduler = Scheduler::new(options.timing);
+
+    let mut emulator = Emulator::new(cpu, scheduler);
+
+    emulator.set_rom(rom.to_vec());
+    emulator.overlay_mut().set_controls(options.hotkeys.describe());
+
+    if options.phosphor_frames > 0 {
+        emulator.set_phosphor(Some(Phosphor::new(options.phosphor_frames)));
+    }
+
+    let rom_name = Path::new(&options.rom).file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or(String::from("screenshot"));
 
-    loop {
-        cpu.step();
+    emulator.overlay_mut().set_visible(options.show_overlay);
 
-        gfx.composite(cpu.video_buffer());
+    if options.debug || options.gdb_port.is_some() {
+        emulator.set_debugger(Some(Debugger::new(options.scale)));
+    }
+
+    let cheats = match options.cheats.clone().or_else(|| cheats::default_path(&sha1::hex_digest(rom))) {
+        Some(path) => try!(Cheats::load(&path)),
+        None       => Cheats::new()
+    };
+
+    if !cheats.cheats().is_empty() {
+        let list: Vec<String> = cheats.cheats().iter().map(|cheat| cheat.to_string()).collect();
+
+        println!("Cheats from {}: {}", cheats.path().unwrap_or("?"), list.join(", "));
+    }
+
+    emulator.set_cheats(Some(cheats));
+    emulator.set_profile_report(options.profile.clone());
+    emulator.set_coverage_report(options.coverage.clone());
+
+    if let Some(port) = options.gdb_port {
+        let gdb = try!(GdbStub::listen(port).map_err(|e| format!("Can't listen for GDB on port {}: {}", port, e)));
+
+        println!("Waiting for GDB on 127.0.0.1:{}", gdb.port().unwrap_or(port));
+
+        emulator.set_gdb(Some(gdb));
+    }
+
+    // Frames with the overlay drawn over them come already scaled up.
+    let renderer = if options.overlay_in_captures {
+        emulator.set_overlay_in_captures(Some(options.scale));
+        Renderer::new(options.palette, 1)
+    } else {
+        Renderer::new(options.palette, options.scale)
+    };
+
+    emulator.set_screenshots(Some(Screenshots::new(renderer, &rom_name)));
+    emulator.set_screenshot_at(options.screenshot_at);
 
-        thread::sleep(Duration::new(0, 1200000));
+    if let Some(ref path) = options.record {
+        let recorder = try!(Recorder::start(path, renderer).map_err(|e| format!("Error: {}", e)));
+
+        emulator.start_recording(recorder);
+    }
+
+    Ok(emulator)
+}
+
+fn open_frontend(options: &Options) -> Result<Box<dyn Frontend>, String> {
+    match options.frontend {
+        FrontendKind::Sdl      => open_sdl(options),
+        FrontendKind::Terminal => Ok(Box::new(TermGfx::new(options.terminal_mode, options.palette, options.keymap, options.hotkeys.clone()))),
+        FrontendKind::Headless => Ok(Box::new(Headless::new(options.max_frames)))
     }
 }
+
+#[cfg(feature = "sdl")]
+fn open_sdl(options: &Options) -> Result<Box<dyn Frontend>, String> {
+    let gfx = try!(Gfx::new(options.scale, options.palette, options.crt, options.keymap, options.hotkeys.clone(),
+                            options.debug));
+
+    Ok(Box::new(gfx))
+}
+
+#[cfg(not(feature = "sdl"))]
+fn open_sdl(_options: &Options) -> Result<Box<dyn Frontend>, String> {
+    Err(String::from("built without SDL support; try --frontend terminal"))
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
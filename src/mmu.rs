@@ -1,17 +1,55 @@
 use std::vec::Vec;
-use std::io::Error;
+use std::io;
 use std::io::Read;
 use std::fs::File;
+use std::str::FromStr;
+
+use error::Error;
+use romdb::{self, RomInfo};
+use sha1;
+
+pub const MEMORY_SIZE: usize = 4096;
+pub const ROM_START: usize = 0x200;
+pub const MAX_ROM_SIZE: usize = MEMORY_SIZE - ROM_START;
+
+// What happens when an instruction touches memory past 0xFFF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutOfBoundsPolicy {
+    Wrap,
+    Clamp,
+    Error
+}
+
+impl FromStr for OutOfBoundsPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutOfBoundsPolicy, String> {
+        match s {
+            "wrap"  => Ok(OutOfBoundsPolicy::Wrap),
+            "clamp" => Ok(OutOfBoundsPolicy::Clamp),
+            "error" => Ok(OutOfBoundsPolicy::Error),
+            _       => Err(format!("Unknown memory policy: {} (expected wrap, clamp or error)", s))
+        }
+    }
+}
 
 pub struct Mmu {
     memory: Vec<u8>,
-    fontset: Vec<u8>
+    fontset: Vec<u8>,
+    policy: OutOfBoundsPolicy,
+    rom_info: Option<&'static RomInfo>
 }
 
 impl Mmu {
     pub fn new() -> Mmu {
+        Mmu::with_policy(OutOfBoundsPolicy::Wrap)
+    }
+
+    pub fn with_policy(policy: OutOfBoundsPolicy) -> Mmu {
         let mut mmu = Mmu {
-            memory:  vec![0; 4096],
+            policy:  policy,
+            rom_info: None,
+            memory:  vec![0; MEMORY_SIZE],
             fontset: vec![
               0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
               0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -38,40 +76,76 @@ impl Mmu {
     }
 
     pub fn reset(&mut self) {
-        self.memory = vec![0; 4096];
+        self.memory = vec![0; MEMORY_SIZE];
+        self.rom_info = None;
         for (i, value) in self.fontset.iter().enumerate() {
             self.memory[i] = *value;
         }
     }
 
-    pub fn write_byte(&mut self, address: usize, value: u8) {
+    pub fn policy(&self) -> OutOfBoundsPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: OutOfBoundsPolicy) {
+        self.policy = policy;
+    }
+
+    // The database entry for the loaded ROM, if it's a known one.
+    pub fn rom_info(&self) -> Option<&'static RomInfo> {
+        self.rom_info
+    }
+
+    pub fn write_byte(&mut self, address: usize, value: u8) -> Result<(), Error> {
+        let address = try!(self.resolve(address));
         self.memory[address] = value;
+
+        Ok(())
     }
 
-    pub fn read_byte(&self, address: usize) -> u8 {
-        self.memory[address]
+    pub fn read_byte(&self, address: usize) -> Result<u8, Error> {
+        let address = try!(self.resolve(address));
+
+        Ok(self.memory[address])
+    }
+
+    pub fn read_word(&self, address: usize) -> Result<u16, Error> {
+        let high = try!(self.read_byte(address)) as u16;
+        let low  = try!(self.read_byte(address + 1)) as u16;
+
+        Ok(high << 8 | low)
     }
 
-    pub fn read_word(&self, address: usize) -> u16 {
-        (self.read_byte(address) as u16) << 8 | (self.read_byte(address + 1) as u16)
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Error> {
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(Error::RomTooLarge(rom.len()));
+        }
+
+        for (i, value) in rom.iter().enumerate() {
+            self.memory[i + ROM_START] = *value;
+        }
+
+        self.rom_info = romdb::lookup(&sha1::hex_digest(rom));
+
+        Ok(())
     }
 
-    pub fn load_rom(&mut self, filename: String) {
-        // println!("Loading ROM: #{}", filename);
-        match read_rom(filename) {
-            Ok(rom) => {
-                for (i, value) in rom.iter().enumerate() {
-                    self.memory[i + 512] = *value;
-                }
-            },
-            Err(e) => {
-                panic!("Error: {}", e);
-            }
+    fn resolve(&self, address: usize) -> Result<usize, Error> {
+        let size = self.memory.len();
+
+        if address < size {
+            return Ok(address);
+        }
+
+        match self.policy {
+            OutOfBoundsPolicy::Wrap  => Ok(address % size),
+            OutOfBoundsPolicy::Clamp => Ok(size - 1),
+            OutOfBoundsPolicy::Error => Err(Error::MemoryOutOfBounds(address))
         }
     }
 }
 
-fn read_rom(filename: String) -> Result<Vec<u8>, Error> {
+pub fn read_rom(filename: String) -> Result<Vec<u8>, io::Error> {
     let mut file   = try!(File::open(filename));
     let mut buffer = Vec::new();
 
@@ -79,3 +153,81 @@ fn read_rom(filename: String) -> Result<Vec<u8>, Error> {
 
     Ok(buffer)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Error;
+
+    fn mmu_with(policy: OutOfBoundsPolicy) -> Mmu {
+        let mut mmu = Mmu::with_policy(policy);
+
+        mmu.write_byte(0x000, 0xAA).unwrap();
+        mmu.write_byte(0x001, 0xBB).unwrap();
+        mmu.write_byte(0xFFF, 0xCC).unwrap();
+
+        mmu
+    }
+
+    #[test]
+    fn reaches_the_last_byte_under_every_policy() {
+        for &policy in [OutOfBoundsPolicy::Wrap, OutOfBoundsPolicy::Clamp, OutOfBoundsPolicy::Error].iter() {
+            assert_eq!(mmu_with(policy).read_byte(0xFFF), Ok(0xCC));
+        }
+    }
+
+    #[test]
+    fn wraps_past_the_end_to_the_start() {
+        let mut mmu = mmu_with(OutOfBoundsPolicy::Wrap);
+
+        assert_eq!(mmu.read_byte(0x1000), Ok(0xAA));
+        assert_eq!(mmu.read_byte(0x1001), Ok(0xBB));
+        assert_eq!(mmu.read_word(0xFFF), Ok(0xCCAA));
+
+        mmu.write_byte(0x1001, 0x11).unwrap();
+        assert_eq!(mmu.read_byte(0x001), Ok(0x11));
+    }
+
+    #[test]
+    fn clamps_past_the_end_to_the_last_byte() {
+        let mut mmu = mmu_with(OutOfBoundsPolicy::Clamp);
+
+        assert_eq!(mmu.read_byte(0x1000), Ok(0xCC));
+        assert_eq!(mmu.read_byte(0x1FFF), Ok(0xCC));
+        assert_eq!(mmu.read_word(0xFFF), Ok(0xCCCC));
+
+        mmu.write_byte(0x1000, 0x11).unwrap();
+        assert_eq!(mmu.read_byte(0xFFF), Ok(0x11));
+        assert_eq!(mmu.read_byte(0x000), Ok(0xAA));
+    }
+
+    #[test]
+    fn refuses_accesses_past_the_end() {
+        let mut mmu = mmu_with(OutOfBoundsPolicy::Error);
+
+        assert_eq!(mmu.read_byte(0x1000), Err(Error::MemoryOutOfBounds(0x1000)));
+        assert_eq!(mmu.read_word(0xFFF), Err(Error::MemoryOutOfBounds(0x1000)));
+        assert_eq!(mmu.write_byte(0x1000, 0x11), Err(Error::MemoryOutOfBounds(0x1000)));
+        assert_eq!(mmu.read_byte(0x000), Ok(0xAA));
+    }
+
+    #[test]
+    fn loads_roms_that_fill_memory_exactly() {
+        let mut rom = vec![0; MAX_ROM_SIZE];
+        rom[MAX_ROM_SIZE - 1] = 0x42;
+
+        let mut mmu = Mmu::new();
+
+        assert_eq!(MAX_ROM_SIZE, 3584);
+        assert_eq!(mmu.load_rom(&rom), Ok(()));
+        assert_eq!(mmu.read_byte(0xFFF), Ok(0x42));
+    }
+
+    #[test]
+    fn rejects_roms_a_byte_too_large() {
+        let mut mmu = Mmu::new();
+
+        assert_eq!(mmu.load_rom(&vec![0; MAX_ROM_SIZE + 1]), Err(Error::RomTooLarge(3585)));
+        assert_eq!(mmu.read_byte(ROM_START), Ok(0));
+    }
+}
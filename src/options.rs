@@ -0,0 +1,353 @@
+use std::str::FromStr;
+use std::vec::Vec;
+
+use crt::CrtSettings;
+use hotkeys::Hotkeys;
+use keypad::Keymap;
+use mmu::OutOfBoundsPolicy;
+use palette::Palette;
+use quirks::Quirks;
+use term_gfx::TermMode;
+use timing::Timing;
+
+pub const DEFAULT_IPS: u32 = 840;
+pub const DEFAULT_SCALE: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontendKind {
+    Sdl,
+    Terminal,
+    Headless
+}
+
+impl FromStr for FrontendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FrontendKind, String> {
+        match s {
+            "sdl"      => Ok(FrontendKind::Sdl),
+            "terminal" => Ok(FrontendKind::Terminal),
+            "headless" => Ok(FrontendKind::Headless),
+            _          => Err(format!("Unknown frontend: {} (expected sdl, terminal or headless)", s))
+        }
+    }
+}
+
+pub const USAGE: &'static str = "Usage: rustychip8 [options] <rom or directory>
+
+Given a directory, shows a menu of the ROMs in it: the arrow keys choose one,
+Enter plays it and Escape goes back to the menu.
+
+Options:
+    --config <file>               read defaults from this TOML file (default:
+                                  ~/.config/rustychip8/config.toml). Keys are these
+                                  options without the dashes, e.g. palette = \"amber\";
+                                  [rom.\"NAME\"] or [rom.<sha1>] tables apply to one ROM,
+                                  and options given here override them all
+    --frontend <sdl|terminal|headless>
+                                  where to draw the display and read keys (default: sdl)
+    --scale <n>                   window pixels per CHIP-8 pixel (default: 10)
+    --crt <effects>               software CRT effects: all, or a comma separated list
+                                  of scanlines, gaps, bloom and curvature, each with
+                                  an optional strength from 0 to 1, e.g. scanlines=0.5
+                                  (default: off)
+    --frames <n>                  quit after n frames (default: run until quit)
+    --screenshot-at-frame <n>     save a PNG of frame n, named after the ROM; F12 saves
+                                  one at any time in the SDL frontend
+    --record <file.gif|file.y4m>  record every frame to an animated GIF or a raw Y4M
+                                  stream; F10 starts and stops a GIF in the SDL frontend
+    --palette <theme|colors>      classic, amber, green, gameboy or lcd, or up to four
+                                  comma separated hex colors: background, foreground,
+                                  then the XO-CHIP plane colors (default: classic)
+    --phosphor <frames>           fade pixels out over this many frames to hide
+                                  flicker (default: 0, off)
+    --terminal-mode <halfblock|braille>
+                                  how the terminal frontend packs pixels (default: halfblock)
+    --keymap <keys>               the keyboard keys for CHIP-8 keys 0 to F, in order
+                                  (default: x123qweasdzc4rfv)
+    --hotkeys <bindings>          rebind the emulator's own keys with a comma separated
+                                  list of command=key, e.g. pause=Space,fast-forward=F3.
+                                  F1 lists the commands and their keys: Escape, P
+                                  (pause), N (next frame), Tab (fast forward), = and -
+                                  (speed), F6 and F5 (soft and hard reset), F12, F10,
+                                  and F2 to show or hide the overlay
+    --no-overlay                  start with the overlay of messages, speed and quirks
+                                  hidden (--overlay shows it)
+    --overlay-in-captures         draw the overlay into screenshots and recordings too
+                                  (--no-overlay-in-captures doesn't)
+    --debug                       start paused, with panels beside the game showing the
+                                  disassembly, registers, call stack, memory around I
+                                  and keypad. F7 steps one instruction, F9 sets a
+                                  breakpoint on the line picked with Up and Down or a
+                                  click, and PageUp and PageDown scroll the memory
+                                  (--no-debug doesn't)
+    --gdb <port>                  start paused, serving the GDB remote protocol on this
+                                  port of localhost so GDB or another debugger can read
+                                  and write registers and memory, set breakpoints, step
+                                  and continue
+    --cheats <file>               where to read frozen addresses and registers from and
+                                  save them to (default: a file named after the ROM's
+                                  SHA-1 under ~/.config/rustychip8/cheats). F3 starts a
+                                  search of memory, F4, F8, ] and [ keep the bytes that
+                                  changed, stayed the same, went up or went down since
+                                  the last step, and F11 freezes the one left
+    --profile <file>              count what the program runs and write a report here
+                                  when it ends: the busiest addresses, how often each
+                                  instruction is used, the call graph, and time spent
+                                  waiting for keys and on the delay timer
+    --coverage <file.png|file>    record which bytes are run as code, read as data or
+                                  written, and save a heatmap of memory or an annotated
+                                  listing of the ROM when it ends
+    --memory <wrap|clamp|error>   what to do with accesses past 0xFFF (default: wrap)
+    --wrap-sprites                wrap sprites around the screen edges instead of clipping
+    --display-wait                stall after each draw until the next 60 Hz frame
+    --shift-vy                    8xy6 and 8xyE shift VY into VX, as on the VIP
+    --load-store-i                Fx55 and Fx65 advance I past the registers, as on the VIP
+    --no-wrap-sprites, --no-display-wait, --no-shift-vy, --no-load-store-i
+                                  turn a quirk off, over the config file or a known ROM's
+                                  settings; where a quirk is given more than once, the
+                                  last one wins
+    --timing <ips|vip>            instructions per second, or vip to charge each
+                                  instruction its COSMAC VIP cycles (default: 840)
+    -h, --help                    print this message";
+
+// The options that turn something on, each with a --no- form that turns it
+// off.
+pub const FLAGS: [&'static str; 7] = [
+    "--overlay", "--overlay-in-captures", "--debug",
+    "--wrap-sprites", "--display-wait", "--shift-vy", "--load-store-i"
+];
+
+// Whether an option is a flag, in either form, rather than taking a value.
+pub fn is_flag(option: &str) -> bool {
+    let flag = if option.starts_with("--no-") { format!("--{}", &option[5..]) } else { option.to_string() };
+
+    FLAGS.contains(&flag.as_str())
+}
+
+pub struct Options {
+    pub rom: String,
+    pub config: Option<String>,
+    pub frontend: FrontendKind,
+    pub terminal_mode: TermMode,
+    pub max_frames: Option<u64>,
+    pub screenshot_at: Option<u64>,
+    pub record: Option<String>,
+    pub scale: usize,
+    pub crt: CrtSettings,
+    pub palette: Palette,
+    pub keymap: Keymap,
+    pub hotkeys: Hotkeys,
+    pub show_overlay: bool,
+    pub overlay_in_captures: bool,
+    pub debug: bool,
+    pub gdb_port: Option<u16>,
+    pub cheats: Option<String>,
+    pub profile: Option<String>,
+    pub coverage: Option<String>,
+    pub phosphor_frames: u32,
+    pub memory_policy: OutOfBoundsPolicy,
+    pub quirks: Quirks,
+    pub timing: Timing
+}
+
+impl Options {
+    pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+        let mut rom             = None;
+        let mut config          = None;
+        let mut frontend        = FrontendKind::Sdl;
+        let mut terminal_mode   = TermMode::HalfBlock;
+        let mut max_frames      = None;
+        let mut screenshot_at   = None;
+        let mut record          = None;
+        let mut scale           = DEFAULT_SCALE;
+        let mut crt             = CrtSettings::off();
+        let mut palette         = Palette::classic();
+        let mut keymap          = Keymap::new();
+        let mut hotkeys         = Hotkeys::new();
+        let mut show_overlay    = true;
+        let mut capture_overlay = false;
+        let mut debug           = false;
+        let mut gdb_port        = None;
+        let mut cheats          = None;
+        let mut profile         = None;
+        let mut coverage        = None;
+        let mut phosphor_frames = 0;
+        let mut memory_policy   = OutOfBoundsPolicy::Wrap;
+        let mut quirks          = Quirks::new();
+        let mut timing          = Timing::Flat(DEFAULT_IPS);
+
+        let args: Vec<String> = args.collect();
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--config" => {
+                    config = Some(try!(value_for(arg, iter.next())).to_string());
+                },
+                "--frontend" => {
+                    frontend = try!(try!(value_for(arg, iter.next())).parse());
+                },
+                "--terminal-mode" => {
+                    terminal_mode = try!(try!(value_for(arg, iter.next())).parse());
+                },
+                "--frames" => {
+                    max_frames = Some(try!(parse_number(arg, iter.next())));
+                },
+                "--scale" => {
+                    scale = try!(parse_number(arg, iter.next()));
+
+                    if scale == 0 {
+                        return Err(String::from("--scale must be at least 1"));
+                    }
+                },
+                "--crt" => {
+                    crt = try!(try!(value_for(arg, iter.next())).parse());
+                },
+                "--screenshot-at-frame" => {
+                    screenshot_at = Some(try!(parse_number(arg, iter.next())));
+                },
+                "--record" => {
+                    record = Some(try!(value_for(arg, iter.next())).to_string());
+                },
+                "--palette" => {
+                    palette = try!(try!(value_for(arg, iter.next())).parse());
+                },
+                "--keymap" => {
+                    keymap = try!(try!(value_for(arg, iter.next())).parse());
+                },
+                "--hotkeys" => {
+                    hotkeys = try!(try!(value_for(arg, iter.next())).parse());
+                },
+                "--phosphor" => {
+                    phosphor_frames = try!(parse_number(arg, iter.next()));
+                },
+                "--memory" => {
+                    memory_policy = try!(try!(value_for(arg, iter.next())).parse());
+                },
+                "--timing" => {
+                    timing = try!(try!(value_for(arg, iter.next())).parse());
+                },
+                "--overlay" | "--no-overlay" => {
+                    show_overlay = arg == "--overlay";
+                },
+                "--overlay-in-captures" | "--no-overlay-in-captures" => {
+                    capture_overlay = arg == "--overlay-in-captures";
+                },
+                "--debug" | "--no-debug" => {
+                    debug = arg == "--debug";
+                },
+                "--cheats" => {
+                    cheats = Some(try!(value_for(arg, iter.next())).to_string());
+                },
+                "--profile" => {
+                    profile = Some(try!(value_for(arg, iter.next())).to_string());
+                },
+                "--coverage" => {
+                    coverage = Some(try!(value_for(arg, iter.next())).to_string());
+                },
+                "--gdb" => {
+                    gdb_port = Some(try!(parse_number(arg, iter.next())));
+                },
+                "--wrap-sprites" | "--no-wrap-sprites" => {
+                    quirks.wrap_sprites = arg == "--wrap-sprites";
+                },
+                "--display-wait" | "--no-display-wait" => {
+                    quirks.display_wait = arg == "--display-wait";
+                },
+                "--shift-vy" | "--no-shift-vy" => {
+                    quirks.shift_vy = arg == "--shift-vy";
+                },
+                "--load-store-i" | "--no-load-store-i" => {
+                    quirks.load_store_i = arg == "--load-store-i";
+                },
+                "-h" | "--help" => {
+                    return Err(String::from(USAGE));
+                },
+                _ if arg.starts_with("-") => {
+                    return Err(format!("Unknown option: {}\n\n{}", arg, USAGE));
+                },
+                _ => {
+                    rom = Some(arg.clone());
+                }
+            }
+        }
+
+        // A key can't press a keypad key and give a command at once.
+        for key in 0..16 {
+            let c = keymap.char_for(key);
+
+            if hotkeys.command_for(&c.to_string()).is_some() {
+                return Err(format!("{} is both keypad key {:X} and a hotkey; move one with --keymap or --hotkeys",
+                                   c.to_ascii_uppercase(), key));
+            }
+        }
+
+        match rom {
+            Some(rom) => {
+                Ok(Options {
+                    rom: rom,
+                    config: config,
+                    frontend: frontend,
+                    terminal_mode: terminal_mode,
+                    max_frames: max_frames,
+                    screenshot_at: screenshot_at,
+                    record: record,
+                    scale: scale,
+                    crt: crt,
+                    palette: palette,
+                    keymap: keymap,
+                    hotkeys: hotkeys,
+                    show_overlay: show_overlay,
+                    overlay_in_captures: capture_overlay,
+                    debug: debug,
+                    gdb_port: gdb_port,
+                    cheats: cheats,
+                    profile: profile,
+                    coverage: coverage,
+                    phosphor_frames: phosphor_frames,
+                    memory_policy: memory_policy,
+                    quirks: quirks,
+                    timing: timing
+                })
+            },
+            None => Err(String::from(USAGE))
+        }
+    }
+}
+
+fn value_for<'a>(option: &str, value: Option<&'a String>) -> Result<&'a str, String> {
+    match value {
+        Some(value) => Ok(value.as_str()),
+        None        => Err(format!("Missing value for {}", option))
+    }
+}
+
+fn parse_number<T: FromStr>(option: &str, value: Option<&String>) -> Result<T, String> {
+    let value = try!(value_for(option, value));
+
+    value.parse().map_err(|_| format!("Invalid value for {}: {}", option, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn rejects_hotkeys_on_keypad_keys() {
+        assert_eq!(parse(&["--hotkeys", "pause=q", "PONG"]).err().unwrap(),
+                   "Q is both keypad key 4 and a hotkey; move one with --keymap or --hotkeys");
+        assert_eq!(parse(&["--keymap", "x123qweasdzc4rfp", "PONG"]).err().unwrap(),
+                   "P is both keypad key F and a hotkey; move one with --keymap or --hotkeys");
+    }
+
+    #[test]
+    fn allows_hotkeys_moved_out_of_the_way() {
+        assert!(parse(&["--hotkeys", "pause=q", "--keymap", "x123pweasdzc4rfv", "PONG"]).is_ok());
+        assert!(parse(&["--hotkeys", "pause=Space", "PONG"]).is_ok());
+    }
+}
@@ -0,0 +1,230 @@
+use std::vec::Vec;
+
+use font;
+use framebuffer::{Framebuffer, PIXEL_OFF, PIXEL_ON};
+use timing::FRAMES_PER_SECOND;
+
+// Overlay layers use one brightness besides off, which is see-through, and
+// on, for text: a dimmed backdrop that keeps text readable over the game,
+// letting a quarter of it show through.
+pub const BACKDROP: u8 = 0x80;
+
+// Overlay text is drawn a fifth as large as CHIP-8 pixels, so it stays
+// readable without covering much of the game.
+const SCALE_DIVISOR: usize = 5;
+
+// How long a notification stays up, and how many can be up at once.
+const NOTIFICATION_FRAMES: u32 = 2 * FRAMES_PER_SECOND;
+const MAX_NOTIFICATIONS: usize = 4;
+
+// Gap between text and the edges of the layer.
+const MARGIN: usize = 2;
+
+// The size of an overlay pixel on a display showing CHIP-8 pixels `scale`
+// pixels wide.
+pub fn pixel_size(scale: usize) -> usize {
+    (scale / SCALE_DIVISOR).max(1)
+}
+
+// Text shown over the display without touching the machine's framebuffer:
+// short-lived notifications, what state the emulator is in, how fast it's
+// going and with which quirks, and the list of controls on request. Frontends
+// that can draw it do so with `draw`; others can show the `summary`.
+pub struct Overlay {
+    visible: bool,
+    notifications: Vec<(String, u32)>,
+    controls: Vec<String>,
+    showing_controls: bool,
+    paused: bool,
+    fast_forward: bool,
+    stats: Option<(u32, u64)>,
+    profile: String
+}
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        Overlay {
+            visible: true,
+            notifications: Vec::new(),
+            controls: Vec::new(),
+            showing_controls: false,
+            paused: false,
+            fast_forward: false,
+            stats: None,
+            profile: String::new()
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    // A hidden overlay draws nothing, but keeps track of everything so it's
+    // up to date when shown again.
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    // Shows a message for a couple of seconds.
+    pub fn notify(&mut self, message: &str) {
+        if self.notifications.len() == MAX_NOTIFICATIONS {
+            self.notifications.remove(0);
+        }
+
+        self.notifications.push((message.to_string(), NOTIFICATION_FRAMES));
+    }
+
+    // The lines listed when the controls are shown.
+    pub fn set_controls(&mut self, controls: Vec<String>) {
+        self.controls = controls;
+    }
+
+    pub fn toggle_controls(&mut self) {
+        self.showing_controls = !self.showing_controls;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    // Frames shown and instructions run in the last second.
+    pub fn set_stats(&mut self, fps: u32, ips: u64) {
+        self.stats = Some((fps, ips));
+    }
+
+    // The quirks the machine is running with.
+    pub fn set_profile(&mut self, profile: &str) {
+        self.profile = profile.to_string();
+    }
+
+    // Called once per frame to age the notifications.
+    pub fn tick(&mut self) {
+        for notification in self.notifications.iter_mut() {
+            notification.1 -= 1;
+        }
+
+        self.notifications.retain(|&(_, frames)| frames > 0);
+    }
+
+    // The state and latest notification on one line.
+    pub fn summary(&self) -> String {
+        if !self.visible {
+            return String::new();
+        }
+
+        let mut parts = Vec::new();
+
+        if let Some(state) = self.state() {
+            parts.push(state.to_string());
+        }
+
+        if let Some(&(ref message, _)) = self.notifications.last() {
+            parts.push(message.clone());
+        }
+
+        parts.join("  ")
+    }
+
+    // Draws the overlay into `layer`, which should start out clear.
+    // Notifications go in the top left corner and the state in the top right,
+    // the speed and quirks along the bottom, and the controls in a box in the
+    // middle.
+    pub fn draw(&self, layer: &mut Framebuffer) {
+        if !self.visible {
+            return;
+        }
+
+        let bottom = layer.height().saturating_sub(font::GLYPH_HEIGHT + MARGIN);
+
+        if let Some((fps, ips)) = self.stats {
+            label(layer, MARGIN, bottom, &format!("{} FPS  {} IPS", fps, ips));
+        }
+
+        if !self.profile.is_empty() {
+            let width = font::width_of(&self.profile);
+
+            label(layer, layer.width().saturating_sub(width + MARGIN), bottom, &self.profile);
+        }
+
+        for (line, &(ref message, _)) in self.notifications.iter().enumerate() {
+            label(layer, MARGIN, MARGIN + line * (font::LINE_HEIGHT + 1), message);
+        }
+
+        if let Some(state) = self.state() {
+            let width = font::width_of(state);
+
+            label(layer, layer.width().saturating_sub(width + MARGIN), MARGIN, state);
+        }
+
+        if self.showing_controls && !self.controls.is_empty() {
+            let width  = self.controls.iter().map(|line| font::width_of(line)).max().unwrap_or(0);
+            let height = self.controls.len() * font::LINE_HEIGHT;
+
+            let x = layer.width().saturating_sub(width) / 2;
+            let y = layer.height().saturating_sub(height) / 2;
+
+            font::fill_rect(layer, x.saturating_sub(MARGIN), y.saturating_sub(MARGIN),
+                            width + 2 * MARGIN, height + 2 * MARGIN, BACKDROP);
+
+            for (line, text) in self.controls.iter().enumerate() {
+                font::draw_text(layer, x, y + line * font::LINE_HEIGHT, text, PIXEL_ON);
+            }
+        }
+    }
+
+    // The frame blown up to `scale` times its size with the overlay drawn
+    // over it, as the SDL frontend would show it. For captures that should
+    // include the overlay.
+    pub fn draw_over(&self, frame: &Framebuffer, scale: usize) -> Framebuffer {
+        let mut out = Framebuffer::new(frame.width() * scale, frame.height() * scale);
+
+        let size = pixel_size(scale);
+        let mut layer = Framebuffer::new(out.width() / size, out.height() / size);
+
+        self.draw(&mut layer);
+
+        for y in 0..out.height() {
+            for x in 0..out.width() {
+                let under = frame.pixel(x / scale, y / scale);
+
+                let over = if x / size < layer.width() && y / size < layer.height() {
+                    layer.pixel(x / size, y / size)
+                } else {
+                    PIXEL_OFF
+                };
+
+                let pixel = match over {
+                    PIXEL_OFF => under,
+                    BACKDROP  => under / 4,
+                    _         => PIXEL_ON
+                };
+
+                out.set_pixel(x, y, pixel);
+            }
+        }
+
+        out
+    }
+
+    fn state(&self) -> Option<&'static str> {
+        if self.paused {
+            Some("PAUSED")
+        } else if self.fast_forward {
+            Some("FAST FORWARD")
+        } else {
+            None
+        }
+    }
+}
+
+// Text on a backdrop just big enough for it.
+fn label(layer: &mut Framebuffer, x: usize, y: usize, text: &str) {
+    font::fill_rect(layer, x.saturating_sub(1), y.saturating_sub(1),
+                    font::width_of(text) + 1, font::LINE_HEIGHT + 1, BACKDROP);
+
+    font::draw_text(layer, x, y, text, PIXEL_ON);
+}
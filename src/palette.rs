@@ -0,0 +1,195 @@
+use std::str::FromStr;
+use std::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8
+}
+
+impl Color {
+    pub fn new(r: u8, g: u8, b: u8) -> Color {
+        Color { r: r, g: g, b: b }
+    }
+
+    // Mixes towards `other` by `amount`, from 0 (all self) to 255 (all other).
+    pub fn blend(&self, other: Color, amount: u8) -> Color {
+        let mix = |from: u8, to: u8| {
+            let from = from as u32;
+            let to   = to as u32;
+            let t    = amount as u32;
+
+            ((from * (255 - t) + to * t) / 255) as u8
+        };
+
+        Color::new(mix(self.r, other.r), mix(self.g, other.g), mix(self.b, other.b))
+    }
+}
+
+// Parses "#RRGGBB" or "RRGGBB".
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Color, String> {
+        let hex = s.trim_start_matches('#');
+
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+               .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+        };
+
+        // from_str_radix would take a sign, as in "+1".
+        let digits = hex.chars().all(|c| c.is_digit(16));
+
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) if digits => Ok(Color::new(r, g, b)),
+            _ => Err(format!("Invalid color: {} (expected #RRGGBB)", s))
+        }
+    }
+}
+
+// Display colors: background, foreground, and for XO-CHIP the second plane
+// and the overlap of both planes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Color; 4]
+}
+
+pub const THEMES: [&'static str; 5] = ["classic", "amber", "green", "gameboy", "lcd"];
+
+impl Palette {
+    pub fn classic() -> Palette {
+        Palette::from_rgb([0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555])
+    }
+
+    pub fn named(name: &str) -> Option<Palette> {
+        let colors = match name {
+            "classic" => [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555],
+            "amber"   => [0x1A0F00, 0xFFB000, 0xB37B00, 0x664600],
+            "green"   => [0x001400, 0x33FF33, 0x20A020, 0x105010],
+            "gameboy" => [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F],
+            "lcd"     => [0xC4CFA1, 0x2B2B26, 0x6B7353, 0x8B956D],
+            _         => return None
+        };
+
+        Some(Palette::from_rgb(colors))
+    }
+
+    pub fn background(&self) -> Color {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Color {
+        self.colors[1]
+    }
+
+    // The color for a pixel's brightness, from background at 0 to foreground
+    // at 255.
+    pub fn shade(&self, brightness: u8) -> Color {
+        self.background().blend(self.foreground(), brightness)
+    }
+
+    fn from_rgb(rgb: [u32; 4]) -> Palette {
+        let mut colors = [Color::new(0, 0, 0); 4];
+
+        for (color, value) in colors.iter_mut().zip(rgb.iter()) {
+            *color = Color::new((value >> 16) as u8, (value >> 8) as u8, *value as u8);
+        }
+
+        Palette { colors: colors }
+    }
+}
+
+// Parses a theme name, or up to four comma separated colors that replace the
+// classic theme's in order.
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Palette, String> {
+        if let Some(palette) = Palette::named(s) {
+            return Ok(palette);
+        }
+
+        let colors: Result<Vec<Color>, String> = s.split(',').map(|color| color.trim().parse()).collect();
+
+        match colors {
+            Err(_) if !s.contains(',') && !s.starts_with('#') => {
+                Err(format!("Unknown palette: {} (expected {} or hex colors)", s, THEMES.join(", ")))
+            },
+            Err(e) => Err(e),
+            Ok(ref colors) if colors.len() > 4 => {
+                Err(format!("Too many colors in palette: {} (at most 4)", s))
+            },
+            Ok(colors) => {
+                let mut palette = Palette::classic();
+
+                for (i, color) in colors.into_iter().enumerate() {
+                    palette.colors[i] = color;
+                }
+
+                Ok(palette)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_colors_with_or_without_a_hash() {
+        assert_eq!("#FFB000".parse(), Ok(Color::new(0xFF, 0xB0, 0x00)));
+        assert_eq!("0f380f".parse(), Ok(Color::new(0x0F, 0x38, 0x0F)));
+    }
+
+    #[test]
+    fn rejects_malformed_colors() {
+        for s in ["#FFF", "#FFB0000", "#GGGGGG", "", "#+1+1+1", "#ÿÿÿ"].iter() {
+            assert_eq!(s.parse::<Color>(), Err(format!("Invalid color: {} (expected #RRGGBB)", s)));
+        }
+    }
+
+    #[test]
+    fn parses_theme_names() {
+        for &name in THEMES.iter() {
+            assert_eq!(name.parse(), Ok(Palette::named(name).unwrap()));
+        }
+
+        assert_eq!("amber".parse::<Palette>().unwrap().foreground(), Color::new(0xFF, 0xB0, 0x00));
+    }
+
+    #[test]
+    fn replaces_classic_colors_in_order() {
+        let palette: Palette = "#102030, #405060".parse().unwrap();
+
+        assert_eq!(palette.background(), Color::new(0x10, 0x20, 0x30));
+        assert_eq!(palette.foreground(), Color::new(0x40, 0x50, 0x60));
+        assert_eq!(palette.colors[2..], Palette::classic().colors[2..]);
+
+        let palette: Palette = "#000001".parse().unwrap();
+
+        assert_eq!(palette.background(), Color::new(0, 0, 1));
+        assert_eq!(palette.foreground(), Color::new(0xFF, 0xFF, 0xFF));
+    }
+
+    #[test]
+    fn rejects_unknown_themes_and_too_many_colors() {
+        assert_eq!("sepia".parse::<Palette>(),
+                   Err(String::from("Unknown palette: sepia (expected classic, amber, green, gameboy, lcd or hex colors)")));
+        assert_eq!("#000000,#111111,#222222,#333333,#444444".parse::<Palette>(),
+                   Err(String::from("Too many colors in palette: #000000,#111111,#222222,#333333,#444444 (at most 4)")));
+        assert_eq!("#000000,red".parse::<Palette>(),
+                   Err(String::from("Invalid color: red (expected #RRGGBB)")));
+    }
+
+    #[test]
+    fn shades_from_background_to_foreground() {
+        let palette = Palette::named("amber").unwrap();
+
+        assert_eq!(palette.shade(0), palette.background());
+        assert_eq!(palette.shade(255), palette.foreground());
+        assert_eq!(palette.shade(0x80), Color::new(0x8C, 0x5F, 0x00));
+    }
+}
@@ -0,0 +1,116 @@
+use framebuffer::{Framebuffer, PIXEL_OFF, PIXEL_ON};
+
+// Emulates CRT phosphor persistence: lit pixels show at full brightness, and
+// once turned off fade out over a number of frames instead of vanishing. This
+// hides most of the flicker from games that XOR-draw moving sprites.
+pub struct Phosphor {
+    decay_frames: u32,
+    output: Framebuffer
+}
+
+impl Phosphor {
+    pub fn new(decay_frames: u32) -> Phosphor {
+        Phosphor {
+            decay_frames: decay_frames,
+            output: Framebuffer::new(0, 0)
+        }
+    }
+
+    pub fn decay_frames(&self) -> u32 {
+        self.decay_frames
+    }
+
+    // Blends a frame from the CPU into the persisted image. Must be called
+    // every frame, changed or not, for pixels to keep fading.
+    pub fn apply(&mut self, frame: &Framebuffer) -> &Framebuffer {
+        if self.output.width() != frame.width() || self.output.height() != frame.height() {
+            self.output = Framebuffer::new(frame.width(), frame.height());
+        }
+
+        self.output.mark_clean();
+
+        let step = fade_step(self.decay_frames);
+
+        for y in 0..frame.height() {
+            for x in 0..frame.width() {
+                let value = if frame.is_lit(x, y) {
+                    PIXEL_ON
+                } else {
+                    self.output.pixel(x, y).saturating_sub(step)
+                };
+
+                self.output.set_pixel(x, y, value);
+            }
+        }
+
+        self.output.end_frame();
+
+        &self.output
+    }
+}
+
+// How much brightness an unlit pixel loses each frame.
+fn fade_step(decay_frames: u32) -> u8 {
+    if decay_frames == 0 {
+        return PIXEL_ON - PIXEL_OFF;
+    }
+
+    let range = (PIXEL_ON - PIXEL_OFF) as u32;
+
+    // Rounded up, so pixels are dark by the last frame; anything slower than
+    // a step a frame fades a step a frame.
+    (range.saturating_add(decay_frames - 1) / decay_frames) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lights one pixel for a frame, then returns its brightness over the
+    // frames after it goes out.
+    fn fade(decay_frames: u32, frames: usize) -> Vec<u8> {
+        let mut phosphor = Phosphor::new(decay_frames);
+        let mut frame = Framebuffer::new(2, 1);
+
+        frame.set_pixel(0, 0, PIXEL_ON);
+        assert_eq!(phosphor.apply(&frame).pixel(0, 0), PIXEL_ON);
+
+        frame.set_pixel(0, 0, PIXEL_OFF);
+
+        (0..frames).map(|_| phosphor.apply(&frame).pixel(0, 0)).collect()
+    }
+
+    #[test]
+    fn fades_out_over_the_decay_frames() {
+        assert_eq!(fade(4, 5), vec![191, 127, 63, 0, 0]);
+        assert_eq!(fade(3, 3), vec![170, 85, 0]);
+    }
+
+    #[test]
+    fn goes_dark_at_once_without_decay() {
+        assert_eq!(fade(0, 2), vec![0, 0]);
+        assert_eq!(fade(1, 2), vec![0, 0]);
+    }
+
+    #[test]
+    fn fades_a_step_a_frame_at_most() {
+        assert_eq!(fade_step(255), 1);
+        assert_eq!(fade_step(1000), 1);
+        assert_eq!(fade_step(u32::max_value()), 1);
+        assert_eq!(fade(u32::max_value(), 3), vec![254, 253, 252]);
+    }
+
+    #[test]
+    fn keeps_relit_pixels_at_full_brightness() {
+        let mut phosphor = Phosphor::new(4);
+        let mut frame = Framebuffer::new(1, 1);
+
+        frame.set_pixel(0, 0, PIXEL_ON);
+        phosphor.apply(&frame);
+        frame.set_pixel(0, 0, PIXEL_OFF);
+        phosphor.apply(&frame);
+        frame.set_pixel(0, 0, PIXEL_ON);
+
+        assert_eq!(phosphor.apply(&frame).pixel(0, 0), PIXEL_ON);
+    }
+}
@@ -0,0 +1,22 @@
+use std::fmt;
+
+// The CHIP-8 family member a program was written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    // The original COSMAC VIP interpreter and its 64x32 display.
+    Chip8,
+    // SUPER-CHIP for the HP 48: 128x64 hires mode, scrolling, big font.
+    SuperChip,
+    // XO-CHIP: SUPER-CHIP plus 64 KiB of memory, two bitplanes and audio.
+    XoChip
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Platform::Chip8     => write!(f, "CHIP-8"),
+            Platform::SuperChip => write!(f, "SUPER-CHIP"),
+            Platform::XoChip    => write!(f, "XO-CHIP")
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::io::{self, Write};
+use std::vec::Vec;
+
+// A minimal PNG encoder: 8-bit RGB, no interlacing, compressed with a single
+// fixed-Huffman deflate block. Emulator frames are large areas of flat color,
+// which even a simple matcher squeezes down to almost nothing.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+pub fn write<W: Write>(out: &mut W, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    assert_eq!(rgb.len(), width * height * 3);
+
+    try!(out.write_all(&SIGNATURE));
+
+    let mut header = Vec::new();
+    push_u32(&mut header, width as u32);
+    push_u32(&mut header, height as u32);
+    // Bit depth 8, color type 2 (RGB), default compression, filter and
+    // interlacing.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    try!(write_chunk(out, b"IHDR", &header));
+
+    // Every row starts with its filter type; 0 leaves the row as it is.
+    let stride = width * 3;
+    let mut raw = Vec::with_capacity((stride + 1) * height);
+
+    for row in rgb.chunks(stride) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    try!(write_chunk(out, b"IDAT", &zlib(&raw)));
+    write_chunk(out, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+
+    push_u32(&mut chunk, data.len() as u32);
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+
+    // The checksum covers the type and data, not the length.
+    let crc = crc32(&chunk[4..]);
+    push_u32(&mut chunk, crc);
+
+    out.write_all(&chunk)
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.push((value >> 24) as u8);
+    out.push((value >> 16) as u8);
+    out.push((value >> 8) as u8);
+    out.push(value as u8);
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+// Wraps a deflate stream in the zlib header and checksum PNG expects.
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+
+    out.extend_from_slice(&deflate(data));
+    push_u32(&mut out, adler32(data));
+
+    out
+}
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize   = 3;
+const MAX_MATCH: usize   = 258;
+const HASH_BITS: usize   = 15;
+
+const LENGTH_BASE: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0
+];
+
+const DISTANCE_BASE: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577
+];
+
+const DISTANCE_EXTRA: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13
+];
+
+// Compresses into one block with the fixed Huffman codes, finding matches by
+// remembering the last place each three-byte sequence was seen.
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::new();
+
+    // BFINAL, then BTYPE 01 for fixed codes.
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut last_seen = vec![usize::max_value(); 1 << HASH_BITS];
+    let mut position = 0;
+
+    while position < data.len() {
+        let mut length = 0;
+        let mut distance = 0;
+
+        if position + MIN_MATCH <= data.len() {
+            let hash = hash(&data[position..position + MIN_MATCH]);
+            let candidate = last_seen[hash];
+
+            last_seen[hash] = position;
+
+            if candidate != usize::max_value() && position - candidate <= WINDOW_SIZE {
+                let limit = MAX_MATCH.min(data.len() - position);
+
+                while length < limit && data[candidate + length] == data[position + length] {
+                    length += 1;
+                }
+
+                distance = position - candidate;
+            }
+        }
+
+        if length >= MIN_MATCH {
+            write_match(&mut bits, length, distance);
+
+            // Keep the table current through the match so later rows can
+            // refer back into it.
+            for skipped in position + 1..position + length {
+                if skipped + MIN_MATCH <= data.len() {
+                    last_seen[hash(&data[skipped..skipped + MIN_MATCH])] = skipped;
+                }
+            }
+
+            position += length;
+        } else {
+            write_literal(&mut bits, data[position] as u16);
+            position += 1;
+        }
+    }
+
+    write_literal(&mut bits, 256);
+
+    bits.finish()
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize;
+
+    (value.wrapping_mul(2654435761) >> 7) & ((1 << HASH_BITS) - 1)
+}
+
+fn write_literal(bits: &mut BitWriter, value: u16) {
+    let value = value as u32;
+
+    match value {
+        0 ... 143   => bits.write_code(0x30 + value, 8),
+        144 ... 255 => bits.write_code(0x190 + value - 144, 9),
+        256 ... 279 => bits.write_code(value - 256, 7),
+        _           => bits.write_code(0xC0 + value - 280, 8)
+    }
+}
+
+fn write_match(bits: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE.iter().rposition(|base| *base <= length).unwrap();
+
+    write_literal(bits, 257 + code as u16);
+    bits.write((length - LENGTH_BASE[code]) as u32, LENGTH_EXTRA[code]);
+
+    let code = DISTANCE_BASE.iter().rposition(|base| *base <= distance).unwrap();
+
+    bits.write_code(code as u32, 5);
+    bits.write((distance - DISTANCE_BASE[code]) as u32, DISTANCE_EXTRA[code]);
+}
+
+// Packs bits least significant first, as deflate stores everything but the
+// Huffman codes themselves.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            buffer: 0,
+            count: 0
+        }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        for bit in 0..count {
+            self.buffer |= ((value >> bit) & 1) << self.count;
+            self.count += 1;
+
+            if self.count == 8 {
+                self.bytes.push(self.buffer as u8);
+                self.buffer = 0;
+                self.count  = 0;
+            }
+        }
+    }
+
+    // Huffman codes go most significant bit first.
+    fn write_code(&mut self, code: u32, length: u32) {
+        for bit in (0..length).rev() {
+            self.write((code >> bit) & 1, 1);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn crc32_matches_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+    }
+
+    #[test]
+    fn adler32_matches_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+
+        // Enough bytes for both sums to wrap round the modulus.
+        assert_eq!(adler32(&vec![0xFF; 6000]), 0xA49759EA);
+    }
+
+    #[test]
+    fn writes_chunks_with_length_type_data_and_crc() {
+        let mut chunk = Vec::new();
+
+        write_chunk(&mut chunk, b"IEND", &[]).unwrap();
+
+        assert_eq!(chunk, [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::vec::Vec;
+
+use disassembler;
+use mmu::{Mmu, MEMORY_SIZE, ROM_START};
+
+// How many of the most run addresses the report lists.
+const TOP_ADDRESSES: usize = 20;
+
+// A read of the delay timer that comes round again within this many
+// instructions is taken for a loop waiting on the timer.
+const TIGHT_LOOP: u64 = 8;
+
+// The width of the longest bar in the instruction histogram.
+const BAR_WIDTH: u64 = 40;
+
+// Counts what the program runs, as it runs: how often each address and each
+// kind of instruction is run, which subroutines call which and how much each
+// runs on its own, and how much is spent spinning on Fx0A for a key or on a
+// delay timer loop. Fed by `Cpu::step`; the report is meant for the end.
+pub struct Profiler {
+    instructions: u64,
+    counts: Vec<u64>,
+    forms: BTreeMap<&'static str, u64>,
+    functions: BTreeMap<u16, u64>,
+    calls: BTreeMap<(u16, u16), u64>,
+    call_stack: Vec<u16>,
+    key_wait: u64,
+    timer_wait: u64,
+    timer_read: Option<(u16, u64)>
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            instructions: 0,
+            counts: vec![0; MEMORY_SIZE],
+            forms: BTreeMap::new(),
+            functions: BTreeMap::new(),
+            calls: BTreeMap::new(),
+            call_stack: Vec::new(),
+            key_wait: 0,
+            timer_wait: 0,
+            timer_read: None
+        }
+    }
+
+    // Called after each instruction is run, with where it was, where it left
+    // PC and what the delay timer reads.
+    pub fn record(&mut self, pc: u16, instruction: u16, next_pc: u16, delay_timer: u8) {
+        let function = self.function();
+
+        self.instructions += 1;
+        self.counts[pc as usize] += 1;
+
+        *self.forms.entry(disassembler::form(instruction)).or_insert(0) += 1;
+        *self.functions.entry(function).or_insert(0) += 1;
+
+        if let Some((_, ref mut since)) = self.timer_read {
+            *since += 1;
+        }
+
+        if instruction & 0xF000 == 0x2000 {
+            *self.calls.entry((function, next_pc)).or_insert(0) += 1;
+            self.call_stack.push(next_pc);
+            return;
+        }
+
+        match instruction & 0xF0FF {
+            0x00EE => {
+                self.call_stack.pop();
+            },
+            // Fx0A stays put until a key is down.
+            0xF00A if next_pc == pc => self.key_wait += 1,
+            0xF007 => self.read_timer(pc, delay_timer),
+            _ => { }
+        }
+    }
+
+    // Forgets the calls in progress, for when the program starts over. The
+    // counts carry on.
+    pub fn restart(&mut self) {
+        self.call_stack.clear();
+        self.timer_read = None;
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn write_report<W: Write>(&self, mmu: &Mmu, out: &mut W) -> io::Result<()> {
+        try!(writeln!(out, "{} instructions run", self.instructions));
+        try!(writeln!(out, ""));
+
+        try!(writeln!(out, "Waiting"));
+        try!(writeln!(out, "  for a key (Fx0A)       {:>10}  {:>5.1}%", self.key_wait, self.share(self.key_wait)));
+        try!(writeln!(out, "  on the delay timer     {:>10}  {:>5.1}%", self.timer_wait, self.share(self.timer_wait)));
+        try!(writeln!(out, ""));
+
+        try!(self.write_addresses(mmu, out));
+        try!(self.write_histogram(out));
+        try!(self.write_call_graph(out));
+
+        Ok(())
+    }
+
+    // The subroutine running now, or where the program starts if none is.
+    fn function(&self) -> u16 {
+        self.call_stack.last().cloned().unwrap_or(ROM_START as u16)
+    }
+
+    // A loop waiting on the delay timer reads it over and over, and the
+    // instructions between reads are spent waiting, up to and including the
+    // read that finds it run out.
+    fn read_timer(&mut self, pc: u16, delay_timer: u8) {
+        if let Some((last_pc, since)) = self.timer_read {
+            if last_pc == pc && since <= TIGHT_LOOP {
+                self.timer_wait += since;
+            }
+        }
+
+        self.timer_read = if delay_timer > 0 { Some((pc, 0)) } else { None };
+    }
+
+    fn share(&self, count: u64) -> f64 {
+        if self.instructions == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.instructions as f64
+        }
+    }
+
+    fn write_addresses<W: Write>(&self, mmu: &Mmu, out: &mut W) -> io::Result<()> {
+        let mut addresses: Vec<usize> = (0..MEMORY_SIZE).filter(|&address| self.counts[address] > 0).collect();
+
+        addresses.sort_by(|&a, &b| self.counts[b].cmp(&self.counts[a]).then(a.cmp(&b)));
+
+        try!(writeln!(out, "Hottest addresses"));
+
+        for &address in addresses.iter().take(TOP_ADDRESSES) {
+            let instruction = mmu.read_word(address).unwrap_or(0);
+
+            try!(writeln!(out, "  {:03X}  {:04X}  {:<18} {:>10}  {:>5.1}%", address, instruction,
+                          disassembler::disassemble(instruction), self.counts[address], self.share(self.counts[address])));
+        }
+
+        writeln!(out, "")
+    }
+
+    fn write_histogram<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut forms: Vec<(&'static str, u64)> = self.forms.iter().map(|(&form, &count)| (form, count)).collect();
+
+        forms.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        let most = forms.first().map(|&(_, count)| count).unwrap_or(0);
+
+        try!(writeln!(out, "Instructions"));
+
+        for &(form, count) in forms.iter() {
+            let bar = (count * BAR_WIDTH + most - 1) / most;
+
+            try!(writeln!(out, "  {:<14} {:>10}  {:>5.1}%  {}", form, count, self.share(count),
+                          "#".repeat(bar as usize)));
+        }
+
+        writeln!(out, "")
+    }
+
+    // Each subroutine with what it runs itself, then what it calls and how
+    // often.
+    fn write_call_graph<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        try!(writeln!(out, "Call graph"));
+
+        for (&function, &count) in self.functions.iter() {
+            try!(writeln!(out, "  {:03X}  {:>10}  {:>5.1}%", function, count, self.share(count)));
+
+            for (&(_, callee), &calls) in self.calls.iter().filter(|&(&(caller, _), _)| caller == function) {
+                try!(writeln!(out, "    calls {:03X} {}", callee, times(calls)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn times(count: u64) -> String {
+    if count == 1 {
+        String::from("once")
+    } else {
+        format!("{} times", count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mmu::Mmu;
+
+    fn report(profiler: &Profiler) -> String {
+        let mut out = Vec::new();
+        profiler.write_report(&Mmu::new(), &mut out).unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn counts_fx0a_as_waiting_only_while_no_key_is_down() {
+        let mut profiler = Profiler::new();
+
+        profiler.record(0x200, 0xF30A, 0x200, 0);
+        profiler.record(0x200, 0xF30A, 0x200, 0);
+        profiler.record(0x200, 0xF30A, 0x202, 0);
+
+        assert_eq!(profiler.key_wait, 2);
+        assert_eq!(profiler.instructions(), 3);
+        assert_eq!(profiler.counts[0x200], 3);
+    }
+
+    // LD V0, DT; SE V0, 0; JP 0x300, until the timer runs out.
+    fn wait_on_timer(profiler: &mut Profiler, from: u8) {
+        for delay_timer in (0..from + 1).rev() {
+            profiler.record(0x300, 0xF007, 0x302, delay_timer);
+
+            if delay_timer == 0 {
+                profiler.record(0x302, 0x3000, 0x306, delay_timer);
+            } else {
+                profiler.record(0x302, 0x3000, 0x304, delay_timer);
+                profiler.record(0x304, 0x1300, 0x300, delay_timer);
+            }
+        }
+    }
+
+    #[test]
+    fn counts_tight_loops_on_the_delay_timer_as_waiting() {
+        let mut profiler = Profiler::new();
+
+        wait_on_timer(&mut profiler, 3);
+
+        // Every lap after the first read, including the read that finds the
+        // timer run out.
+        assert_eq!(profiler.timer_wait, 3 * 3);
+        assert_eq!(profiler.timer_read, None);
+    }
+
+    #[test]
+    fn ignores_timer_reads_far_apart() {
+        let mut profiler = Profiler::new();
+
+        profiler.record(0x300, 0xF007, 0x302, 10);
+
+        for _ in 0..TIGHT_LOOP {
+            profiler.record(0x302, 0x7001, 0x304, 10);
+        }
+
+        profiler.record(0x300, 0xF007, 0x302, 9);
+
+        assert_eq!(profiler.timer_wait, 0);
+    }
+
+    #[test]
+    fn ignores_timer_reads_from_elsewhere() {
+        let mut profiler = Profiler::new();
+
+        profiler.record(0x300, 0xF007, 0x302, 10);
+        profiler.record(0x310, 0xF007, 0x312, 10);
+
+        assert_eq!(profiler.timer_wait, 0);
+    }
+
+    #[test]
+    fn charges_instructions_to_the_running_subroutine() {
+        let mut profiler = Profiler::new();
+
+        profiler.record(0x200, 0x2300, 0x300, 0);
+        profiler.record(0x300, 0x6001, 0x302, 0);
+        profiler.record(0x302, 0x2400, 0x400, 0);
+        profiler.record(0x400, 0x00EE, 0x304, 0);
+        profiler.record(0x304, 0x2400, 0x400, 0);
+        profiler.record(0x400, 0x00EE, 0x306, 0);
+        profiler.record(0x306, 0x00EE, 0x202, 0);
+        profiler.record(0x202, 0x1202, 0x202, 0);
+
+        assert_eq!(profiler.functions.get(&0x200), Some(&2));
+        assert_eq!(profiler.functions.get(&0x300), Some(&4));
+        assert_eq!(profiler.functions.get(&0x400), Some(&2));
+
+        assert_eq!(profiler.calls.get(&(0x200, 0x300)), Some(&1));
+        assert_eq!(profiler.calls.get(&(0x300, 0x400)), Some(&2));
+        assert_eq!(profiler.calls.len(), 2);
+
+        let report = report(&profiler);
+
+        assert!(report.contains("    calls 300 once\n"));
+        assert!(report.contains("    calls 400 2 times\n"));
+    }
+
+    #[test]
+    fn forgets_calls_in_progress_on_restart() {
+        let mut profiler = Profiler::new();
+
+        profiler.record(0x200, 0x2300, 0x300, 0);
+        profiler.restart();
+        profiler.record(0x200, 0x6001, 0x202, 0);
+
+        assert_eq!(profiler.functions.get(&0x200), Some(&2));
+        assert_eq!(profiler.functions.get(&0x300), None);
+    }
+}
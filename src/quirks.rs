@@ -0,0 +1,63 @@
+use std::fmt;
+use std::vec::Vec;
+
+// Behaviours that differ between CHIP-8 interpreters. The defaults are all off,
+// which is what CHIP-48 and SUPER-CHIP did and what most surviving games
+// expect. The original COSMAC VIP interpreter clipped sprites too, but turned
+// on everything else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // Sprite pixels past the right or bottom edge wrap to the opposite side
+    // instead of being clipped.
+    pub wrap_sprites: bool,
+    // Dxyn stalls until the next 60 Hz tick, as the VIP waited for vertical
+    // blank before drawing. Limits games to 60 draws per second.
+    pub display_wait: bool,
+    // 8xy6 and 8xyE shift VY into VX, as on the VIP, rather than shifting VX
+    // in place.
+    pub shift_vy: bool,
+    // Fx55 and Fx65 leave I just past the last register they touched, as on
+    // the VIP, rather than leaving it alone.
+    pub load_store_i: bool
+}
+
+impl Quirks {
+    pub fn new() -> Quirks {
+        Quirks {
+            wrap_sprites: false,
+            display_wait: false,
+            shift_vy: false,
+            load_store_i: false
+        }
+    }
+
+    // The quirks that are on, as command line flags.
+    pub fn args(&self) -> Vec<String> {
+        let flags = [
+            (self.wrap_sprites, "--wrap-sprites"),
+            (self.display_wait, "--display-wait"),
+            (self.shift_vy,     "--shift-vy"),
+            (self.load_store_i, "--load-store-i")
+        ];
+
+        flags.iter()
+            .filter(|&&(on, _)| on)
+            .map(|&(_, flag)| String::from(flag))
+            .collect()
+    }
+}
+
+// The quirks that are on, named as their options are, e.g. "shift-vy,
+// load-store-i", or "no quirks".
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = self.args();
+        let names: Vec<&str> = flags.iter().map(|flag| &flag[2..]).collect();
+
+        if names.is_empty() {
+            write!(f, "no quirks")
+        } else {
+            write!(f, "{}", names.join(", "))
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::vec::Vec;
+
+use framebuffer::Framebuffer;
+use gif::GifWriter;
+use screenshot::Renderer;
+use timing::FRAMES_PER_SECOND;
+
+// GIF delays are in hundredths of a second, and most viewers slow anything
+// shorter than two of them right down, so faster changes are dropped.
+const MIN_GIF_DELAY: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // An animated GIF, with repeated frames merged into longer delays.
+    Gif,
+    // Uncompressed YUV 4:4:4 at 60 frames per second, for ffmpeg to encode.
+    Y4m
+}
+
+impl Format {
+    // Picks the format from a file name's extension.
+    pub fn for_path(path: &str) -> Option<Format> {
+        let extension = Path::new(path).extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+
+        match extension.as_ref().map(|extension| extension.as_str()) {
+            Some("gif") => Some(Format::Gif),
+            Some("y4m") => Some(Format::Y4m),
+            _           => None
+        }
+    }
+}
+
+// Writes every presented frame to a file until stopped.
+pub struct Recorder {
+    path: String,
+    format: Format,
+    renderer: Renderer,
+    // The Y4M stream, or the GIF file until the first frame gives its size.
+    out: Option<BufWriter<File>>,
+    gif: Option<GifWriter<BufWriter<File>>>,
+    size: (usize, usize),
+    pixels: Vec<u8>,
+    // The GIF frame waiting for its delay to be known, how many frames have
+    // been recorded, and how much of that time has been written out.
+    pending: Option<Vec<u8>>,
+    frames: u64,
+    written: u64
+}
+
+impl Recorder {
+    // Frames are rendered with `renderer`, so recordings match screenshots.
+    pub fn start(path: &str, renderer: Renderer) -> io::Result<Recorder> {
+        let format = match Format::for_path(path) {
+            Some(format) => format,
+            None => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          format!("can't record to {}: expected a .gif or .y4m file", path)));
+            }
+        };
+
+        let out = BufWriter::new(try!(File::create(path)));
+
+        Ok(Recorder {
+            path: path.to_string(),
+            format: format,
+            renderer: renderer,
+            out: Some(out),
+            gif: None,
+            size: (0, 0),
+            pixels: Vec::new(),
+            pending: None,
+            frames: 0,
+            written: 0
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Records a frame that stays up for `frames` 60 Hz frames, as when
+    // fast forwarding runs several for each one shown. Every frame must be
+    // the same size as the first.
+    pub fn record(&mut self, frame: &Framebuffer, frames: u64) -> io::Result<()> {
+        let size = self.renderer.size(frame);
+
+        if self.frames == 0 {
+            self.size = size;
+            try!(self.write_header());
+        } else if size != self.size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "the display changed size while recording"));
+        }
+
+        match self.format {
+            Format::Gif => try!(self.record_gif(frame)),
+            Format::Y4m => try!(self.record_y4m(frame, frames))
+        }
+
+        self.frames += frames;
+
+        Ok(())
+    }
+
+    // Writes out anything still pending and the file's trailer.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.pending.is_some() {
+            try!(self.flush_gif_frame());
+        }
+
+        if let Some(mut gif) = self.gif.take() {
+            try!(gif.finish());
+        }
+
+        match self.out.take() {
+            Some(mut out) => out.flush(),
+            None          => Ok(())
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let (width, height) = self.size;
+
+        match self.format {
+            Format::Gif => {
+                // Frames are stored as brightness levels, which map straight
+                // onto a 256 entry color table.
+                let colors: Vec<_> = (0..256).map(|level| self.renderer.palette.shade(level as u8)).collect();
+
+                if let Some(out) = self.out.take() {
+                    self.gif = Some(try!(GifWriter::new(out, width, height, &colors)));
+                }
+            },
+            Format::Y4m => {
+                if let Some(ref mut out) = self.out {
+                    try!(write!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444\n",
+                                width, height, FRAMES_PER_SECOND));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record_gif(&mut self, frame: &Framebuffer) -> io::Result<()> {
+        let scale = self.renderer.scale;
+        let (width, height) = self.size;
+
+        let mut indices = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                indices.push(frame.pixel(x / scale, y / scale));
+            }
+        }
+
+        let changed = match self.pending {
+            Some(ref pending) => *pending != indices,
+            None              => true
+        };
+
+        if !changed {
+            return Ok(());
+        }
+
+        // Show the previous frame until now, unless that's too short to
+        // display, in which case it's replaced.
+        if self.pending.is_some() && delay_until(self.frames) - self.written >= MIN_GIF_DELAY {
+            try!(self.flush_gif_frame());
+        }
+
+        self.pending = Some(indices);
+
+        Ok(())
+    }
+
+    fn flush_gif_frame(&mut self) -> io::Result<()> {
+        let end = delay_until(self.frames).max(self.written + 1);
+        let delay = end - self.written;
+
+        if let (Some(pending), Some(gif)) = (self.pending.take(), self.gif.as_mut()) {
+            // A delay too long for GIF's 16 bits, of a frame held for over ten
+            // minutes, is spread over copies of it.
+            let mut remaining = delay;
+
+            while remaining > 0 {
+                let part = remaining.min(u16::max_value() as u64);
+
+                try!(gif.write_frame(&pending, part as u16));
+                remaining -= part;
+            }
+        }
+
+        self.written = end;
+
+        Ok(())
+    }
+
+    fn record_y4m(&mut self, frame: &Framebuffer, frames: u64) -> io::Result<()> {
+        self.renderer.render(frame, &mut self.pixels);
+
+        let count = self.pixels.len() / 3;
+        let mut planes = vec![0; count * 3];
+
+        for (index, rgb) in self.pixels.chunks(3).enumerate() {
+            let (r, g, b) = (rgb[0] as i32, rgb[1] as i32, rgb[2] as i32);
+
+            // BT.601 studio range, as Y4M players assume.
+            planes[index]             = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            planes[count + index]     = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            planes[count * 2 + index] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+
+        if let Some(ref mut out) = self.out {
+            for _ in 0..frames {
+                try!(out.write_all(b"FRAME\n"));
+                try!(out.write_all(&planes));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+// How many hundredths of a second `frames` 60 Hz frames last.
+fn delay_until(frames: u64) -> u64 {
+    (frames * 100 + FRAMES_PER_SECOND as u64 / 2) / FRAMES_PER_SECOND as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::process;
+    use std::vec::Vec;
+
+    use framebuffer::{Framebuffer, PIXEL_ON};
+    use palette::Palette;
+    use screenshot::Renderer;
+
+    use super::*;
+
+    // A file in the temporary directory, removed once read.
+    fn temp_path(name: &str) -> String {
+        env::temp_dir().join(format!("rustychip8-{}-{}", process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    fn read_and_remove(path: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+        fs::remove_file(path).unwrap();
+
+        bytes
+    }
+
+    // The delays of each frame of a GIF, from its graphic control extensions.
+    fn gif_delays(gif: &[u8]) -> Vec<u16> {
+        gif.windows(6)
+            .filter(|bytes| bytes[..4] == [0x21, 0xF9, 0x04, 0x00])
+            .map(|bytes| bytes[4] as u16 | (bytes[5] as u16) << 8)
+            .collect()
+    }
+
+    fn lit(width: usize, height: usize, x: usize, y: usize) -> Framebuffer {
+        let mut frame = Framebuffer::new(width, height);
+
+        frame.set_pixel(x, y, PIXEL_ON);
+        frame
+    }
+
+    #[test]
+    fn picks_the_format_from_the_extension() {
+        assert_eq!(Format::for_path("game.gif"), Some(Format::Gif));
+        assert_eq!(Format::for_path("game.Y4M"), Some(Format::Y4m));
+        assert_eq!(Format::for_path("game.png"), None);
+        assert_eq!(Format::for_path("game"), None);
+
+        assert!(Recorder::start(&temp_path("game.png"), Renderer::new(Palette::classic(), 1)).is_err());
+    }
+
+    #[test]
+    fn rounds_frames_to_hundredths() {
+        assert_eq!(delay_until(0), 0);
+        assert_eq!(delay_until(1), 2);
+        assert_eq!(delay_until(3), 5);
+        assert_eq!(delay_until(6), 10);
+        assert_eq!(delay_until(60), 100);
+    }
+
+    #[test]
+    fn converts_frames_to_y4m() {
+        let path = temp_path("convert.y4m");
+
+        {
+            let mut recorder = Recorder::start(&path, Renderer::new(Palette::classic(), 1)).unwrap();
+
+            recorder.record(&lit(2, 1, 0, 0), 1).unwrap();
+            recorder.record(&lit(2, 1, 1, 0), 2).unwrap();
+            recorder.finish().unwrap();
+        }
+
+        let mut expected = b"YUV4MPEG2 W2 H1 F60:1 Ip A1:1 C444\n".to_vec();
+
+        // White and black in studio range: Y, then U, then V.
+        expected.extend_from_slice(b"FRAME\n");
+        expected.extend_from_slice(&[235, 16, 128, 128, 128, 128]);
+
+        // The second frame lasts two.
+        for _ in 0..2 {
+            expected.extend_from_slice(b"FRAME\n");
+            expected.extend_from_slice(&[16, 235, 128, 128, 128, 128]);
+        }
+
+        assert_eq!(read_and_remove(&path), expected);
+    }
+
+    #[test]
+    fn merges_repeated_gif_frames_and_drops_short_ones() {
+        let path = temp_path("merge.gif");
+
+        {
+            let mut recorder = Recorder::start(&path, Renderer::new(Palette::classic(), 1)).unwrap();
+
+            // A second and a frame of one frame, then one that rounds to a
+            // hundredth of a second, too short to show, then half a second
+            // of another.
+            for _ in 0..61 {
+                recorder.record(&lit(2, 2, 0, 0), 1).unwrap();
+            }
+
+            recorder.record(&lit(2, 2, 1, 0), 1).unwrap();
+
+            for _ in 0..30 {
+                recorder.record(&lit(2, 2, 1, 1), 1).unwrap();
+            }
+        }
+
+        assert_eq!(gif_delays(&read_and_remove(&path)), [102, 51]);
+    }
+
+    #[test]
+    fn spreads_long_gif_delays_over_copies() {
+        let path = temp_path("long.gif");
+
+        {
+            let mut recorder = Recorder::start(&path, Renderer::new(Palette::classic(), 1)).unwrap();
+
+            // Eleven minutes of the same frame.
+            for _ in 0..11 * 60 * 60 {
+                recorder.record(&lit(1, 1, 0, 0), 1).unwrap();
+            }
+
+            recorder.record(&Framebuffer::new(1, 1), 1).unwrap();
+            recorder.finish().unwrap();
+        }
+
+        assert_eq!(gif_delays(&read_and_remove(&path)), [65535, 465, 2]);
+    }
+
+    #[test]
+    fn times_frames_that_stand_for_several() {
+        let path = temp_path("fast.gif");
+
+        {
+            let mut recorder = Recorder::start(&path, Renderer::new(Palette::classic(), 1)).unwrap();
+
+            // Two seconds fast forwarded at 4 frames a time, then a second
+            // at normal speed.
+            for _ in 0..30 {
+                recorder.record(&lit(2, 2, 0, 0), 4).unwrap();
+            }
+
+            for _ in 0..60 {
+                recorder.record(&lit(2, 2, 1, 1), 1).unwrap();
+            }
+        }
+
+        assert_eq!(gif_delays(&read_and_remove(&path)), [200, 100]);
+    }
+}
@@ -0,0 +1,394 @@
+use std::vec::Vec;
+
+use platform::Platform;
+use quirks::Quirks;
+use timing::Timing;
+
+// What's known about a ROM, and the settings it plays best with. Settings
+// left as None fall back to the defaults.
+#[derive(Debug)]
+pub struct RomInfo {
+    pub sha1: &'static str,
+    pub title: &'static str,
+    pub author: Option<&'static str>,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub timing: Option<Timing>,
+    // In the form --keymap and --palette take.
+    pub keymap: Option<&'static str>,
+    pub palette: Option<&'static str>
+}
+
+impl RomInfo {
+    // The settings as command line arguments, so a config file or the
+    // command line can override them.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = self.quirks.args();
+
+        if let Some(timing) = self.timing {
+            args.push(String::from("--timing"));
+            args.push(match timing {
+                Timing::Flat(ips)  => ips.to_string(),
+                Timing::CosmacVip => String::from("vip")
+            });
+        }
+
+        if let Some(keymap) = self.keymap {
+            args.push(String::from("--keymap"));
+            args.push(keymap.to_string());
+        }
+
+        if let Some(palette) = self.palette {
+            args.push(String::from("--palette"));
+            args.push(palette.to_string());
+        }
+
+        args
+    }
+}
+
+pub fn lookup(sha1: &str) -> Option<&'static RomInfo> {
+    ROMS.iter().find(|info| info.sha1 == sha1)
+}
+
+const DEFAULT_QUIRKS: Quirks = Quirks {
+    wrap_sprites: false,
+    display_wait: false,
+    shift_vy: false,
+    load_store_i: false
+};
+
+// Games that move a step each time they draw, and so run far too fast without
+// the VIP's wait for vertical blank, though they were written for CHIP-48's
+// shifts and loads.
+const DISPLAY_WAIT_QUIRKS: Quirks = Quirks {
+    wrap_sprites: false,
+    display_wait: true,
+    shift_vy: false,
+    load_store_i: false
+};
+
+// Programs written for the VIP itself expect it in every respect.
+const VIP_QUIRKS: Quirks = Quirks {
+    wrap_sprites: false,
+    display_wait: true,
+    shift_vy: true,
+    load_store_i: true
+};
+
+// Keymaps that put each game's controls under W, A, S and D, in the form
+// --keymap takes. The keys they displace move to where the game's keys were.
+
+// 4 and 6 move left and right, 5 fires or serves.
+const LEFT_FIRE_RIGHT_KEYMAP: &'static str = "x123awdqsezc4rfv";
+
+// 3, 6, 7 and 8 steer up, down, left and right.
+const CHASE_KEYMAP: &'static str = "x12wq3sadezc4rfv";
+
+// 2, 8, 4 and 6 steer up, down, left and right, 5 fires.
+const TANK_KEYMAP: &'static str = "x1w3aedqs2zc4rfv";
+
+// 5 and 6 move left and right, 4 rotates and 7 drops.
+const TETRIS_KEYMAP: &'static str = "x123wadsqezc4rfv";
+
+// The public domain games in roms/.
+// Keep them sorted by title.
+pub static ROMS: [RomInfo; 23] = [
+    RomInfo {
+        sha1: "ea9af3c09b0d9e265fcd92bcc5d51a2939fdf27a",
+        title: "15 Puzzle",
+        author: Some("Roger Ivie"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "d40abc54374e4343639f993e897e00904ddf85d9",
+        title: "Blinky",
+        author: Some("Hans Christian Egeberg"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: Some(CHASE_KEYMAP),
+        palette: None
+    },
+    RomInfo {
+        sha1: "6f6509f38220e057a7e32ebb22dd353c1078e3e7",
+        title: "Blitz",
+        author: Some("David Winter"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "f13766c14aeb02ad8d4d103cb5eadd282d20cddc",
+        title: "Brix",
+        author: Some("Andreas Gustafsson"),
+        platform: Platform::Chip8,
+        quirks: DISPLAY_WAIT_QUIRKS,
+        timing: None,
+        keymap: Some(LEFT_FIRE_RIGHT_KEYMAP),
+        palette: None
+    },
+    RomInfo {
+        sha1: "2d10c07b532f4fa7c07a07324ba26ca39fe484fd",
+        title: "Connect 4",
+        author: Some("David Winter"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "5260f8931e0e9f41e555b382a14a88368e3ed886",
+        title: "Guess",
+        author: Some("David Winter"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "050f07a54371da79f924dd0227b89d07b4f2aed0",
+        title: "Hidden",
+        author: Some("David Winter"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "d6fa9dc9005dc0496f39ba52fef56f9fd0a5a158",
+        title: "Kaleidoscope",
+        author: Some("Joseph Weisbecker"),
+        platform: Platform::Chip8,
+        quirks: VIP_QUIRKS,
+        timing: Some(Timing::CosmacVip),
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "b9272ae1acdaaa79ab649f6b48b72088ca2b1d74",
+        title: "Maze",
+        author: Some("David Winter"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "d979858bb9ffd07b48f52f92a8bcac0199f3623e",
+        title: "Merlin",
+        author: Some("David Winter"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "0d0cc129dad3c45ba672f85fec71a668232212cc",
+        title: "Missile Command",
+        author: Some("David Winter"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: Some("amber")
+    },
+    RomInfo {
+        sha1: "b232ef880bd6060fb45fa6effed7edf0ae95670e",
+        title: "Pong",
+        author: Some("Paul Vervalin"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "a60611339661e3ab2d8af024ad1da5880a6f8665",
+        title: "Pong 2",
+        author: None,
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "1293db0ccccbe7dd3fc5a09a2abc5d7b175e18e0",
+        title: "Puzzle",
+        author: None,
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "f100197f0f2f05b4f3c8c31ab9c2c3930d3e9571",
+        title: "Space Invaders",
+        author: Some("David Winter"),
+        platform: Platform::Chip8,
+        quirks: DISPLAY_WAIT_QUIRKS,
+        timing: None,
+        keymap: Some(LEFT_FIRE_RIGHT_KEYMAP),
+        palette: Some("green")
+    },
+    RomInfo {
+        sha1: "1bdb4ddaa7049266fa3226851f28855a365cfd12",
+        title: "Syzygy",
+        author: Some("Roy Trevino"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: Some(CHASE_KEYMAP),
+        palette: None
+    },
+    RomInfo {
+        sha1: "18b9d15f4c159e1f0ed58c2d8ec1d89325d3a3b6",
+        title: "Tank",
+        author: None,
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: Some(TANK_KEYMAP),
+        palette: None
+    },
+    RomInfo {
+        sha1: "5f518084744bf3cb8733f6e5454dfd1634320563",
+        title: "Tetris",
+        author: Some("Fran Dachille"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: Some(TETRIS_KEYMAP),
+        palette: Some("gameboy")
+    },
+    RomInfo {
+        sha1: "429d455a4bc53167942bf6fd934d72b0f648dce3",
+        title: "Tic-Tac-Toe",
+        author: Some("David Winter"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "bdb92475acfe11bc7814a2f5eade13fcd09b756a",
+        title: "UFO",
+        author: Some("Lutz V"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: Some(LEFT_FIRE_RIGHT_KEYMAP),
+        palette: None
+    },
+    RomInfo {
+        sha1: "ade839585ddeb0e3633177df03c1d91589e629eb",
+        title: "Vers",
+        author: Some("JMN"),
+        platform: Platform::Chip8,
+        quirks: DEFAULT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "da710f631f8e35534d0b9170bcf892a60f49c43d",
+        title: "Vertical Brix",
+        author: Some("Paul Robson"),
+        platform: Platform::Chip8,
+        quirks: DISPLAY_WAIT_QUIRKS,
+        timing: None,
+        keymap: None,
+        palette: None
+    },
+    RomInfo {
+        sha1: "d666688a8fce468a7d88b536bc1ef5f35ba12031",
+        title: "Wipe Off",
+        author: Some("Joseph Weisbecker"),
+        platform: Platform::Chip8,
+        quirks: VIP_QUIRKS,
+        timing: Some(Timing::CosmacVip),
+        keymap: Some(LEFT_FIRE_RIGHT_KEYMAP),
+        palette: None
+    }
+];
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use keypad::Keymap;
+    use options::Options;
+    use sha1;
+
+    use super::*;
+
+    // Every ROM in roms/ is in the table under the hash of its contents, and
+    // everything in the table is in roms/.
+    #[test]
+    fn hashes_match_the_roms_directory() {
+        let mut found = Vec::new();
+
+        for entry in fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/roms")).unwrap() {
+            let path = entry.unwrap().path();
+            let rom  = fs::read(&path).unwrap();
+            let hash = sha1::hex_digest(&rom);
+
+            match lookup(&hash) {
+                Some(info) => found.push(info.title),
+                None       => panic!("{} ({}) isn't in the table", path.display(), hash)
+            }
+        }
+
+        for info in ROMS.iter() {
+            assert!(found.contains(&info.title), "{} isn't in roms/", info.title);
+        }
+    }
+
+    #[test]
+    fn titles_are_sorted() {
+        for pair in ROMS.windows(2) {
+            assert!(pair[0].title < pair[1].title, "{} comes before {}", pair[1].title, pair[0].title);
+        }
+    }
+
+    #[test]
+    fn settings_are_valid_options() {
+        for info in ROMS.iter() {
+            let mut args = info.args();
+            args.push(String::from("rom"));
+
+            if let Err(e) = Options::parse(args.into_iter()) {
+                panic!("{}: {}", info.title, e);
+            }
+        }
+    }
+
+    // The CHIP-8 keys under W, A, S and D.
+    fn wasd(keymap: &str) -> Vec<usize> {
+        let keymap: Keymap = keymap.parse().unwrap();
+
+        "wasd".chars().map(|c| keymap.key_for(c).unwrap()).collect()
+    }
+
+    #[test]
+    fn keymaps_put_the_controls_under_wasd() {
+        assert_eq!(wasd(LEFT_FIRE_RIGHT_KEYMAP), [5, 4, 8, 6]);
+        assert_eq!(wasd(CHASE_KEYMAP), [3, 7, 6, 8]);
+        assert_eq!(wasd(TANK_KEYMAP), [2, 4, 8, 6]);
+        assert_eq!(wasd(TETRIS_KEYMAP), [4, 5, 7, 6]);
+    }
+}
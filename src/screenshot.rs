@@ -0,0 +1,131 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::vec::Vec;
+
+use framebuffer::Framebuffer;
+use palette::Palette;
+use png;
+
+// Turns frames into RGB images the way a frontend would show them: in the
+// active palette, with every pixel blown up to a `scale` sized square.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Renderer {
+    pub palette: Palette,
+    pub scale: usize
+}
+
+impl Renderer {
+    pub fn new(palette: Palette, scale: usize) -> Renderer {
+        Renderer {
+            palette: palette,
+            scale: scale
+        }
+    }
+
+    pub fn size(&self, frame: &Framebuffer) -> (usize, usize) {
+        (frame.width() * self.scale, frame.height() * self.scale)
+    }
+
+    pub fn render(&self, frame: &Framebuffer, rgb: &mut Vec<u8>) {
+        let (width, height) = self.size(frame);
+
+        rgb.clear();
+        rgb.reserve(width * height * 3);
+
+        for y in 0..height {
+            for x in 0..width {
+                let color = self.palette.shade(frame.pixel(x / self.scale, y / self.scale));
+
+                rgb.push(color.r);
+                rgb.push(color.g);
+                rgb.push(color.b);
+            }
+        }
+    }
+}
+
+// Saves frames as PNGs named after the ROM and the frame they were taken on,
+// e.g. PONG-000120.png. Recordings started from a hotkey are named the same
+// way.
+pub struct Screenshots {
+    renderer: Renderer,
+    prefix: String
+}
+
+impl Screenshots {
+    pub fn new(renderer: Renderer, prefix: &str) -> Screenshots {
+        Screenshots {
+            renderer: renderer,
+            prefix: prefix.to_string()
+        }
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    // Names a capture of the given frame, e.g. path(120, "gif").
+    pub fn path(&self, frame_number: u64, extension: &str) -> String {
+        format!("{}-{:06}.{}", self.prefix, frame_number, extension)
+    }
+
+    // Writes the frame and returns where it went.
+    pub fn save(&self, frame: &Framebuffer, frame_number: u64) -> io::Result<String> {
+        let path = self.path(frame_number, "png");
+
+        let (width, height) = self.renderer.size(frame);
+        let mut rgb = Vec::new();
+
+        self.renderer.render(frame, &mut rgb);
+
+        let mut file = BufWriter::new(try!(File::create(&path)));
+        try!(png::write(&mut file, width, height, &rgb));
+
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use framebuffer::{Framebuffer, PIXEL_ON};
+    use palette::Palette;
+    use png;
+
+    use super::*;
+
+    // testdata/screenshot.png was checked against an independent decoder:
+    // its chunk CRCs, and the pixels inflated from its IDAT.
+    #[test]
+    fn renders_a_frame_as_the_golden_image() {
+        let mut frame = Framebuffer::new(8, 4);
+
+        for y in 0..4 {
+            for x in 0..8 {
+                if (x + y) % 3 == 0 {
+                    frame.set_pixel(x, y, PIXEL_ON);
+                }
+            }
+        }
+
+        // Half lit, as phosphor persistence leaves pixels.
+        frame.set_pixel(7, 3, 0x80);
+
+        let renderer = Renderer::new(Palette::named("amber").unwrap(), 3);
+        let (width, height) = renderer.size(&frame);
+
+        assert_eq!((width, height), (24, 12));
+
+        let mut rgb = Vec::new();
+        renderer.render(&frame, &mut rgb);
+
+        assert_eq!(&rgb[..6], &[0xFF, 0xB0, 0x00, 0xFF, 0xB0, 0x00]);
+        assert_eq!(&rgb[(11 * 24 + 23) * 3..], &[0x8C, 0x5F, 0x00]);
+
+        let mut image = Vec::new();
+        png::write(&mut image, width, height, &rgb).unwrap();
+
+        assert_eq!(&image[..], &include_bytes!("../testdata/screenshot.png")[..]);
+    }
+}
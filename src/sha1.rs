@@ -0,0 +1,102 @@
+use std::vec::Vec;
+
+// SHA-1, for recognising ROMs by their contents. Not for anything that needs
+// to be secure.
+pub fn digest(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    // Pad with a one bit, zeros up to 56 bytes into the last block, then the
+    // message length in bits.
+    let mut message: Vec<u8> = data.to_vec();
+    message.push(0x80);
+
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+
+    let bits = (data.len() as u64).wrapping_mul(8);
+
+    for shift in (0..8).rev() {
+        message.push((bits >> (shift * 8)) as u8);
+    }
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+
+        for i in 0..16 {
+            w[i] = (block[i * 4] as u32) << 24 | (block[i * 4 + 1] as u32) << 16 |
+                   (block[i * 4 + 2] as u32) << 8 | block[i * 4 + 3] as u32;
+        }
+
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (state[0], state[1], state[2], state[3], state[4]);
+
+        for i in 0..80 {
+            let (f, k) = match i {
+                0 ... 19  => ((b & c) | (!b & d), 0x5A827999),
+                20 ... 39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40 ... 59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _         => (b ^ c ^ d, 0xCA62C1D6)
+            };
+
+            let temp = a.rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(w[i]);
+
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+        state[4] = state[4].wrapping_add(e);
+    }
+
+    let mut digest = [0u8; 20];
+
+    for (i, word) in state.iter().enumerate() {
+        for byte in 0..4 {
+            digest[i * 4 + byte] = (word >> (24 - byte * 8)) as u8;
+        }
+    }
+
+    digest
+}
+
+// The digest as 40 lowercase hex digits, the way sha1sum prints it.
+pub fn hex_digest(data: &[u8]) -> String {
+    digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The examples from FIPS 180-2.
+    #[test]
+    fn matches_the_standard_examples() {
+        assert_eq!(hex_digest(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex_digest(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hex_digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+                   "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+        assert_eq!(hex_digest(&vec![b'a'; 1000000]), "34aa973cd4c4daa4f61eeb2bdbad27316534016f");
+    }
+
+    // Padding spills into a second block from 56 bytes on.
+    #[test]
+    fn pads_across_a_block_boundary() {
+        assert_eq!(hex_digest(&[b'a'; 55]), "c1c8bbdc22796e28c0e15163d20899b65621d65a");
+        assert_eq!(hex_digest(&[b'a'; 56]), "c2db330f6083854c99d4b5bfb6e8f29f201be699");
+        assert_eq!(hex_digest(&[b'a'; 64]), "0098ba824b5c16427bd7a1122a5a442a25ec644d");
+    }
+}
@@ -1,22 +1,503 @@
-use cpu::Cpu;
+use std::char;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::process::{self, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Instant;
+use std::vec::Vec;
 
-pub struct TermGfx { }
+use framebuffer::Framebuffer;
+use frontend::{Command, Frontend};
+use hotkeys::Hotkeys;
+use keypad::Keymap;
+use overlay::Overlay;
+use palette::{Color, Palette};
+
+// Terminals only report key presses, so a key counts as held for this many
+// frames after its last press; keyboard auto-repeat keeps it held.
+const KEY_HOLD_FRAMES: u32 = 6;
+
+// How many polls an escape sequence cut off at the end of the input is given
+// to finish before a lone escape is taken for the Escape key.
+const ESCAPE_WAIT_POLLS: u32 = 3;
+
+// Longer than any sequence we know, so anything this long unfinished is junk.
+const MAX_ESCAPE_SEQUENCE: usize = 8;
+
+const CTRL_C: u8    = 0x03;
+const TAB: u8       = 0x09;
+const ESCAPE: u8    = 0x1B;
+const BACKSPACE: u8 = 0x7F;
+
+// How pixels are packed into character cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TermMode {
+    // Half blocks: 1x2 pixels per cell, 64x16 cells for CHIP-8.
+    HalfBlock,
+    // Braille dots: 2x4 pixels per cell, 32x8 cells for CHIP-8 and 64x16 for
+    // SUPER-CHIP's 128x64.
+    Braille
+}
+
+impl TermMode {
+    fn cell_size(&self) -> (usize, usize) {
+        match *self {
+            TermMode::HalfBlock => (1, 2),
+            TermMode::Braille   => (2, 4)
+        }
+    }
+}
+
+impl FromStr for TermMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TermMode, String> {
+        match s {
+            "halfblock" => Ok(TermMode::HalfBlock),
+            "braille"   => Ok(TermMode::Braille),
+            _           => Err(format!("Unknown terminal mode: {} (expected halfblock or braille)", s))
+        }
+    }
+}
+
+// Renders the display with Unicode half blocks or braille, packing several
+// pixels into each character cell, and reads the keypad from a raw-mode
+// terminal.
+pub struct TermGfx {
+    mode: TermMode,
+    palette: Palette,
+    keymap: Keymap,
+    hotkeys: Hotkeys,
+    cells: Vec<char>,
+    rows: usize,
+    status: String,
+    machine_status: String,
+    overlay_status: String,
+    input: Receiver<u8>,
+    // The start of an escape sequence whose end hasn't arrived yet, and how
+    // many polls it's been waiting.
+    partial_escape: Vec<u8>,
+    escape_waits: u32,
+    held: [u32; 16],
+    commands: Vec<Command>,
+    held_commands: Vec<(Command, u32)>,
+    quit: bool,
+    saved_mode: Option<String>,
+    frames: u32,
+    fps: u32,
+    second_started: Instant
+}
 
 impl TermGfx {
-    pub fn new() -> TermGfx {
-        TermGfx { }
+    pub fn new(mode: TermMode, palette: Palette, keymap: Keymap, hotkeys: Hotkeys) -> TermGfx {
+        let saved_mode = stty(&["-g"]).map(|mode| mode.trim().to_string());
+        stty(&["raw", "-echo"]);
+
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let stdin = io::stdin();
+
+            for byte in stdin.lock().bytes() {
+                match byte {
+                    Ok(byte) => {
+                        if sender.send(byte).is_err() {
+                            break;
+                        }
+                    },
+                    Err(_) => break
+                }
+            }
+        });
+
+        // The screen is cleared on the first frame; after that only changed
+        // cells are redrawn.
+        print!("\x1b[?25l");
+
+        TermGfx {
+            mode: mode,
+            palette: palette,
+            keymap: keymap,
+            hotkeys: hotkeys,
+            cells: Vec::new(),
+            rows: 0,
+            status: String::new(),
+            machine_status: String::new(),
+            overlay_status: String::new(),
+            input: receiver,
+            partial_escape: Vec::new(),
+            escape_waits: 0,
+            held: [0; 16],
+            commands: Vec::new(),
+            held_commands: Vec::new(),
+            quit: false,
+            saved_mode: saved_mode,
+            frames: 0,
+            fps: 0,
+            second_started: Instant::now()
+        }
     }
 
-    pub fn composite(&self, buffer: Vec<u8>) {
-        for row in buffer.chunks(64) {
-            println!("{}", "");
-            for byte in row.iter() {
-                match *byte {
-                    0x0 => { print!("{}", " ") },
-                    _   => { print!("{}", "X") }
+    // Appends escape sequences redrawing the cells that changed.
+    fn draw_cells(&mut self, frame: &Framebuffer, columns: usize, out: &mut String) {
+        let mut cursor = None;
+
+        for row in 0..self.rows {
+            for column in 0..columns {
+                let cell = match self.mode {
+                    TermMode::HalfBlock => half_block(frame, column, row * 2),
+                    TermMode::Braille   => braille(frame, column * 2, row * 4)
+                };
+
+                let index = row * columns + column;
+
+                if self.cells[index] != cell {
+                    self.cells[index] = cell;
+
+                    // Runs of changed cells only need the cursor moved once.
+                    if cursor != Some(index) {
+                        out.push_str(&format!("\x1b[{};{}H", row + 1, column + 1));
+                    }
+
+                    out.push(cell);
+                    cursor = if column + 1 < columns { Some(index + 1) } else { None };
                 }
             }
         }
-        print!("{}[2J", 27 as char);
+    }
+
+    fn count_frame(&mut self) {
+        self.frames += 1;
+
+        if self.second_started.elapsed().as_secs() >= 1 {
+            self.fps            = self.frames;
+            self.frames         = 0;
+            self.second_started = Instant::now();
+        }
+    }
+}
+
+impl Frontend for TermGfx {
+    fn present(&mut self, frame: &Framebuffer) {
+        let (cell_width, cell_height) = self.mode.cell_size();
+
+        let columns = (frame.width() + cell_width - 1) / cell_width;
+        let rows    = (frame.height() + cell_height - 1) / cell_height;
+
+        let mut out = String::new();
+
+        let resized = self.cells.len() != columns * rows;
+
+        if resized {
+            self.cells = vec!['\0'; columns * rows];
+            self.rows  = rows;
+            self.status.clear();
+
+            // Cells only ever hold the two colors, so set them once and
+            // clear to the background.
+            out.push_str(&truecolor(38, self.palette.foreground()));
+            out.push_str(&truecolor(48, self.palette.background()));
+            out.push_str("\x1b[2J");
+        }
+
+        if resized || frame.is_dirty() {
+            self.draw_cells(frame, columns, &mut out);
+        }
+
+        self.count_frame();
+
+        let mut status = format!("FPS: {:<3} {}  ", self.fps, self.machine_status);
+
+        if !self.overlay_status.is_empty() {
+            status.push_str(&self.overlay_status);
+            status.push_str("  ");
+        }
+
+        status.push_str("Ctrl-C to quit");
+
+        if status != self.status {
+            out.push_str(&format!("\x1b[{};1H\x1b[K{}", self.rows + 1, status));
+            self.status = status;
+        }
+
+        if !out.is_empty() {
+            let stdout = io::stdout();
+            let mut handle = stdout.lock();
+
+            let _ = handle.write_all(out.as_bytes());
+            let _ = handle.flush();
+        }
+    }
+
+    fn poll_input(&mut self, keys: &mut [bool; 16]) {
+        for held in self.held.iter_mut() {
+            if *held > 0 {
+                *held -= 1;
+            }
+        }
+
+        for held in self.held_commands.iter_mut() {
+            held.1 -= 1;
+        }
+
+        self.held_commands.retain(|&(_, frames)| frames > 0);
+
+        let mut bytes = mem::replace(&mut self.partial_escape, Vec::new());
+
+        while let Ok(byte) = self.input.try_recv() {
+            bytes.push(byte);
+        }
+
+        let mut index = 0;
+
+        while index < bytes.len() {
+            let byte = bytes[index];
+            index += 1;
+
+            if byte == CTRL_C {
+                self.quit = true;
+                continue;
+            }
+
+            let name = match byte {
+                ESCAPE => {
+                    let waited_out = self.escape_waits >= ESCAPE_WAIT_POLLS;
+
+                    let (name, length) = match escape_sequence(&bytes[index..], waited_out) {
+                        Some(sequence) => sequence,
+                        None => {
+                            // The rest may well come with the next read.
+                            self.partial_escape = bytes[index - 1..].to_vec();
+                            self.escape_waits += 1;
+                            break;
+                        }
+                    };
+
+                    self.escape_waits = 0;
+                    index += length;
+
+                    match name {
+                        Some(name) => name.to_string(),
+                        None       => continue
+                    }
+                },
+                _ => key_name(byte)
+            };
+
+            // Key presses count as held like keypad keys do, which is what
+            // fast forward needs.
+            if let Some(command) = self.hotkeys.command_for(&name) {
+                self.commands.push(command);
+                self.held_commands.retain(|&(held, _)| held != command);
+                self.held_commands.push((command, KEY_HOLD_FRAMES));
+            } else if let Some(key) = self.keymap.key_for(byte as char) {
+                self.held[key] = KEY_HOLD_FRAMES;
+            }
+        }
+
+        for (key, held) in self.held.iter().enumerate() {
+            keys[key] = *held > 0;
+        }
+    }
+
+    // Terminals have no tone to hold, so ring the bell once instead.
+    fn play_tone(&mut self) {
+        print!("\x07");
+        let _ = io::stdout().flush();
+    }
+
+    fn stop_tone(&mut self) { }
+
+    fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    fn commands(&mut self) -> Vec<Command> {
+        mem::replace(&mut self.commands, Vec::new())
+    }
+
+    fn is_held(&self, command: Command) -> bool {
+        self.held_commands.iter().any(|&(held, _)| held == command)
+    }
+
+    // There's no room to draw over the display, so the overlay goes on the
+    // status line.
+    fn show_overlay(&mut self, overlay: &Overlay) {
+        self.overlay_status = overlay.summary();
+    }
+
+    fn shows_messages(&self) -> bool {
+        true
+    }
+
+    fn show_status(&mut self, status: &str) {
+        self.machine_status = status.to_string();
+    }
+}
+
+impl Drop for TermGfx {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[{};1H\x1b[?25h\r\n", self.rows + 2);
+        let _ = io::stdout().flush();
+
+        if let Some(ref mode) = self.saved_mode {
+            stty(&[mode.as_str()]);
+        }
+    }
+}
+
+// Names a key the way SDL does, so hotkeys mean the same in both frontends.
+fn key_name(byte: u8) -> String {
+    match byte {
+        b'\r' | b'\n' => String::from("Return"),
+        TAB           => String::from("Tab"),
+        BACKSPACE     => String::from("Backspace"),
+        b' '          => String::from("Space"),
+        _             => (byte as char).to_ascii_uppercase().to_string()
+    }
+}
+
+// Decodes the keys that arrive as escape sequences, given the bytes after the
+// escape: arrows send ESC [ A or ESC O A, function keys ESC O P or ESC [ 1 5 ~
+// and so on. An escape followed by anything else is the Escape key itself.
+// Returns the key's name, if it's one we know, and how many bytes the
+// sequence used, or None if the sequence may not have all arrived yet. Once
+// `waited_out`, it's taken that no more is coming: a lone escape is the
+// Escape key, and the start of a sequence is dropped.
+fn escape_sequence(bytes: &[u8], waited_out: bool) -> Option<(Option<&'static str>, usize)> {
+    match bytes.first() {
+        Some(&b'[') | Some(&b'O') => { },
+        Some(_)                   => return Some((Some("Escape"), 0)),
+        None if waited_out        => return Some((Some("Escape"), 0)),
+        None                      => return None
+    }
+
+    // Parameters run until a letter or '~' ends the sequence.
+    let end = match bytes[1..].iter().position(|&b| b.is_ascii_alphabetic() || b == b'~') {
+        Some(end) => end + 1,
+        None if waited_out || bytes.len() > MAX_ESCAPE_SEQUENCE => return Some((None, bytes.len())),
+        None => return None
+    };
+
+    let name = match &bytes[1..end + 1] {
+        b"A"   => Some("Up"),
+        b"B"   => Some("Down"),
+        b"C"   => Some("Right"),
+        b"D"   => Some("Left"),
+        b"P"   => Some("F1"),
+        b"Q"   => Some("F2"),
+        b"R"   => Some("F3"),
+        b"S"   => Some("F4"),
+        b"5~"  => Some("PageUp"),
+        b"6~"  => Some("PageDown"),
+        b"15~" => Some("F5"),
+        b"17~" => Some("F6"),
+        b"18~" => Some("F7"),
+        b"19~" => Some("F8"),
+        b"20~" => Some("F9"),
+        b"21~" => Some("F10"),
+        b"23~" => Some("F11"),
+        b"24~" => Some("F12"),
+        _      => None
+    };
+
+    Some((name, end + 1))
+}
+
+// An ANSI 24-bit color escape: 38 sets the foreground, 48 the background.
+fn truecolor(layer: u8, color: Color) -> String {
+    format!("\x1b[{};2;{};{};{}m", layer, color.r, color.g, color.b)
+}
+
+// Cells are either on or off, so pixels a display filter left partly lit
+// count as lit while at least half bright.
+fn lit(frame: &Framebuffer, x: usize, y: usize) -> bool {
+    x < frame.width() && y < frame.height() && frame.pixel(x, y) >= 0x80
+}
+
+fn half_block(frame: &Framebuffer, x: usize, y: usize) -> char {
+    let top    = lit(frame, x, y);
+    let bottom = lit(frame, x, y + 1);
+
+    match (top, bottom) {
+        (true, true)   => '\u{2588}',
+        (true, false)  => '\u{2580}',
+        (false, true)  => '\u{2584}',
+        (false, false) => ' '
+    }
+}
+
+// Braille patterns start at U+2800, with one bit per dot. Dots 1-3 and 4-6
+// run down the left and right columns, and dots 7 and 8 sit beneath them.
+const BRAILLE_DOTS: [[u32; 4]; 2] = [
+    [0x01, 0x02, 0x04, 0x40],
+    [0x08, 0x10, 0x20, 0x80]
+];
+
+fn braille(frame: &Framebuffer, x: usize, y: usize) -> char {
+    let mut bits = 0;
+
+    for dx in 0..2 {
+        for dy in 0..4 {
+            if lit(frame, x + dx, y + dy) {
+                bits |= BRAILLE_DOTS[dx][dy];
+            }
+        }
+    }
+
+    if bits == 0 {
+        return ' ';
+    }
+
+    char::from_u32(0x2800 + bits).unwrap_or(' ')
+}
+
+// stty acts on the terminal attached to its stdin, so hand it ours.
+fn stty(args: &[&str]) -> Option<String> {
+    let output = process::Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output();
+
+    match output {
+        Ok(ref output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        },
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_whole_sequences() {
+        assert_eq!(escape_sequence(b"[A", false), Some((Some("Up"), 2)));
+        assert_eq!(escape_sequence(b"OP", false), Some((Some("F1"), 2)));
+        assert_eq!(escape_sequence(b"[24~x", false), Some((Some("F12"), 4)));
+        assert_eq!(escape_sequence(b"[99~", false), Some((None, 4)));
+    }
+
+    #[test]
+    fn takes_an_escape_before_anything_else_as_the_key() {
+        assert_eq!(escape_sequence(b"q", false), Some((Some("Escape"), 0)));
+        assert_eq!(escape_sequence(b"\x1b[A", false), Some((Some("Escape"), 0)));
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_sequence() {
+        assert_eq!(escape_sequence(b"", false), None);
+        assert_eq!(escape_sequence(b"[", false), None);
+        assert_eq!(escape_sequence(b"[2", false), None);
+    }
+
+    #[test]
+    fn gives_up_waiting_in_the_end() {
+        assert_eq!(escape_sequence(b"", true), Some((Some("Escape"), 0)));
+        assert_eq!(escape_sequence(b"[2", true), Some((None, 2)));
+        assert_eq!(escape_sequence(b"[123456789", false), Some((None, 10)));
     }
 }
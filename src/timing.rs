@@ -0,0 +1,266 @@
+use std::str::FromStr;
+
+use cpu::Cpu;
+use error::Error;
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+
+// The VIP's 1.7609 MHz clock runs 8 clocks per machine cycle, giving about
+// 3668 machine cycles per 60 Hz frame.
+pub const VIP_CYCLES_PER_FRAME: i64 = 3668;
+
+// Each frame the 1861 display chip steals 1024 cycles of DMA, and the
+// interrupt routine that services it and the timers takes roughly 50 more.
+pub const VIP_INTERRUPT_CYCLES: i64 = 1024 + 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    // A fixed number of instructions per second, whatever they are.
+    Flat(u32),
+    // Every instruction costs its COSMAC VIP machine cycles.
+    CosmacVip
+}
+
+// The fastest flat rate, in instructions per second. Far beyond anything a
+// game needs, but low enough that a frame still finishes in good time.
+pub const MAX_RATE: u32 = 1000000;
+
+// The rates the speed hotkeys step through, in instructions per frame. None
+// may go past MAX_RATE.
+const SPEED_STEPS: [u32; 22] = [
+    1, 2, 3, 4, 5, 6, 8, 10, 12, 15, 20, 25, 30, 40, 50, 70, 100, 150, 200, 300, 500, 1000
+];
+
+impl Timing {
+    // Instructions per 60 Hz frame, for the timings that have a fixed rate.
+    pub fn per_frame(&self) -> Option<u32> {
+        match *self {
+            Timing::Flat(ips) => Some(ips / FRAMES_PER_SECOND),
+            Timing::CosmacVip => None
+        }
+    }
+
+    // The next rate up or down from this one, or None at either end. VIP
+    // timing is as fast as the VIP was, so it has no steps.
+    pub fn step(&self, faster: bool) -> Option<Timing> {
+        let per_frame = match self.per_frame() {
+            Some(per_frame) => per_frame,
+            None            => return None
+        };
+
+        let next = if faster {
+            SPEED_STEPS.iter().find(|&&step| step > per_frame)
+        } else {
+            SPEED_STEPS.iter().rev().find(|&&step| step < per_frame)
+        };
+
+        next.map(|&step| Timing::Flat((step * FRAMES_PER_SECOND).min(MAX_RATE)))
+    }
+}
+
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Timing, String> {
+        match s {
+            "vip" => Ok(Timing::CosmacVip),
+            _     => {
+                match s.parse() {
+                    Ok(ips) if ips > 0 && ips <= MAX_RATE => Ok(Timing::Flat(ips)),
+                    Ok(_)  => Err(format!("Instruction rate out of range: {} (expected 1 to {})", s, MAX_RATE)),
+                    Err(_) => Err(format!("Unknown timing: {} (expected an instruction rate or vip)", s))
+                }
+            }
+        }
+    }
+}
+
+// Decides how much of the program runs in each 60 Hz frame.
+pub struct Scheduler {
+    timing: Timing,
+    // Instructions per second that didn't divide evenly into a frame.
+    remainder: u32,
+    // Cycles carried over from the last frame; negative if it overran.
+    budget: i64
+}
+
+impl Scheduler {
+    pub fn new(timing: Timing) -> Scheduler {
+        Scheduler {
+            timing: timing,
+            remainder: 0,
+            budget: 0
+        }
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing    = timing;
+        self.remainder = 0;
+        self.budget    = 0;
+    }
+
+    // Runs one frame's worth of instructions, then the 60 Hz tick.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Result<(), Error> {
+        try!(self.run_frame_until(cpu, |_| false));
+
+        Ok(())
+    }
+
+    // Like `run_frame`, but checks `stop` before every instruction and gives
+    // up on the frame, without the tick, as soon as it returns true. Returns
+    // whether it stopped early.
+    pub fn run_frame_until<F: FnMut(&Cpu) -> bool>(&mut self, cpu: &mut Cpu, mut stop: F) -> Result<bool, Error> {
+        match self.timing {
+            Timing::Flat(ips) => {
+                self.remainder += ips;
+
+                let count = self.remainder / FRAMES_PER_SECOND;
+                self.remainder %= FRAMES_PER_SECOND;
+
+                for _ in 0..count {
+                    if cpu.is_waiting_for_vblank() {
+                        break;
+                    }
+
+                    if stop(cpu) {
+                        return Ok(true);
+                    }
+
+                    try!(cpu.step());
+                }
+            },
+            Timing::CosmacVip => {
+                self.budget += VIP_CYCLES_PER_FRAME - VIP_INTERRUPT_CYCLES;
+
+                while self.budget > 0 {
+                    // Whatever is left of the frame is spent waiting.
+                    if cpu.is_waiting_for_vblank() {
+                        self.budget = 0;
+                        break;
+                    }
+
+                    // The rest of the frame is dropped, as with flat timing.
+                    if stop(cpu) {
+                        self.budget = 0;
+                        return Ok(true);
+                    }
+
+                    let before = cpu.cycles();
+                    try!(cpu.step());
+                    self.budget -= (cpu.cycles() - before) as i64;
+                }
+            }
+        }
+
+        cpu.tick();
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::Cpu;
+    use mmu::Mmu;
+
+    fn cpu_with(rom: &[u8]) -> Cpu {
+        let mut mmu = Mmu::new();
+        mmu.load_rom(rom).unwrap();
+
+        Cpu::new(mmu)
+    }
+
+    // 0x200: JP 0x200, one instruction a time forever.
+    const SPIN: [u8; 2] = [0x12, 0x00];
+
+    #[test]
+    fn parses_rates_and_vip() {
+        assert_eq!("vip".parse::<Timing>(), Ok(Timing::CosmacVip));
+        assert_eq!("840".parse::<Timing>(), Ok(Timing::Flat(840)));
+        assert_eq!(MAX_RATE.to_string().parse::<Timing>(), Ok(Timing::Flat(MAX_RATE)));
+    }
+
+    #[test]
+    fn rejects_rates_out_of_range() {
+        assert!("0".parse::<Timing>().is_err());
+        assert!((MAX_RATE + 1).to_string().parse::<Timing>().is_err());
+        assert!("4294967295".parse::<Timing>().is_err());
+        assert!("-1".parse::<Timing>().is_err());
+        assert!("fast".parse::<Timing>().is_err());
+    }
+
+    #[test]
+    fn steps_between_speeds() {
+        assert_eq!(Timing::Flat(840).step(true), Some(Timing::Flat(900)));
+        assert_eq!(Timing::Flat(840).step(false), Some(Timing::Flat(720)));
+        assert_eq!(Timing::Flat(60).step(false), None);
+        assert_eq!(Timing::Flat(60000).step(true), None);
+        assert_eq!(Timing::Flat(MAX_RATE).step(false), Some(Timing::Flat(60000)));
+        assert_eq!(Timing::CosmacVip.step(true), None);
+    }
+
+    #[test]
+    fn carries_the_remainder_of_a_rate_between_frames() {
+        let mut cpu = cpu_with(&SPIN);
+        let mut scheduler = Scheduler::new(Timing::Flat(90));
+
+        scheduler.run_frame(&mut cpu).unwrap();
+        assert_eq!(cpu.instructions(), 1);
+
+        scheduler.run_frame(&mut cpu).unwrap();
+        assert_eq!(cpu.instructions(), 3);
+
+        for _ in 0..58 {
+            scheduler.run_frame(&mut cpu).unwrap();
+        }
+
+        assert_eq!(cpu.instructions(), 90);
+    }
+
+    #[test]
+    fn charges_vip_cycles_per_instruction() {
+        // LD V0, 5; CLS; DRW V0, V0, 1; LD V1, 123; LD [I], V1; JP 0x200
+        let mut cpu = cpu_with(&[
+            0x60, 0x05, 0x00, 0xE0, 0xD0, 0x01, 0x61, 0x7B, 0xF1, 0x55, 0x12, 0x00
+        ]);
+
+        // LD is the cheapest routine; CLS clears 256 bytes; a sprite row 5
+        // bits off a byte boundary is shifted 5 times; Fx55 stores 2 bytes.
+        let expected = [40 + 6, 40 + 24 + 256 * 6, 40 + 52 + (52 + 5 * 8), 40 + 6, 40 + 14 + 2 * 14, 40 + 12];
+
+        for &cycles in expected.iter() {
+            let before = cpu.cycles();
+            cpu.step().unwrap();
+            assert_eq!(cpu.cycles() - before, cycles);
+        }
+    }
+
+    #[test]
+    fn spends_the_cycles_left_after_the_interrupt_each_vip_frame() {
+        let mut cpu = cpu_with(&SPIN);
+        let mut scheduler = Scheduler::new(Timing::CosmacVip);
+
+        // Each JP costs 52 cycles, so a frame's 3668 - 1074 = 2594 cycles
+        // run 50 of them, overrunning by 6.
+        scheduler.run_frame(&mut cpu).unwrap();
+        assert_eq!(VIP_CYCLES_PER_FRAME - VIP_INTERRUPT_CYCLES, 2594);
+        assert_eq!(cpu.instructions(), 50);
+        assert_eq!(cpu.cycles(), 50 * 52);
+
+        // The overrun comes out of the next frame, until it adds up to a
+        // whole instruction fewer.
+        for _ in 0..7 {
+            scheduler.run_frame(&mut cpu).unwrap();
+        }
+
+        assert_eq!(cpu.instructions(), 8 * 50);
+
+        scheduler.run_frame(&mut cpu).unwrap();
+        assert_eq!(cpu.instructions(), 8 * 50 + 49);
+    }
+}